@@ -0,0 +1,54 @@
+/*
+ * The C interface of rzstd, built with `cargo build --release --features ffi`
+ * into target/release/librzstd.so (or .dylib, .dll).
+ */
+#ifndef RZSTD_H
+#define RZSTD_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * A matched line given to the callback of rzstd_search.
+ * The pointers are only valid during the call of the callback.
+ */
+typedef struct RzstdMatch {
+    /* The searched file, NUL terminated. */
+    const char *file;
+    /* The 1-based number of the line. */
+    uint64_t line_number;
+    /* The offset of the line in the decompressed data. */
+    uint64_t byte_offset;
+    /* The line, without its terminator. It isn't NUL terminated,
+     * and may contain NUL bytes. */
+    const char *line;
+    size_t line_len;
+} RzstdMatch;
+
+/* Called with each matched line. Returning anything else than 0 stops the search. */
+typedef int (*RzstdCallback)(const RzstdMatch *found, void *user_data);
+
+/*
+ * Searches a file, compressed in any of the supported formats or not,
+ * for the regex pattern, and calls the callback with each matched line.
+ * Returns the number of lines given to the callback, or -1 if the
+ * search failed, see rzstd_last_error.
+ */
+int64_t rzstd_search(const char *path, const char *pattern, RzstdCallback callback, void *user_data);
+
+/*
+ * Returns the error of the last call of rzstd_search that failed
+ * on the calling thread, or NULL if none did. The string is valid
+ * until the next call that fails on the thread.
+ */
+const char *rzstd_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RZSTD_H */
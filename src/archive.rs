@@ -0,0 +1,79 @@
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+
+use crate::format::Format;
+use crate::search;
+
+/// The extensions of the archives that aren't compressed,
+/// which are searched in directories by default like the
+/// compressed files.
+pub const EXTENSIONS: &[&str] = &["tar", "zip", "7z"];
+
+/// The size of the blocks of a tar archive, which is also
+/// the size of the header of each member.
+pub const TAR_BLOCK_LEN: u64 = 512;
+
+/// Where the magic of the POSIX and GNU tar headers is.
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+/// Returns true if the decompressed data is a tar archive,
+/// from the header of its first member.
+pub fn is_tar(prefix: &[u8]) -> bool {
+    prefix.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC)
+}
+
+/// The magic bytes of zip archives, which start their first member.
+pub const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// The magic bytes of 7z archives.
+pub const SEVEN_Z_MAGIC: &[u8] = b"7z\xbc\xaf\x27\x1c";
+
+/// An archive that is read from its index, so that it can
+/// only be searched in a file and not streamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileArchive {
+    Zip,
+    SevenZ,
+}
+
+/// Returns the archive the file is, if it is a zip or 7z archive.
+/// The file is rewound to its start afterwards.
+pub fn file_archive(file: &mut File) -> io::Result<Option<FileArchive>> {
+    let mut magic = Vec::new();
+    file.by_ref().take(SEVEN_Z_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    file.rewind()?;
+    Ok(from_magic(&magic))
+}
+
+/// Returns the archive whose magic bytes start the data, if any.
+pub fn from_magic(prefix: &[u8]) -> Option<FileArchive> {
+    if prefix.starts_with(ZIP_MAGIC) {
+        Some(FileArchive::Zip)
+    } else if prefix.starts_with(SEVEN_Z_MAGIC) {
+        Some(FileArchive::SevenZ)
+    } else {
+        None
+    }
+}
+
+/// Returns the path of a member inside its archive that globs are
+/// matched against, without the `./` that starts the members
+/// of the archives made with `tar -cf archive.tar .`.
+pub fn member_name(name: &str) -> &Path {
+    Path::new(name.trim_start_matches("./"))
+}
+
+/// Returns true if the name of a member is the one of an archive
+/// or a compressed file, which may contain files to search.
+pub fn is_archive_name(name: &str) -> bool {
+    let extension = Path::new(name).extension().and_then(|extension| extension.to_str());
+    Format::from_path(name).is_some() || extension.is_some_and(|extension| EXTENSIONS.contains(&extension))
+}
+
+/// Returns the path shown for a member of an archive,
+/// `archive.tar.zst:member/path`.
+pub fn member_path(archive_path: &str, member: &str) -> String {
+    format!("{}:{}", search::display_path(archive_path), member)
+}
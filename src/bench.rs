@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::config::{SearchBuilder, SearchConfig};
+use crate::format::{self, Format};
+use crate::matches;
+use crate::search::SearchOptions;
+
+/// What a run of `rzstd bench` measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Decoding the files, without searching them.
+    Decompress,
+    /// Searching the decoded data of the files, already in memory.
+    Search,
+    /// Decoding the files and searching them, like a search does.
+    Combined,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 3] = [Stage::Decompress, Stage::Search, Stage::Combined];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Decompress => "decompress",
+            Stage::Search => "search",
+            Stage::Combined => "combined",
+        }
+    }
+}
+
+/// The files measured by `rzstd bench`, with their decoded data,
+/// which the search-only runs search in memory.
+pub struct Bench {
+    files: Vec<String>,
+    decoded: Vec<Vec<u8>>,
+    /// Searches the files.
+    config: SearchConfig,
+    /// Searches their decoded data.
+    decoded_config: SearchConfig,
+}
+
+impl Bench {
+    /// Decodes the files once, which checks them before they are
+    /// measured and keeps their decoded data.
+    pub fn new(files: Vec<String>, patterns: &[String], options: SearchOptions) -> Result<Bench> {
+        let decoded_config = SearchBuilder::from_options(options.clone())
+            .patterns(patterns.iter().cloned())
+            .format(Format::Uncompressed)
+            .build()?;
+        let config = SearchBuilder::from_options(options).patterns(patterns.iter().cloned()).build()?;
+
+        let mut decoded = Vec::new();
+        for file_path in &files {
+            let mut data = Vec::new();
+            let result = decoder(file_path, &config, DEFAULT_READ_LEN).and_then(|mut decoder| decoder.read_to_end(&mut data));
+            if let Err(e) = result {
+                return Err(anyhow::anyhow!("Error decoding file {}: {}", file_path, e));
+            }
+            decoded.push(data);
+        }
+
+        Ok(Bench {
+            files,
+            decoded,
+            config,
+            decoded_config,
+        })
+    }
+
+    /// Returns the size of the decoded data of all the files.
+    pub fn decoded_len(&self) -> u64 {
+        self.decoded.iter().map(|data| data.len() as u64).sum()
+    }
+
+    /// Runs a stage over all the files, with up to threads files at
+    /// once like -j, and returns how long it took. The files are read
+    /// buffer_len bytes at a time, and so is their decoded data when
+    /// it isn't searched. The search-only runs don't read the files.
+    pub fn run(&self, stage: Stage, threads: usize, buffer_len: usize) -> Result<Duration> {
+        let next = AtomicUsize::new(0);
+        let failed = Mutex::new(None);
+        let started = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= self.files.len() {
+                        return;
+                    }
+                    if let Err(e) = self.run_file(stage, index, buffer_len) {
+                        let file_path = &self.files[index];
+                        let e = anyhow::anyhow!("Error running {} on file {}: {}", stage.name(), file_path, e);
+                        failed.lock().unwrap().get_or_insert(e);
+                        return;
+                    }
+                });
+            }
+        });
+        let elapsed = started.elapsed();
+
+        match failed.into_inner().unwrap() {
+            Some(e) => Err(e),
+            None => Ok(elapsed),
+        }
+    }
+
+    /// Runs a stage over a single file.
+    fn run_file(&self, stage: Stage, index: usize, buffer_len: usize) -> io::Result<()> {
+        let file_path = &self.files[index];
+        match stage {
+            Stage::Decompress => {
+                let mut decoder = decoder(file_path, &self.config, buffer_len)?;
+                let mut buffer = vec![0; buffer_len];
+                while decoder.read(&mut buffer)? > 0 {}
+                Ok(())
+            }
+            Stage::Search => search(&self.decoded_config, &self.decoded[index][..], None),
+            Stage::Combined => {
+                let file = BufReader::with_capacity(buffer_len, File::open(file_path)?);
+                search(&self.config, file, Some(file_path))
+            }
+        }
+    }
+}
+
+/// The size of the reads when the files are decoded
+/// before they are measured.
+const DEFAULT_READ_LEN: usize = 64 << 10;
+
+/// Returns the decoder of a file, which reads buffer_len bytes of
+/// the file at a time. The format is told like for the searches.
+fn decoder(file_path: &str, config: &SearchConfig, buffer_len: usize) -> io::Result<Box<dyn Read>> {
+    let options = config.options();
+    let file = BufReader::with_capacity(buffer_len, File::open(file_path)?);
+    let (detected, input) = format::detect(file)?;
+    let format = Format::resolve(options.format.or(detected), file_path);
+    format.decoder(input, options.zstd())
+}
+
+/// Searches the input until its end, for the lines to be found.
+fn search<'a, R: Read + 'a>(config: &SearchConfig, input: R, file_path: Option<&str>) -> io::Result<()> {
+    let mut matches = match matches::search_input(config, input, file_path) {
+        Ok(matches) => matches,
+        Err(e) => return Err(io::Error::other(e.to_string())),
+    };
+    for _ in matches.by_ref() {}
+    match matches.error() {
+        Some(e) => Err(io::Error::new(e.kind(), e.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Returns a size with the largest of the K, M or G units that
+/// divides it, like the sizes given on the command line.
+pub fn format_size(size: u64) -> String {
+    for (unit, shift) in [("G", 30), ("M", 20), ("K", 10)] {
+        if size >= 1 << shift && size.is_multiple_of(1 << shift) {
+            return format!("{}{}", size >> shift, unit);
+        }
+    }
+    size.to_string()
+}
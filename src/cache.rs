@@ -0,0 +1,253 @@
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use crate::format::Format;
+
+/// The size of the blocks of decoded data kept in the cache.
+const BLOCK_LEN: usize = 4 << 20;
+/// The extension of the files of the blocks.
+const BLOCK_EXTENSION: &str = "block";
+
+/// Numbers the blocks written at once, so that each one gets a
+/// temporary file of its own.
+static WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+/// A cache of the decoded data of the files, in a directory, so that
+/// searching the same files again with other patterns doesn't decode
+/// them once more.
+///
+/// The data of a file is kept in blocks of 4M, each in a file named
+/// after the key of the file and the offset of the block. Once the
+/// blocks take more than the size of the cache, the least recently
+/// used ones are removed. A file whose first blocks are cached is
+/// only decoded again from the first missing block, which still
+/// needs decoding the data before it.
+#[derive(Debug, Clone)]
+pub struct BlockCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl BlockCache {
+    pub fn new(dir: PathBuf, max_size: u64) -> BlockCache {
+        BlockCache { dir, max_size }
+    }
+
+    /// Returns the key of a file, made from its path, size and
+    /// modification time, and from what its data is decoded with, so
+    /// that a changed file isn't read from the cache. Returns None
+    /// for the files whose metadata can't be read.
+    pub fn file_key(file_path: &str, format: Format, dictionary: Option<&[u8]>) -> Option<u64> {
+        let path = fs::canonicalize(file_path).ok()?;
+        let metadata = fs::metadata(&path).ok()?;
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        metadata.modified().ok()?.hash(&mut hasher);
+        format.hash(&mut hasher);
+        dictionary.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    /// Returns the decoded data of the file with the key, read from
+    /// the cache as long as its blocks are there, and from the decoder
+    /// made when one is missing. The decoded blocks are added to the
+    /// cache.
+    pub fn reader<'a, F>(&self, key: u64, decoder: F) -> CachedReader<'a>
+    where
+        F: FnOnce() -> io::Result<Box<dyn Read + 'a>> + 'a,
+    {
+        CachedReader {
+            cache: self.clone(),
+            key,
+            offset: 0,
+            state: State::Start,
+            decoder: Some(Box::new(decoder)),
+            written: false,
+        }
+    }
+
+    fn block_path(&self, key: u64, offset: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}-{:016x}.{}", key, offset, BLOCK_EXTENSION))
+    }
+
+    /// Opens a cached block and marks it as used.
+    fn open_block(&self, key: u64, offset: u64) -> Option<File> {
+        let path = self.block_path(key, offset);
+        let block = File::open(&path).ok()?;
+        let _ = File::options().write(true).open(&path).and_then(|file| file.set_modified(SystemTime::now()));
+        Some(block)
+    }
+
+    /// Writes a block to a temporary file first, so that the searches
+    /// reading the cache at the same time never see half of it.
+    fn write_block(&self, key: u64, offset: u64, data: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.block_path(key, offset);
+        let written = WRITTEN.fetch_add(1, Ordering::Relaxed);
+        let temporary = path.with_extension(format!("{}-{}", std::process::id(), written));
+        let result = File::create(&temporary).and_then(|mut file| file.write_all(data));
+        match result {
+            Ok(()) => fs::rename(&temporary, &path),
+            Err(e) => {
+                let _ = fs::remove_file(&temporary);
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes the least recently used blocks until the blocks
+    /// take no more than the size of the cache.
+    fn evict(&self) -> io::Result<()> {
+        let mut blocks = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != BLOCK_EXTENSION) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            blocks.push((metadata.modified()?, metadata.len(), path));
+        }
+
+        let mut size: u64 = blocks.iter().map(|(_, len, _)| len).sum();
+        blocks.sort();
+        for (_, len, path) in blocks {
+            if size <= self.max_size {
+                break;
+            }
+            remove_block(&path)?;
+            size -= len;
+        }
+        Ok(())
+    }
+}
+
+/// Removes a block, which another search may have removed already.
+fn remove_block(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Makes the decoder of a file, once a block isn't in the cache.
+type MakeDecoder<'a> = Box<dyn FnOnce() -> io::Result<Box<dyn Read + 'a>> + 'a>;
+
+/// Where a [`CachedReader`] reads the data from.
+enum State<'a> {
+    /// Nothing is read yet.
+    Start,
+    /// A cached block, with the number of bytes read from it.
+    Cached(File, usize),
+    /// The decoder, with the data of the block being decoded
+    /// and its offset.
+    Decoding(Box<dyn Read + 'a>, Vec<u8>, u64),
+    /// All the data is read.
+    Done,
+}
+
+/// Reads the decoded data of a file from the cache, or from its decoder.
+/// See [`BlockCache`].
+pub struct CachedReader<'a> {
+    cache: BlockCache,
+    key: u64,
+    /// The offset of the next byte to read in the decoded data.
+    offset: u64,
+    state: State<'a>,
+    decoder: Option<MakeDecoder<'a>>,
+    /// True once a block is written to the cache.
+    written: bool,
+}
+
+impl CachedReader<'_> {
+    /// Reads from the block at the offset if it is cached, or else
+    /// from the decoder, as the block isn't the start of a cached one.
+    fn open(&mut self) -> io::Result<()> {
+        if let Some(block) = self.cache.open_block(self.key, self.offset) {
+            self.state = State::Cached(block, 0);
+            return Ok(());
+        }
+        let make_decoder = match self.decoder.take() {
+            Some(make_decoder) => make_decoder,
+            None => return Err(io::Error::other("the decoder of the file is already used")),
+        };
+        let mut decoder = make_decoder()?;
+        // The data before the block is already read from the cache
+        let skipped = io::copy(&mut (&mut decoder).take(self.offset), &mut io::sink())?;
+        if skipped < self.offset {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the file is shorter than its cached data"));
+        }
+        self.state = State::Decoding(decoder, Vec::with_capacity(BLOCK_LEN), self.offset);
+        Ok(())
+    }
+
+    /// Adds a decoded block to the cache. A cache that can't be
+    /// written doesn't stop the search, the file is only decoded
+    /// again the next time.
+    fn write_block(&mut self, offset: u64, block: &[u8]) {
+        if self.cache.write_block(self.key, offset, block).is_ok() {
+            self.written = true;
+        }
+    }
+}
+
+impl Read for CachedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match &mut self.state {
+                State::Start => self.open()?,
+                State::Cached(block, read_from_block) => {
+                    let read = block.read(buf)?;
+                    if read > 0 || buf.is_empty() {
+                        *read_from_block += read;
+                        self.offset += read as u64;
+                        return Ok(read);
+                    }
+                    // The last block is the only one that isn't full,
+                    // it is empty when the data ends at a full block
+                    if *read_from_block < BLOCK_LEN {
+                        self.state = State::Done;
+                    } else {
+                        self.open()?;
+                    }
+                }
+                State::Decoding(decoder, block, block_offset) => {
+                    let read = decoder.read(buf)?;
+                    block.extend_from_slice(&buf[..read]);
+                    self.offset += read as u64;
+                    if read > 0 && block.len() < BLOCK_LEN {
+                        return Ok(read);
+                    }
+
+                    // The blocks are only cached once they are decoded
+                    // whole, or once the data ends
+                    let full: Vec<u8> = match read {
+                        0 => std::mem::take(block),
+                        _ => block.drain(..BLOCK_LEN).collect(),
+                    };
+                    let offset = *block_offset;
+                    *block_offset += BLOCK_LEN as u64;
+                    if read == 0 {
+                        self.state = State::Done;
+                    }
+                    self.write_block(offset, &full);
+                    return Ok(read);
+                }
+                State::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+impl Drop for CachedReader<'_> {
+    fn drop(&mut self) {
+        if self.written {
+            let _ = self.cache.evict();
+        }
+    }
+}
@@ -0,0 +1,1198 @@
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use encoding_rs::Encoding;
+
+use crate::cache::BlockCache;
+use crate::color::ColorConfig;
+use crate::compress::{self, CompressOptions};
+use crate::config::{SearchBuilder, SearchConfig};
+use crate::format::{self, Format};
+use crate::index;
+use crate::matcher::{CaseMode, MatcherOptions};
+use crate::progress::Progress;
+use crate::search::{self, BinaryMode, InvalidUtf8, SearchOptions};
+use crate::walk::{GlobFilter, WalkOptions};
+
+/// The file path that stands for stdin.
+pub const STDIN_PATH: &str = "-";
+
+/// When to color the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color the output when stdout is a terminal.
+    #[default]
+    Auto,
+    /// Always color the output.
+    Always,
+    /// Never color the output.
+    Never,
+}
+
+impl ColorChoice {
+    /// Returns true if the output is colored.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stdout().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Command line arguments of rzstd.
+///
+/// Every option that changes how files are decoded, searched
+/// or printed lives here so that main.rs only has to hand
+/// the parsed arguments over to the per-file tasks.
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "rzstd",
+    version,
+    about = "A utility that does a grep over compressed files in parallel.",
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Args {
+    /// The command to run instead of the search. A pattern named
+    /// like a command is searched for with -e.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The regex to search for.
+    /// When patterns are given with -e or -f, this is the first file instead.
+    #[arg(value_name = "PATTERN", required_unless_present_any = ["regexps", "pattern_files"])]
+    pub pattern: Option<String>,
+
+    /// The compressed files to search. The format of each file is
+    /// told by its first bytes, or else by its extension, like
+    /// `.br` or `.lzma`. Files that aren't compressed are searched
+    /// as they are, and tar, zip and 7z archives are searched member
+    /// by member.
+    /// A file of `-` reads from stdin. Stdin is also
+    /// read when no files are given, unless searching recursively.
+    #[arg(value_name = "FILES")]
+    pub files: Vec<String>,
+
+    /// A regex to search for. Can be given multiple times,
+    /// in which case lines matching any of the patterns are printed.
+    #[arg(short = 'e', long = "regexp", value_name = "PATTERN")]
+    pub regexps: Vec<String>,
+
+    /// Read patterns from a file, one per line. Can be given multiple times.
+    /// The patterns are combined with the ones given with -e. An empty
+    /// file gives no pattern, and no line matches, like with grep.
+    #[arg(short = 'f', long = "file", value_name = "PATTERNFILE")]
+    pub pattern_files: Vec<String>,
+
+    /// Search case insensitively.
+    #[arg(short = 'i', long = "ignore-case", overrides_with = "smart_case")]
+    pub ignore_case: bool,
+
+    /// Search case insensitively if the pattern is all lowercase,
+    /// and case sensitively otherwise. The last of -i and -S wins.
+    #[arg(short = 'S', long = "smart-case", overrides_with = "ignore_case")]
+    pub smart_case: bool,
+
+    /// Treat the patterns as literal strings instead of regexes.
+    #[arg(short = 'F', long = "fixed-strings")]
+    pub fixed_strings: bool,
+
+    /// Only match on word boundaries.
+    #[arg(short = 'w', long = "word-regexp", overrides_with = "line_regexp")]
+    pub word_regexp: bool,
+
+    /// Only match when the pattern matches the whole line.
+    /// The last of -w and -x wins.
+    #[arg(short = 'x', long = "line-regexp", overrides_with = "word_regexp")]
+    pub line_regexp: bool,
+
+    /// Match ASCII only instead of Unicode, which is faster on the
+    /// data known to be ASCII: `\w`, `\d`, `\s`, `\b` and the case
+    /// folding of -i and -S only know the ASCII characters, `.` matches
+    /// any byte, and `\xFF` matches the byte 0xFF rather than `ÿ`.
+    #[arg(long = "no-unicode")]
+    pub no_unicode: bool,
+
+    /// The most bytes the compiled regex can take, 100M by default.
+    /// The patterns that compile to a larger regex, like `\w{1000}` or
+    /// thousands of alternatives, fail to build. SIZE is a number of
+    /// bytes, which can end with K, M or G.
+    #[arg(long = "regex-size-limit", value_name = "SIZE", value_parser = parse_size)]
+    pub regex_size_limit: Option<u64>,
+
+    /// The most bytes the lazy DFA of the regex can take in each
+    /// search, 1000M by default. A smaller DFA doesn't fail, but the
+    /// searches of the large regexes then get slower.
+    #[arg(long = "dfa-size-limit", value_name = "SIZE", value_parser = parse_size)]
+    pub dfa_size_limit: Option<u64>,
+
+    /// Print the lines that don't match.
+    #[arg(short = 'v', long = "invert-match")]
+    pub invert_match: bool,
+
+    /// Only print the number of matched lines of each file.
+    #[arg(short = 'c', long = "count")]
+    pub count: bool,
+
+    /// Only print the number of matches of each file.
+    /// A line with several matches counts several times.
+    #[arg(long = "count-matches")]
+    pub count_matches: bool,
+
+    /// Only print the names of the files with at least one match.
+    #[arg(short = 'l', long = "files-with-matches")]
+    pub files_with_matches: bool,
+
+    /// Only print the names of the files without any match.
+    #[arg(short = 'L', long = "files-without-match", conflicts_with = "files_with_matches")]
+    pub files_without_match: bool,
+
+    /// Stop searching a file after NUM matched lines.
+    #[arg(short = 'm', long = "max-count", value_name = "NUM")]
+    pub max_count: Option<u64>,
+
+    /// Prefix each line with its line number.
+    #[arg(short = 'n', long = "line-number")]
+    pub line_number: bool,
+
+    /// Prefix each line with the column of its first match.
+    /// This implies --line-number.
+    #[arg(long = "column")]
+    pub column: bool,
+
+    /// Prefix each line with its byte offset in the decompressed data.
+    #[arg(short = 'b', long = "byte-offset")]
+    pub byte_offset: bool,
+
+    /// Only print the matched parts of each line, one per line.
+    #[arg(short = 'o', long = "only-matching")]
+    pub only_matching: bool,
+
+    /// Print NUM lines after each match.
+    #[arg(short = 'A', long = "after-context", value_name = "NUM")]
+    pub after_context: Option<usize>,
+
+    /// Print NUM lines before each match.
+    #[arg(short = 'B', long = "before-context", value_name = "NUM")]
+    pub before_context: Option<usize>,
+
+    /// Print NUM lines before and after each match.
+    /// -A and -B take precedence over this.
+    #[arg(short = 'C', long = "context", value_name = "NUM")]
+    pub context: Option<usize>,
+
+    /// Print nothing. Exit with 0 at the first match, and with 1
+    /// if nothing matched.
+    #[arg(short = 'q', long = "quiet")]
+    pub quiet: bool,
+
+    /// Prefix each line with the path of its file.
+    /// This is the default when searching more than one file.
+    #[arg(short = 'H', long = "with-filename", overrides_with = "no_filename")]
+    pub with_filename: bool,
+
+    /// Never prefix the lines with the path of their file.
+    #[arg(long = "no-filename", overrides_with = "with_filename")]
+    pub no_filename: bool,
+
+    /// Search the directories recursively.
+    /// Without files, the current directory is searched.
+    #[arg(short = 'r', long = "recursive")]
+    pub recursive: bool,
+
+    /// The extensions of the files searched in directories.
+    /// Can be given multiple times. Files given on the
+    /// command line are searched whatever their extension.
+    #[arg(long = "ext", value_name = "EXT", default_values_t = format::default_extensions())]
+    pub extensions: Vec<String>,
+
+    /// Only search the files matching GLOB, or skip them if GLOB
+    /// starts with `!`. Can be given multiple times.
+    /// The globs take precedence over --ext.
+    #[arg(short = 'g', long = "glob", value_name = "GLOB")]
+    pub globs: Vec<String>,
+
+    /// Don't skip the files ignored by .gitignore, .ignore
+    /// and .rzstdignore files in the searched directories.
+    #[arg(long = "no-ignore")]
+    pub no_ignore: bool,
+
+    /// Follow symlinks, both given on the command line and
+    /// found in the searched directories.
+    #[arg(long = "follow")]
+    pub follow: bool,
+
+    /// Search the hidden files and directories found
+    /// in the searched directories.
+    #[arg(long = "hidden")]
+    pub hidden: bool,
+
+    /// Search the files listed in FILE, one per line, in addition
+    /// to the ones given on the command line. A FILE of `-` reads
+    /// the list from stdin.
+    #[arg(long = "files-from", value_name = "FILE")]
+    pub files_from: Option<String>,
+
+    /// Like --files-from, but the files in FILE are separated
+    /// by NUL bytes, like `find -print0` writes them.
+    #[arg(long = "files-from0", value_name = "FILE", conflicts_with = "files_from")]
+    pub files_from0: Option<String>,
+
+    /// When to color the output.
+    #[arg(long = "color", value_name = "WHEN", value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Change the style of a kind of text, like `match:fg:yellow`,
+    /// `match:style:bold` or `path:none`. Can be given multiple times.
+    /// The kinds are match, path, line, column, offset and separator.
+    #[arg(long = "colors", value_name = "SPEC")]
+    pub colors: Vec<String>,
+
+    /// Print the results as JSON Lines, using the same messages
+    /// as ripgrep's --json output.
+    #[arg(
+        long = "json",
+        conflicts_with_all = ["count", "count_matches", "files_with_matches", "files_without_match", "quiet", "only_matching"]
+    )]
+    pub json: bool,
+
+    /// Print each match on its own line as `file:line:column:text`,
+    /// the format Vim's quickfix list and the editors' grep
+    /// integrations read.
+    #[arg(long = "vimgrep", conflicts_with_all = ["json", "only_matching"])]
+    pub vimgrep: bool,
+
+    /// Print the path of each file once above its matches, with
+    /// a blank line between the files, instead of on every line.
+    /// The lines of a file are printed once the file is searched.
+    #[arg(long = "heading", conflicts_with_all = ["json", "vimgrep"])]
+    pub heading: bool,
+
+    /// Print statistics about the search once all the files are
+    /// searched, like the number of files and the bytes decompressed.
+    #[arg(long = "stats")]
+    pub stats: bool,
+
+    /// Treat the decompressed data as lines separated by NUL bytes
+    /// instead of newlines, like the output of `find -print0`.
+    /// The printed lines then end with NUL bytes too.
+    #[arg(short = 'z', long = "null-data")]
+    pub null_data: bool,
+
+    /// Let the matches span several lines, like `-U 'Exception.*\n\s+at '`
+    /// for the first frame of a stack trace. `\n` and `\s` match the
+    /// line breaks, `.` doesn't. The whole decoded data of each file
+    /// is then kept in memory, and the lines of each match are printed
+    /// together, each one with its own prefix.
+    #[arg(short = 'U', long = "multiline")]
+    pub multiline: bool,
+
+    /// Compile the patterns with PCRE2, for the backreferences like
+    /// `(\w+) \1` and the look-around like `foo(?=bar)` that the
+    /// default regex engine doesn't have. Needs rzstd built with the
+    /// `pcre2` feature.
+    #[arg(short = 'P', long = "pcre2")]
+    pub pcre2: bool,
+
+    /// Compile the patterns with vectorscan, which matches thousands of
+    /// patterns at once, like the patterns of a large -f file. They are
+    /// PCRE patterns without backreferences and look-around, matched on
+    /// the bytes of each line. Needs rzstd built with the `vectorscan`
+    /// feature.
+    #[arg(long = "vectorscan", conflicts_with_all = ["pcre2", "multiline"])]
+    pub vectorscan: bool,
+
+    /// Search the binary data, the decoded data after a NUL byte,
+    /// as text, and print its matched lines as they are. Without it,
+    /// the first matched line of the binary data prints `binary file
+    /// FILE matches` instead, like grep, and ends the search of the file.
+    #[arg(short = 'a', long = "text", conflicts_with = "binary")]
+    pub text: bool,
+
+    /// Print the matched lines of the binary data instead of
+    /// `binary file FILE matches`, split at the NUL bytes so they
+    /// don't take the whole terminal.
+    #[arg(long = "binary")]
+    pub binary: bool,
+
+    /// What to do with the lines that aren't valid UTF-8, like the
+    /// latin-1 lines of older logs: `lossy` keeps them, with U+FFFD
+    /// instead of their invalid bytes in the --json messages, and
+    /// `skip` skips them as if they didn't match.
+    #[arg(long = "invalid-utf8", value_name = "MODE", value_enum, default_value_t = InvalidUtf8::Lossy)]
+    pub invalid_utf8: InvalidUtf8,
+
+    /// The text encoding of the decoded data, like `utf-16le` for the
+    /// logs written on Windows or `latin1` for older ones, which is
+    /// transcoded to UTF-8 before it is searched and printed. The
+    /// labels are the ones of the WHATWG Encoding Standard.
+    #[arg(short = 'E', long = "encoding", value_name = "ENCODING", value_parser = parse_encoding)]
+    pub encoding: Option<&'static Encoding>,
+
+    /// Print REPLACEMENT instead of each match. Capture groups are
+    /// expanded, `$1` being the first group and `${name}` a named one.
+    /// Use `$$` for a literal `$`.
+    #[arg(long = "replace", value_name = "REPLACEMENT", conflicts_with = "json")]
+    pub replace: Option<String>,
+
+    /// Write the lines of each file to `DIR/<file>.matches` instead
+    /// of stdout. Files without any printed line get no output file.
+    /// The lines aren't prefixed with the path, unless -H is given.
+    /// An output file that already exists, like one of an earlier
+    /// search or of another path to the same file, is an error.
+    #[arg(
+        short = 'O',
+        long = "output-dir",
+        value_name = "DIR",
+        conflicts_with_all = ["count", "count_matches", "files_with_matches", "files_without_match", "quiet", "json", "heading"]
+    )]
+    pub output_dir: Option<PathBuf>,
+
+    /// Decode all the files with this format instead of telling it
+    /// from their first bytes and extension. This is needed for bare
+    /// zlib and deflate streams, which can't be told apart otherwise.
+    #[arg(long = "format", value_name = "FORMAT", value_enum)]
+    pub format: Option<Format>,
+
+    /// Decode the zstd files with the dictionary in FILE, like
+    /// the ones trained with `zstd --train`. The other formats
+    /// don't use it.
+    #[arg(short = 'D', long = "dictionary", value_name = "FILE")]
+    pub dictionary: Option<String>,
+
+    /// Decode the zstd frames with windows of up to 2^WINDOWLOG bytes,
+    /// which bounds the memory of each decoder. zstd only decodes the
+    /// windows of up to 2^27 bytes by default, so the files compressed
+    /// with a larger `--long`, like `--long=31`, need a WINDOWLOG as
+    /// large, and the smaller ones reject the files that use more.
+    #[arg(long = "max-window", value_name = "WINDOWLOG", value_parser = clap::value_parser!(u32).range(10..=31))]
+    pub max_window: Option<u32>,
+
+    /// Print the magic number, offset and payload size of the
+    /// skippable frames of the zstd files to stderr, as they
+    /// are read. Only the part of a file that is searched is read.
+    #[arg(long = "show-skippable")]
+    pub show_skippable: bool,
+
+    /// Also open the archives and compressed files found inside
+    /// archives, like the `.gz` files of a `.tar.zst`, up to N levels
+    /// deep. By default the members are searched as they are.
+    #[arg(long = "search-archives", value_name = "N", default_value_t = 0)]
+    pub search_archives: usize,
+
+    /// Stop with an error once the archives and compressed files
+    /// opened with --search-archives decode to more than SIZE bytes
+    /// in a file, to guard against decompression bombs. SIZE is a
+    /// number of bytes, which can end with K, M or G.
+    #[arg(long = "max-expansion", value_name = "SIZE", default_value = "1G", value_parser = parse_size)]
+    pub max_expansion: u64,
+
+    /// Search up to N files at once, and the frames of a seekable
+    /// zstd file with up to N threads. Defaults to the number of CPUs.
+    #[arg(short = 'j', long = "threads", value_name = "N")]
+    pub threads: Option<usize>,
+
+    /// Decode the frames of the zstd files on up to N threads at once,
+    /// for the files made of many frames like the ones of pzstd. With
+    /// more than 1, a file made of a single frame is still decoded on
+    /// another thread than the one searching it.
+    #[arg(long = "decompress-threads", value_name = "N", default_value_t = 1)]
+    pub decompress_threads: usize,
+
+    /// Memory map the files instead of reading them, which can be
+    /// faster for many medium-sized files. The files that can't be
+    /// mapped are read. A file truncated while it is searched can
+    /// make rzstd crash.
+    #[arg(long = "mmap")]
+    pub mmap: bool,
+
+    /// Keep the decoded data of the compressed files in DIR, so that
+    /// searching them again with other patterns reads it instead of
+    /// decoding the files once more. A file that changed is decoded
+    /// again.
+    #[arg(long = "cache-dir", value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Remove the least recently used data from the --cache-dir once
+    /// it takes more than SIZE bytes, which can end with K, M or G.
+    #[arg(long = "cache-size", value_name = "SIZE", default_value = "1G", value_parser = parse_size)]
+    pub cache_size: u64,
+
+    /// Once the end of a file is reached, wait for more data to be
+    /// appended to it and search it too, like `tail -f | grep`, for
+    /// the zstd logs written one frame after the other. The lines are
+    /// printed as they are found, until rzstd is stopped.
+    #[arg(
+        long = "follow-tail",
+        conflicts_with_all = ["count", "count_matches", "files_with_matches", "files_without_match", "json", "heading", "output_dir"]
+    )]
+    pub follow_tail: bool,
+
+    /// Once the files are searched, watch them, and the directories
+    /// with -r, and search the files again each time they change or
+    /// new ones are added, until rzstd is stopped. The files given
+    /// with --files-from are only searched once.
+    #[arg(long = "watch", conflicts_with_all = ["follow_tail", "quiet"])]
+    pub watch: bool,
+
+    /// Show on stderr a progress bar of each file being read, with
+    /// how much of its compressed data is read and how fast, and one
+    /// more of all the files. This is the default when stderr is a
+    /// terminal, --progress shows them even when it isn't.
+    #[arg(long = "progress", overrides_with = "no_progress")]
+    pub progress: bool,
+
+    /// Never show the progress bars, like for cron jobs and CI logs.
+    #[arg(long = "no-progress", overrides_with = "progress")]
+    pub no_progress: bool,
+
+    /// Instead of the progress bars, write on stderr a line of JSON
+    /// about each file being read every second, and once it is done,
+    /// for the tools that show the progress themselves.
+    #[arg(long = "progress-json", conflicts_with_all = ["progress", "no_progress"])]
+    pub progress_json: bool,
+
+    /// Read the indexes of the files written by `rzstd index
+    /// --index-dir DIR` from DIR, instead of next to the files.
+    #[arg(long = "index-dir", value_name = "DIR")]
+    pub index_dir: Option<PathBuf>,
+
+    /// Only search the members of the archives matching GLOB, or
+    /// skip them if GLOB starts with `!`. Can be given multiple times.
+    /// A glob with a `/`, like `var/log/**/*.log`, is matched against
+    /// the path of the member in its archive, and one without a `/`
+    /// against its name. The skipped members of zip archives aren't
+    /// even decompressed.
+    #[arg(long = "member-glob", value_name = "GLOB")]
+    pub member_globs: Vec<String>,
+}
+
+/// The commands of rzstd other than the search.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Write the indexes of zstd files, which let the searches skip
+    /// the frames that can't have matches.
+    Index(IndexArgs),
+    /// Write the decoded data of the files to stdout, one after the
+    /// other, like zstdcat and zcat but for all the formats.
+    Cat(CatArgs),
+    /// Decode the files to check that they aren't corrupted, with the
+    /// checksums of the zstd frames, and tell where the corrupted ones
+    /// stop decoding.
+    Test(TestArgs),
+    /// Compress the files with zstd, to `FILE.zst`, or stdin to stdout.
+    Compress(CompressArgs),
+    /// Train a zstd dictionary on sample files, like the logs whose
+    /// next files are compressed with it.
+    #[command(name = "train-dict")]
+    TrainDict(TrainDictArgs),
+    /// Answer the searches sent as JSON lines to a Unix socket, for the
+    /// tools searching the same files again and again.
+    Serve(ServeArgs),
+    /// Measure how fast the files are decoded and searched, with
+    /// several numbers of threads and buffer sizes.
+    Bench(BenchArgs),
+}
+
+/// Command line arguments of `rzstd index`.
+#[derive(clap::Args, Debug, Clone)]
+pub struct IndexArgs {
+    /// The zstd files to index. The index of a file is written next
+    /// to it, in `FILE.rzi`. Only the files made of several frames
+    /// have frames to skip.
+    #[arg(value_name = "FILES", required = true)]
+    pub files: Vec<String>,
+
+    /// Write the indexes to DIR instead of next to the files, under the
+    /// absolute paths of the files, for the files in read-only
+    /// directories. The searches read them with the same --index-dir.
+    #[arg(long = "index-dir", value_name = "DIR")]
+    pub index_dir: Option<PathBuf>,
+
+    /// The most bytes of Bloom filter a frame gets, which can end with
+    /// K, M or G. The larger the filters, the fewer frames are searched
+    /// for nothing. 0 writes the indexes without filters, which only
+    /// give the line numbers and byte offsets of the frames.
+    #[arg(long = "bloom-size", value_name = "SIZE", default_value_t = index::DEFAULT_BLOOM_SIZE, value_parser = parse_size)]
+    pub bloom_size: u64,
+
+    /// Index the lines separated by NUL bytes, for the searches with -z.
+    #[arg(short = 'z', long = "null-data")]
+    pub null_data: bool,
+
+    /// Decode the files with the dictionary in FILE.
+    #[arg(short = 'D', long = "dictionary", value_name = "FILE")]
+    pub dictionary: Option<String>,
+
+    /// Decode the zstd frames with windows of up to 2^WINDOWLOG bytes,
+    /// 27 by default, like for the searches.
+    #[arg(long = "max-window", value_name = "WINDOWLOG", value_parser = clap::value_parser!(u32).range(10..=31))]
+    pub max_window: Option<u32>,
+}
+
+impl IndexArgs {
+    /// Returns the zstd dictionary given with -D, read from its file.
+    pub fn dictionary(&self) -> Result<Option<Arc<Vec<u8>>>> {
+        read_dictionary(self.dictionary.as_deref())
+    }
+
+    /// Returns the byte that ends the indexed lines.
+    pub fn line_terminator(&self) -> u8 {
+        if self.null_data {
+            b'\0'
+        } else {
+            b'\n'
+        }
+    }
+}
+
+/// Command line arguments of `rzstd cat`.
+#[derive(clap::Args, Debug, Clone)]
+pub struct CatArgs {
+    /// The files to decode, whose format is told like for the
+    /// searched files. A file of `-` reads from stdin, which is also
+    /// read when no files are given.
+    #[arg(value_name = "FILES")]
+    pub files: Vec<String>,
+
+    /// Decode all the files with this format instead of telling it
+    /// from their first bytes and extension.
+    #[arg(long = "format", value_name = "FORMAT", value_enum)]
+    pub format: Option<Format>,
+
+    /// Decode the zstd files with the dictionary in FILE.
+    #[arg(short = 'D', long = "dictionary", value_name = "FILE")]
+    pub dictionary: Option<String>,
+
+    /// Decode the zstd frames with windows of up to 2^WINDOWLOG bytes,
+    /// 27 by default, like for the searches.
+    #[arg(long = "max-window", value_name = "WINDOWLOG", value_parser = clap::value_parser!(u32).range(10..=31))]
+    pub max_window: Option<u32>,
+
+    /// Decode the frames of the zstd files on up to N threads at once.
+    #[arg(long = "decompress-threads", value_name = "N", default_value_t = 1)]
+    pub decompress_threads: usize,
+
+    /// Show on stderr a progress bar of each file being decoded,
+    /// and one more of all the files. This is the default when
+    /// stderr is a terminal, --progress shows them even when it isn't.
+    #[arg(long = "progress", overrides_with = "no_progress")]
+    pub progress: bool,
+
+    /// Never show the progress bars.
+    #[arg(long = "no-progress", overrides_with = "progress")]
+    pub no_progress: bool,
+
+    /// Instead of the progress bars, write on stderr a line of JSON
+    /// about each file being decoded every second, and once it is done.
+    #[arg(long = "progress-json", conflicts_with_all = ["progress", "no_progress"])]
+    pub progress_json: bool,
+}
+
+impl CatArgs {
+    /// Returns the options the files are decoded with, the ones
+    /// of the search that change how the files are decoded.
+    /// This reads the dictionary given with -D.
+    pub fn search_options(&self) -> Result<SearchOptions> {
+        Ok(SearchOptions {
+            // The files are given one by one, like to zcat
+            follow_links: true,
+            format: self.format,
+            dictionary: read_dictionary(self.dictionary.as_deref())?,
+            max_window: self.max_window,
+            decompress_threads: self.decompress_threads,
+            progress: match self.progress_json {
+                true => Some(Progress::json()),
+                false => Progress::from_flags(self.progress, self.no_progress),
+            },
+            ..Default::default()
+        })
+    }
+
+    /// Returns the files given on the command line, or stdin.
+    pub fn files(&self) -> Vec<String> {
+        match self.files.is_empty() {
+            true => vec![STDIN_PATH.to_string()],
+            false => self.files.clone(),
+        }
+    }
+}
+
+/// Command line arguments of `rzstd test`.
+#[derive(clap::Args, Debug, Clone)]
+pub struct TestArgs {
+    /// The files to check, whose format is told like for the searched
+    /// files. A file of `-` reads from stdin, which is also read when
+    /// no files are given.
+    #[arg(value_name = "FILES")]
+    pub files: Vec<String>,
+
+    /// Decode all the files with this format instead of telling it
+    /// from their first bytes and extension.
+    #[arg(long = "format", value_name = "FORMAT", value_enum)]
+    pub format: Option<Format>,
+
+    /// Decode the zstd files with the dictionary in FILE.
+    #[arg(short = 'D', long = "dictionary", value_name = "FILE")]
+    pub dictionary: Option<String>,
+
+    /// Decode the zstd frames with windows of up to 2^WINDOWLOG bytes,
+    /// 27 by default, like for the searches.
+    #[arg(long = "max-window", value_name = "WINDOWLOG", value_parser = clap::value_parser!(u32).range(10..=31))]
+    pub max_window: Option<u32>,
+
+    /// Print the size of the decoded data of each file that
+    /// isn't corrupted.
+    #[arg(short = 'v', long = "verbose")]
+    pub verbose: bool,
+}
+
+impl TestArgs {
+    /// Returns the options the files are decoded with.
+    /// This reads the dictionary given with -D.
+    pub fn search_options(&self) -> Result<SearchOptions> {
+        Ok(SearchOptions {
+            follow_links: true,
+            format: self.format,
+            dictionary: read_dictionary(self.dictionary.as_deref())?,
+            max_window: self.max_window,
+            ..Default::default()
+        })
+    }
+
+    /// Returns the files given on the command line, or stdin.
+    pub fn files(&self) -> Vec<String> {
+        match self.files.is_empty() {
+            true => vec![STDIN_PATH.to_string()],
+            false => self.files.clone(),
+        }
+    }
+}
+
+/// Command line arguments of `rzstd compress`.
+#[derive(clap::Args, Debug, Clone)]
+pub struct CompressArgs {
+    /// The files to compress, each one to `FILE.zst` next to it. A file
+    /// of `-` reads from stdin and writes to stdout, and stdin is also
+    /// read when no files are given.
+    #[arg(value_name = "FILES")]
+    pub files: Vec<String>,
+
+    /// Write the compressed data to FILE, or to stdout for `-`.
+    /// Only one file can be compressed to it.
+    #[arg(short = 'o', long = "output", value_name = "FILE", conflicts_with = "stdout")]
+    pub output: Option<String>,
+
+    /// Write the compressed data of all the files to stdout,
+    /// one frame after the other.
+    #[arg(short = 'c', long = "stdout")]
+    pub stdout: bool,
+
+    /// Overwrite the output files that already exist.
+    #[arg(short = 'f', long = "force")]
+    pub force: bool,
+
+    /// The compression level, from the fast negative levels to 22.
+    #[arg(short = 'l', long = "level", value_name = "LEVEL", default_value_t = 3, allow_negative_numbers = true)]
+    pub level: i32,
+
+    /// Find the matches far apart in large files, with a window of
+    /// 2^WINDOWLOG bytes, 27 by default. The files compressed with a
+    /// WINDOWLOG above 27 need `--long` to be decoded by zstd.
+    #[arg(
+        long = "long",
+        value_name = "WINDOWLOG",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "27",
+        value_parser = clap::value_parser!(u32).range(10..=31)
+    )]
+    pub long: Option<u32>,
+
+    /// Compress each file on N threads, or on as many as the CPUs for 0.
+    #[arg(short = 'T', long = "threads", value_name = "N", default_value_t = 1)]
+    pub threads: u32,
+
+    /// Compress the files with the dictionary in FILE, which decoding
+    /// them then needs too, with -D.
+    #[arg(short = 'D', long = "dictionary", value_name = "FILE")]
+    pub dictionary: Option<String>,
+}
+
+impl CompressArgs {
+    /// Returns the options the files are compressed with.
+    /// This reads the dictionary given with -D.
+    pub fn compress_options(&self) -> Result<CompressOptions> {
+        let levels = compress::levels();
+        if !levels.contains(&self.level) {
+            let (min, max) = (levels.start(), levels.end());
+            return Err(anyhow::anyhow!("Error compressing: the level is {}, not between {} and {}", self.level, min, max));
+        }
+        let threads = match self.threads {
+            0 => thread::available_parallelism().map_or(1, |threads| threads.get() as u32),
+            threads => threads,
+        };
+        Ok(CompressOptions {
+            level: self.level,
+            long: self.long,
+            threads,
+            dictionary: read_dictionary(self.dictionary.as_deref())?,
+        })
+    }
+
+    /// Returns the files given on the command line, or stdin.
+    pub fn files(&self) -> Vec<String> {
+        match self.files.is_empty() {
+            true => vec![STDIN_PATH.to_string()],
+            false => self.files.clone(),
+        }
+    }
+
+    /// Returns where the compressed data of a file is written,
+    /// None for stdout.
+    pub fn output_path(&self, file_path: &str) -> Option<String> {
+        if self.stdout || file_path == STDIN_PATH && self.output.is_none() {
+            return None;
+        }
+        match &self.output {
+            Some(output) if output == STDIN_PATH => None,
+            Some(output) => Some(output.clone()),
+            None => Some(format!("{}.{}", file_path, compress::EXTENSION)),
+        }
+    }
+}
+
+/// Command line arguments of `rzstd train-dict`.
+#[derive(clap::Args, Debug, Clone)]
+pub struct TrainDictArgs {
+    /// The sample files, and the directories whose files are samples.
+    /// The samples can be compressed in any of the searched formats.
+    #[arg(value_name = "SAMPLES", required = true)]
+    pub samples: Vec<String>,
+
+    /// Write the dictionary to FILE.
+    #[arg(short = 'o', long = "output", value_name = "FILE", default_value = "dictionary")]
+    pub output: PathBuf,
+
+    /// The most bytes of the dictionary, which can end with K, M or G.
+    #[arg(long = "max-size", value_name = "SIZE", default_value = "110K", value_parser = parse_size)]
+    pub max_size: u64,
+
+    /// Cut the data of each file in samples of SIZE bytes, which can
+    /// end with K, M or G, for large files like logs. Each file is a
+    /// single sample by default.
+    #[arg(long = "sample-size", value_name = "SIZE", value_parser = parse_size)]
+    pub sample_size: Option<u64>,
+
+    /// Only use the files of the directories matching GLOB, or skip
+    /// them if GLOB starts with `!`. Can be given multiple times.
+    /// All the files are used by default.
+    #[arg(short = 'g', long = "glob", value_name = "GLOB")]
+    pub globs: Vec<String>,
+}
+
+impl TrainDictArgs {
+    /// Returns the options used to walk the directories of samples.
+    /// This parses the globs given with -g.
+    pub fn walk_options(&self) -> Result<WalkOptions> {
+        let mut globs = self.globs.clone();
+        if !globs.iter().any(|glob| !glob.starts_with('!')) {
+            globs.push("*".to_string());
+        }
+        Ok(WalkOptions {
+            globs: GlobFilter::new(&globs)?,
+            ignore_files: true,
+            ..Default::default()
+        })
+    }
+
+    /// Returns the options the samples are decoded with.
+    pub fn search_options(&self) -> SearchOptions {
+        SearchOptions {
+            follow_links: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Command line arguments of `rzstd serve`.
+#[derive(clap::Args, Debug, Clone)]
+pub struct ServeArgs {
+    /// Listen on the Unix socket at PATH. A socket left there by a
+    /// server that stopped is replaced.
+    #[arg(long = "socket", value_name = "PATH", required = true)]
+    pub socket: PathBuf,
+
+    /// The extensions of the files searched in the directories of the
+    /// recursive searches. Can be given multiple times.
+    #[arg(long = "ext", value_name = "EXT", default_values_t = format::default_extensions())]
+    pub extensions: Vec<String>,
+
+    /// Keep the decoded data of the compressed files in DIR, so that
+    /// the searches of the same files with other patterns read it.
+    #[arg(long = "cache-dir", value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Remove the least recently used data from the --cache-dir once
+    /// it takes more than SIZE bytes, which can end with K, M or G.
+    #[arg(long = "cache-size", value_name = "SIZE", default_value = "1G", value_parser = parse_size)]
+    pub cache_size: u64,
+
+    /// Decode the zstd files with the dictionary in FILE.
+    #[arg(short = 'D', long = "dictionary", value_name = "FILE")]
+    pub dictionary: Option<String>,
+
+    /// Decode the zstd frames with windows of up to 2^WINDOWLOG bytes,
+    /// 27 by default, like for the searches.
+    #[arg(long = "max-window", value_name = "WINDOWLOG", value_parser = clap::value_parser!(u32).range(10..=31))]
+    pub max_window: Option<u32>,
+}
+
+impl ServeArgs {
+    /// Returns the options all the searches start from.
+    /// This reads the dictionary given with -D.
+    pub fn search_options(&self) -> Result<SearchOptions> {
+        Ok(SearchOptions {
+            follow_links: true,
+            max_expansion: search::DEFAULT_MAX_EXPANSION,
+            dictionary: read_dictionary(self.dictionary.as_deref())?,
+            max_window: self.max_window,
+            cache: self.cache_dir.clone().map(|dir| BlockCache::new(dir, self.cache_size)),
+            ..Default::default()
+        })
+    }
+
+    /// Returns the options used to walk the directories.
+    pub fn walk_options(&self) -> WalkOptions {
+        WalkOptions {
+            extensions: self.extensions.clone(),
+            ignore_files: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Command line arguments of `rzstd bench`.
+#[derive(clap::Args, Debug, Clone)]
+pub struct BenchArgs {
+    /// The files to measure, whose format is told like for the
+    /// searched files. Their decoded data is kept in memory.
+    #[arg(value_name = "FILES", required = true)]
+    pub files: Vec<String>,
+
+    /// A regex to search for. Can be given multiple times.
+    #[arg(short = 'e', long = "regexp", value_name = "PATTERN", required = true)]
+    pub regexps: Vec<String>,
+
+    /// The numbers of files decoded and searched at once, like -j,
+    /// separated by commas. Defaults to 1 and the number of CPUs.
+    #[arg(short = 'j', long = "threads", value_name = "NUM", value_delimiter = ',')]
+    pub threads: Vec<usize>,
+
+    /// The sizes of the reads of the files and of their decoded data,
+    /// separated by commas, which can end with K, M or G.
+    #[arg(
+        long = "buffer-size",
+        value_name = "SIZE",
+        value_delimiter = ',',
+        default_value = "8K,64K,1M",
+        value_parser = parse_size
+    )]
+    pub buffer_sizes: Vec<u64>,
+
+    /// Run each measure NUM times, and keep the fastest run.
+    #[arg(long = "runs", value_name = "NUM", default_value_t = 3)]
+    pub runs: usize,
+
+    /// Decode the zstd files with the dictionary in FILE.
+    #[arg(short = 'D', long = "dictionary", value_name = "FILE")]
+    pub dictionary: Option<String>,
+
+    /// Decode the zstd frames with windows of up to 2^WINDOWLOG bytes,
+    /// 27 by default, like for the searches.
+    #[arg(long = "max-window", value_name = "WINDOWLOG", value_parser = clap::value_parser!(u32).range(10..=31))]
+    pub max_window: Option<u32>,
+}
+
+impl BenchArgs {
+    /// Returns the options the files are decoded and searched with.
+    /// This checks the measures and reads the dictionary given with -D.
+    pub fn search_options(&self) -> Result<SearchOptions> {
+        if self.threads.contains(&0) || self.buffer_sizes.contains(&0) || self.runs == 0 {
+            return Err(anyhow::anyhow!("Error running bench: the threads, buffer sizes and runs can't be 0"));
+        }
+        Ok(SearchOptions {
+            dictionary: read_dictionary(self.dictionary.as_deref())?,
+            max_window: self.max_window,
+            ..Default::default()
+        })
+    }
+
+    /// Returns the numbers of threads to measure, without the
+    /// ones given twice.
+    pub fn threads(&self) -> Vec<usize> {
+        let mut threads = self.threads.clone();
+        if threads.is_empty() {
+            threads = vec![1, thread::available_parallelism().map_or(1, |jobs| jobs.get())];
+        }
+        let mut measured = Vec::new();
+        for count in threads {
+            if !measured.contains(&count) {
+                measured.push(count);
+            }
+        }
+        measured
+    }
+}
+
+/// Reads the zstd dictionary given with -D, if any.
+fn read_dictionary(path: Option<&str>) -> Result<Option<Arc<Vec<u8>>>> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    match fs::read(path) {
+        Ok(dictionary) => Ok(Some(Arc::new(dictionary))),
+        Err(e) => Err(anyhow::anyhow!("Error reading dictionary {}: {}", path, e)),
+    }
+}
+
+/// Parses a size like `512`, `64K`, `10M` or `1G`,
+/// whose suffixes are powers of 1024.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (digits, unit) = match size.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&size[..index], 1 << 10),
+        Some((index, 'M' | 'm')) => (&size[..index], 1 << 20),
+        Some((index, 'G' | 'g')) => (&size[..index], 1 << 30),
+        _ => (size, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(unit))
+        .ok_or_else(|| format!("invalid size {}", size))
+}
+
+/// Parses the label of a text encoding, like `utf-16le` or `latin1`.
+fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    match Encoding::for_label_no_replacement(label.as_bytes()) {
+        Some(encoding) => Ok(encoding),
+        None => Err(format!("unknown encoding {}", label)),
+    }
+}
+
+impl Args {
+    /// Returns the patterns to search for.
+    /// This reads the pattern files given with -f.
+    pub fn patterns(&self) -> Result<Vec<String>> {
+        if !self.has_flag_patterns() {
+            return Ok(self.pattern.iter().cloned().collect());
+        }
+
+        let mut patterns = self.regexps.clone();
+        for pattern_file in &self.pattern_files {
+            let contents = match fs::read_to_string(pattern_file) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    let e = anyhow::anyhow!("Error reading pattern file {}: {}", pattern_file, e);
+                    return Err(e);
+                }
+            };
+            patterns.extend(contents.lines().map(|line| line.to_string()));
+        }
+        Ok(patterns)
+    }
+
+    /// Returns the options used to build the matcher.
+    pub fn matcher_options(&self) -> MatcherOptions {
+        let case = if self.ignore_case {
+            CaseMode::Insensitive
+        } else if self.smart_case {
+            CaseMode::Smart
+        } else {
+            CaseMode::Sensitive
+        };
+
+        MatcherOptions {
+            case,
+            fixed_strings: self.fixed_strings,
+            word: self.word_regexp,
+            whole_line: self.line_regexp,
+            ascii_only: self.no_unicode,
+            size_limit: self.regex_size_limit.map(|size| size as usize),
+            dfa_size_limit: self.dfa_size_limit.map(|size| size as usize),
+            multiline: self.multiline,
+            pcre2: self.pcre2,
+            vectorscan: self.vectorscan,
+        }
+    }
+
+    /// Returns the config every file is searched with,
+    /// made of the patterns and the options.
+    pub fn search_config(&self) -> Result<SearchConfig> {
+        SearchBuilder::from_options(self.search_options()?)
+            .patterns(self.patterns()?)
+            .matcher_options(self.matcher_options())
+            .build()
+    }
+
+    /// Returns the options used to search each file.
+    /// This parses the color specs given with --colors and the
+    /// globs given with --member-glob, and reads the dictionary
+    /// given with -D.
+    pub fn search_options(&self) -> Result<SearchOptions> {
+        Ok(SearchOptions {
+            invert_match: self.invert_match,
+            count: self.count,
+            count_matches: self.count_matches,
+            files_with_matches: self.files_with_matches,
+            files_without_match: self.files_without_match,
+            max_count: self.max_count,
+            // The JSON messages always carry the line numbers
+            line_number: self.line_number || self.column || self.json || self.vimgrep,
+            column: self.column || self.vimgrep,
+            byte_offset: self.byte_offset,
+            only_matching: self.only_matching,
+            after_context: self.after_context.or(self.context).unwrap_or(0),
+            before_context: self.before_context.or(self.context).unwrap_or(0),
+            quiet: self.quiet,
+            with_filename: self.with_filename(),
+            follow_links: self.follow,
+            colors: ColorConfig::new(&self.colors)?,
+            json: self.json,
+            vimgrep: self.vimgrep,
+            heading: self.heading,
+            null_data: self.null_data,
+            multiline: self.multiline,
+            binary: self.binary_mode(),
+            invalid_utf8: self.invalid_utf8,
+            encoding: self.encoding,
+            replace: self.replace.clone(),
+            output_dir: self.output_dir.clone(),
+            format: self.format,
+            dictionary: self.dictionary()?,
+            max_window: self.max_window,
+            show_skippable: self.show_skippable,
+            search_archives: self.search_archives,
+            max_expansion: self.max_expansion,
+            member_globs: GlobFilter::new(&self.member_globs)?,
+            sink: None,
+            threads: self.threads,
+            decompress_threads: self.decompress_threads,
+            mmap: self.mmap,
+            cache: self.cache_dir.clone().map(|dir| BlockCache::new(dir, self.cache_size)),
+            index_dir: self.index_dir.clone(),
+            follow_tail: self.follow_tail,
+            progress: match self.progress_json {
+                true => Some(Progress::json()),
+                false => Progress::from_flags(self.progress, self.no_progress),
+            },
+        })
+    }
+
+    /// Returns what is printed of the binary data, see -a and --binary.
+    fn binary_mode(&self) -> BinaryMode {
+        if self.text {
+            BinaryMode::Text
+        } else if self.binary {
+            BinaryMode::Search
+        } else {
+            BinaryMode::Suppress
+        }
+    }
+
+    /// Returns the zstd dictionary given with -D, read from its file.
+    fn dictionary(&self) -> Result<Option<Arc<Vec<u8>>>> {
+        read_dictionary(self.dictionary.as_deref())
+    }
+
+    /// Returns the options used to walk the directories.
+    /// This parses the globs given with -g.
+    pub fn walk_options(&self) -> Result<WalkOptions> {
+        Ok(WalkOptions {
+            extensions: self.extensions.clone(),
+            globs: GlobFilter::new(&self.globs)?,
+            ignore_files: !self.no_ignore,
+            follow_links: self.follow,
+            hidden: self.hidden,
+        })
+    }
+
+    /// Returns true if the lines are prefixed with the path of their file.
+    /// Without -H or --no-filename, this depends on how many files are searched.
+    /// --vimgrep always prints the paths, and --output-dir only
+    /// prints them with -H since each output file has a single input.
+    fn with_filename(&self) -> bool {
+        if self.with_filename || self.vimgrep {
+            return true;
+        }
+        if self.no_filename || self.output_dir.is_some() {
+            return false;
+        }
+        self.recursive || self.file_list().is_some() || self.files().len() > 1
+    }
+
+    /// Returns true if the output is colored.
+    /// The output files of --output-dir are only colored
+    /// with --color always, as they are never a terminal.
+    pub fn color_enabled(&self) -> bool {
+        if self.output_dir.is_some() && self.color == ColorChoice::Auto {
+            return false;
+        }
+        self.color.enabled()
+    }
+
+    /// Returns true if the patterns are given with -e or -f
+    /// instead of the first positional argument.
+    fn has_flag_patterns(&self) -> bool {
+        !self.regexps.is_empty() || !self.pattern_files.is_empty()
+    }
+
+    /// Returns the number of files searched at once, given with -j.
+    /// The followed files are all searched at once, as their
+    /// searches never end.
+    pub fn jobs(&self) -> usize {
+        if self.follow_tail {
+            return usize::MAX;
+        }
+        self.threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |jobs| jobs.get()))
+    }
+
+    /// Returns the file list given with --files-from or --files-from0,
+    /// and the byte that separates the files in it.
+    pub fn file_list(&self) -> Option<(&str, u8)> {
+        if let Some(list) = &self.files_from {
+            return Some((list, b'\n'));
+        }
+        self.files_from0.as_deref().map(|list| (list, b'\0'))
+    }
+
+    /// Returns the files given on the command line.
+    /// Falls back to stdin, or to the current directory
+    /// when searching recursively, when no files are given.
+    /// The files of the file list are not included.
+    pub fn files(&self) -> Vec<String> {
+        let mut files = Vec::new();
+        // The first positional argument is a file
+        // when the patterns are given with -e or -f.
+        if self.has_flag_patterns() {
+            files.extend(self.pattern.iter().cloned());
+        }
+        files.extend(self.files.iter().cloned());
+
+        // With --files-from, all the files can come from the list
+        if files.is_empty() && self.file_list().is_none() {
+            let default = if self.recursive { "." } else { STDIN_PATH };
+            files.push(default.to_string());
+        }
+        files
+    }
+}
@@ -0,0 +1,174 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use colored::{Color, ColoredString, Colorize};
+
+/// How one kind of text is styled in the output.
+#[derive(Debug, Clone, Default)]
+pub struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    dimmed: bool,
+}
+
+impl Style {
+    /// Returns a style with only a foreground color.
+    fn fg(color: Color) -> Style {
+        Style {
+            fg: Some(color),
+            ..Default::default()
+        }
+    }
+
+    /// Applies the style to the text.
+    /// Nothing is applied when colors are turned off.
+    pub fn paint(&self, text: &str) -> ColoredString {
+        let mut painted = text.normal();
+        if let Some(fg) = self.fg {
+            painted = painted.color(fg);
+        }
+        if let Some(bg) = self.bg {
+            painted = painted.on_color(bg);
+        }
+        if self.bold {
+            painted = painted.bold();
+        }
+        if self.italic {
+            painted = painted.italic();
+        }
+        if self.underline {
+            painted = painted.underline();
+        }
+        if self.dimmed {
+            painted = painted.dimmed();
+        }
+        painted
+    }
+
+    /// Adds the bytes with the style applied to the output, like paint
+    /// does for text, so that the lines are printed as they are decoded.
+    pub fn paint_bytes(&self, bytes: &[u8], output: &mut Vec<u8>) {
+        // The style starts and ends with the codes around a space,
+        // which are empty when colors are turned off
+        let painted = self.paint(" ").to_string();
+        let (start, end) = painted.split_once(' ').unwrap_or_default();
+        if start.is_empty() {
+            output.extend_from_slice(bytes);
+            return;
+        }
+
+        output.extend_from_slice(start.as_bytes());
+        // A reset inside the bytes would end the style too early,
+        // so the style starts again after it
+        let mut rest = bytes;
+        while let Some(reset) = rest.windows(RESET.len()).position(|window| window == RESET) {
+            output.extend_from_slice(&rest[..reset + RESET.len()]);
+            output.extend_from_slice(start.as_bytes());
+            rest = &rest[reset + RESET.len()..];
+        }
+        output.extend_from_slice(rest);
+        output.extend_from_slice(end.as_bytes());
+    }
+}
+
+/// The code that ends the styles.
+const RESET: &[u8] = b"\x1b[0m";
+
+/// The styles of the output. Each one can be changed
+/// with --colors.
+#[derive(Debug, Clone)]
+pub struct ColorConfig {
+    /// The matched text.
+    pub matched: Style,
+    /// The path of the file.
+    pub path: Style,
+    /// The line numbers.
+    pub line: Style,
+    /// The column numbers.
+    pub column: Style,
+    /// The byte offsets.
+    pub offset: Style,
+    /// The `--` between the groups of context lines.
+    pub separator: Style,
+}
+
+impl Default for ColorConfig {
+    fn default() -> ColorConfig {
+        ColorConfig {
+            matched: Style::fg(Color::Red),
+            path: Style::fg(Color::Magenta),
+            line: Style::fg(Color::Green),
+            column: Style::fg(Color::Green),
+            offset: Style::fg(Color::Green),
+            separator: Style::fg(Color::Cyan),
+        }
+    }
+}
+
+impl ColorConfig {
+    /// Builds the styles from the default ones changed by the
+    /// specs given with --colors, in order.
+    ///
+    /// A spec is `{type}:{attribute}:{value}` or `{type}:none`, where
+    /// the type is match, path, line, column, offset or separator,
+    /// and the attribute is fg, bg or style. Colors are names like
+    /// `yellow` or `bright blue`, or `r,g,b` triples. Styles are
+    /// bold, italic, underline, dimmed and their `no` variants,
+    /// like `nobold`.
+    pub fn new(specs: &[String]) -> Result<ColorConfig> {
+        let mut config = ColorConfig::default();
+        for spec in specs {
+            if let Err(e) = config.apply(spec) {
+                return Err(anyhow::anyhow!("Error parsing color spec {}: {}", spec, e));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Applies a single spec given with --colors.
+    fn apply(&mut self, spec: &str) -> Result<()> {
+        let parts: Vec<&str> = spec.splitn(3, ':').collect();
+        let style = match parts[0] {
+            "match" => &mut self.matched,
+            "path" => &mut self.path,
+            "line" => &mut self.line,
+            "column" => &mut self.column,
+            "offset" => &mut self.offset,
+            "separator" => &mut self.separator,
+            other => return Err(anyhow::anyhow!("unknown type {}", other)),
+        };
+
+        match parts[1..] {
+            ["none"] => *style = Style::default(),
+            ["fg", value] => style.fg = Some(parse_color(value)?),
+            ["bg", value] => style.bg = Some(parse_color(value)?),
+            ["style", "bold"] => style.bold = true,
+            ["style", "nobold"] => style.bold = false,
+            ["style", "italic"] => style.italic = true,
+            ["style", "noitalic"] => style.italic = false,
+            ["style", "underline"] => style.underline = true,
+            ["style", "nounderline"] => style.underline = false,
+            ["style", "dimmed"] => style.dimmed = true,
+            ["style", "nodimmed"] => style.dimmed = false,
+            ["style", other] => return Err(anyhow::anyhow!("unknown style {}", other)),
+            _ => return Err(anyhow::anyhow!("expected {{type}}:{{attribute}}:{{value}} or {{type}}:none")),
+        }
+        Ok(())
+    }
+}
+
+/// Parses a color name or an `r,g,b` triple.
+fn parse_color(value: &str) -> Result<Color> {
+    if let Ok(color) = Color::from_str(value) {
+        return Ok(color);
+    }
+
+    let rgb: Vec<Result<u8, _>> = value.split(',').map(|c| c.trim().parse::<u8>()).collect();
+    match rgb[..] {
+        [Ok(r), Ok(g), Ok(b)] => Ok(Color::TrueColor { r, g, b }),
+        _ => Err(anyhow::anyhow!("unknown color {}", value)),
+    }
+}
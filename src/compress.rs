@@ -0,0 +1,100 @@
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+/// The extension of the compressed files.
+pub const EXTENSION: &str = "zst";
+
+/// How `rzstd compress` compresses the files.
+#[derive(Debug, Clone, Default)]
+pub struct CompressOptions {
+    /// The compression level, from the negative fast ones to 22.
+    pub level: i32,
+    /// The window log of the long distance matching, which finds
+    /// the matches far apart in large files, or None without it.
+    /// The files compressed with a window log above 27 need a
+    /// larger window to be decoded.
+    pub long: Option<u32>,
+    /// The number of threads compressing the data, 0 or 1 to
+    /// compress it on the calling thread. Only the builds with the
+    /// `native` feature compress on several threads.
+    pub threads: u32,
+    /// The dictionary the data is compressed with, which the
+    /// decoders need too.
+    pub dictionary: Option<Arc<Vec<u8>>>,
+}
+
+/// Compresses the input into a zstd frame written to the output, with
+/// the checksum of the data, like the zstd command line tool does.
+/// size is the size of the input when it is known, which is written
+/// in the header of the frame. Returns the size of the input.
+pub fn compress<R: Read, W: Write>(
+    mut input: R,
+    output: W,
+    size: Option<u64>,
+    options: &CompressOptions,
+) -> io::Result<u64> {
+    let mut encoder = match &options.dictionary {
+        Some(dictionary) => zstd::stream::write::Encoder::with_dictionary(output, options.level, dictionary)?,
+        None => zstd::stream::write::Encoder::new(output, options.level)?,
+    };
+    encoder.include_checksum(true)?;
+    if let Some(window_log) = options.long {
+        encoder.long_distance_matching(true)?;
+        encoder.window_log(window_log)?;
+    }
+    #[cfg(feature = "native")]
+    if options.threads > 1 {
+        encoder.multithread(options.threads)?;
+    }
+    encoder.set_pledged_src_size(size)?;
+
+    let read = io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    Ok(read)
+}
+
+/// Returns the levels the data can be compressed with.
+pub fn levels() -> std::ops::RangeInclusive<i32> {
+    zstd::compression_level_range()
+}
+
+/// The samples a zstd dictionary is trained on, like the logs whose
+/// next files are compressed with it.
+#[derive(Debug, Clone, Default)]
+pub struct Samples {
+    /// The samples one after the other.
+    data: Vec<u8>,
+    /// The size of each sample.
+    sizes: Vec<usize>,
+}
+
+impl Samples {
+    /// Adds the data of a file, as a single sample, or cut in samples
+    /// of sample_size bytes for the large files like logs.
+    pub fn add(&mut self, data: &[u8], sample_size: Option<usize>) {
+        match sample_size {
+            Some(sample_size) if sample_size > 0 => {
+                self.sizes.extend(data.chunks(sample_size).map(<[u8]>::len));
+            }
+            _ if !data.is_empty() => self.sizes.push(data.len()),
+            _ => {}
+        }
+        self.data.extend_from_slice(data);
+    }
+
+    /// Returns the number of samples.
+    pub fn len(&self) -> usize {
+        self.sizes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sizes.is_empty()
+    }
+
+    /// Trains a dictionary of at most max_size bytes on the samples.
+    /// zstd needs many samples, a few thousand small ones or about
+    /// a hundred times the size of the dictionary.
+    pub fn train(&self, max_size: usize) -> io::Result<Vec<u8>> {
+        zstd::dict::from_continuous(&self.data, &self.sizes, max_size)
+    }
+}
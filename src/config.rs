@@ -0,0 +1,289 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use encoding_rs::Encoding;
+
+use crate::cache::BlockCache;
+use crate::format::Format;
+use crate::matcher::{self, CaseMode, MatcherOptions, PatternMatcher};
+use crate::prefilter::LineMatcher;
+use crate::search::{self, BinaryMode, InvalidUtf8, SearchOptions};
+use crate::sink::SinkFactory;
+
+/// What to search for and how, shared by all the files of a search.
+///
+/// The config is made by a [`SearchBuilder`], which checks that its
+/// options go together and compiles the patterns once. Clones are
+/// cheap, every task searching a file gets one.
+#[derive(Debug, Clone)]
+pub struct SearchConfig {
+    matcher: LineMatcher,
+    options: Arc<SearchOptions>,
+}
+
+impl SearchConfig {
+    /// Returns the matcher compiled from the patterns.
+    pub fn matcher(&self) -> &PatternMatcher {
+        self.matcher.regex()
+    }
+
+    /// Returns the matcher that looks for the literals of the
+    /// patterns before running the regex.
+    pub(crate) fn line_matcher(&self) -> &LineMatcher {
+        &self.matcher
+    }
+
+    /// Returns the options each file is searched with.
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
+    }
+}
+
+/// Builds a [`SearchConfig`], for the command line and the library
+/// users alike:
+///
+/// ```no_run
+/// use rzstd::{CaseMode, SearchBuilder};
+///
+/// let config = SearchBuilder::new().pattern("error").case(CaseMode::Smart).context(2, 2).build()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SearchBuilder {
+    /// The patterns, None until some are given. No patterns at all,
+    /// like from an empty pattern file, match no line.
+    patterns: Option<Vec<String>>,
+    matcher: MatcherOptions,
+    options: SearchOptions,
+}
+
+impl SearchBuilder {
+    pub fn new() -> SearchBuilder {
+        SearchBuilder::from_options(SearchOptions {
+            max_expansion: search::DEFAULT_MAX_EXPANSION,
+            ..SearchOptions::default()
+        })
+    }
+
+    /// Starts from all the options of a search, like the ones
+    /// parsed from the command line.
+    pub fn from_options(options: SearchOptions) -> SearchBuilder {
+        SearchBuilder {
+            options,
+            ..SearchBuilder::default()
+        }
+    }
+
+    /// Adds a pattern to search for. A line matches if any of the patterns match.
+    pub fn pattern(mut self, pattern: impl Into<String>) -> SearchBuilder {
+        self.patterns.get_or_insert_with(Vec::new).push(pattern.into());
+        self
+    }
+
+    /// Adds patterns to search for. Giving none, like the lines of
+    /// an empty pattern file, searches for nothing, and no line matches.
+    pub fn patterns<P: Into<String>>(mut self, patterns: impl IntoIterator<Item = P>) -> SearchBuilder {
+        self.patterns.get_or_insert_with(Vec::new).extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets how the patterns are matched.
+    pub fn matcher_options(mut self, options: MatcherOptions) -> SearchBuilder {
+        self.matcher = options;
+        self
+    }
+
+    /// Sets how the case of letters is matched.
+    pub fn case(mut self, case: CaseMode) -> SearchBuilder {
+        self.matcher.case = case;
+        self
+    }
+
+    /// Returns the lines that don't match instead of the ones that do.
+    pub fn invert_match(mut self, invert_match: bool) -> SearchBuilder {
+        self.options.invert_match = invert_match;
+        self
+    }
+
+    /// Stops searching a file after this many matched lines.
+    pub fn max_count(mut self, max_count: u64) -> SearchBuilder {
+        self.options.max_count = Some(max_count);
+        self
+    }
+
+    /// Counts the lines, so that each line comes with its number.
+    pub fn line_number(mut self, line_number: bool) -> SearchBuilder {
+        self.options.line_number = line_number;
+        self
+    }
+
+    /// Sets the number of context lines before and after each matched line.
+    pub fn context(mut self, before: usize, after: usize) -> SearchBuilder {
+        self.options.before_context = before;
+        self.options.after_context = after;
+        self
+    }
+
+    /// Separates the lines by NUL bytes instead of newlines.
+    pub fn null_data(mut self, null_data: bool) -> SearchBuilder {
+        self.options.null_data = null_data;
+        self
+    }
+
+    /// Sets what is printed of the binary data of the files.
+    /// Defaults to [`BinaryMode::Suppress`], like grep.
+    pub fn binary(mut self, binary: BinaryMode) -> SearchBuilder {
+        self.options.binary = binary;
+        self
+    }
+
+    /// Sets what is done with the lines that aren't valid UTF-8.
+    /// Defaults to [`InvalidUtf8::Lossy`], which keeps them.
+    pub fn invalid_utf8(mut self, invalid_utf8: InvalidUtf8) -> SearchBuilder {
+        self.options.invalid_utf8 = invalid_utf8;
+        self
+    }
+
+    /// Transcodes the decoded data of the files from this encoding to
+    /// UTF-8 before searching it, instead of taking it for UTF-8.
+    pub fn encoding(mut self, encoding: &'static Encoding) -> SearchBuilder {
+        self.options.encoding = Some(encoding);
+        self
+    }
+
+    /// Decodes all the files with this format, instead of telling
+    /// it from their first bytes and extension.
+    pub fn format(mut self, format: Format) -> SearchBuilder {
+        self.options.format = Some(format);
+        self
+    }
+
+    /// Decodes the zstd files with this dictionary.
+    pub fn dictionary(mut self, dictionary: Vec<u8>) -> SearchBuilder {
+        self.options.dictionary = Some(Arc::new(dictionary));
+        self
+    }
+
+    /// Decodes the zstd frames with windows of up to 2^window_log
+    /// bytes, instead of the 2^27 bytes zstd accepts by default.
+    pub fn max_window(mut self, window_log: u32) -> SearchBuilder {
+        self.options.max_window = Some(window_log);
+        self
+    }
+
+    /// Sets the number of threads a single file can be searched
+    /// with, like the frames of the seekable zstd files. Defaults
+    /// to the number of CPUs.
+    pub fn threads(mut self, threads: usize) -> SearchBuilder {
+        self.options.threads = Some(threads);
+        self
+    }
+
+    /// Decodes the frames of the zstd files on up to this many
+    /// threads at once. Defaults to decoding them on the thread
+    /// searching the file.
+    pub fn decompress_threads(mut self, threads: usize) -> SearchBuilder {
+        self.options.decompress_threads = threads;
+        self
+    }
+
+    /// Memory maps the files instead of reading them.
+    pub fn mmap(mut self, mmap: bool) -> SearchBuilder {
+        self.options.mmap = mmap;
+        self
+    }
+
+    /// Keeps the decoded data of the compressed files in the directory,
+    /// so that searching them again reads it instead of decoding them.
+    /// The least recently used data is removed once the cache takes
+    /// more than max_size bytes.
+    pub fn cache(mut self, dir: impl Into<PathBuf>, max_size: u64) -> SearchBuilder {
+        self.options.cache = Some(BlockCache::new(dir.into(), max_size));
+        self
+    }
+
+    /// Reads the indexes of the files from the directory, where
+    /// `rzstd index --index-dir` writes them, instead of next to them.
+    pub fn index_dir(mut self, dir: impl Into<PathBuf>) -> SearchBuilder {
+        self.options.index_dir = Some(dir.into());
+        self
+    }
+
+    /// Opens the archives and compressed files found inside
+    /// archives too, up to this many levels deep.
+    pub fn search_archives(mut self, depth: usize) -> SearchBuilder {
+        self.options.search_archives = depth;
+        self
+    }
+
+    /// Sets the most bytes the nested archives of a file can decode to.
+    pub fn max_expansion(mut self, max_expansion: u64) -> SearchBuilder {
+        self.options.max_expansion = max_expansion;
+        self
+    }
+
+    /// Gives the lines of the files to the sinks the factory makes,
+    /// instead of printing them.
+    pub fn sink(mut self, sink: SinkFactory) -> SearchBuilder {
+        self.options.sink = Some(sink);
+        self
+    }
+
+    /// Checks the options and compiles the patterns.
+    pub fn build(self) -> Result<SearchConfig> {
+        let options = SearchOptions {
+            multiline: self.matcher.multiline,
+            ..self.options
+        };
+        let Some(patterns) = self.patterns else {
+            return Err(anyhow::anyhow!("Error building search: no pattern to search for"));
+        };
+        if options.threads == Some(0) {
+            return Err(anyhow::anyhow!("Error building search: the files need at least one thread"));
+        }
+        if options.search_archives > 0 && options.max_expansion == 0 {
+            return Err(anyhow::anyhow!("Error building search: nested archives need a max expansion"));
+        }
+        if options.json && (options.heading || options.replace.is_some() || options.output_dir.is_some()) {
+            let message = "JSON can't be printed with headings, replacements or output files";
+            return Err(anyhow::anyhow!("Error building search: {}", message));
+        }
+
+        let regex = matcher::build_matcher(&patterns, &self.matcher)?;
+        Ok(SearchConfig {
+            matcher: LineMatcher::new(regex, &patterns, &self.matcher),
+            options: Arc::new(options),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINES: &[u8] = b"alpha\nbeta\ngamma\n";
+
+    fn matched_lines(config: &SearchConfig) -> Vec<Vec<u8>> {
+        crate::search(config, LINES).unwrap().map(|found| found.line).collect()
+    }
+
+    #[test]
+    fn no_patterns_match_nothing() {
+        let config = SearchBuilder::new().patterns(Vec::<String>::new()).build().unwrap();
+        assert!(matched_lines(&config).is_empty());
+    }
+
+    #[test]
+    fn no_patterns_inverted_match_every_line() {
+        let config = SearchBuilder::new().patterns(Vec::<String>::new()).invert_match(true).build().unwrap();
+        assert_eq!(matched_lines(&config), [b"alpha".to_vec(), b"beta".to_vec(), b"gamma".to_vec()]);
+    }
+
+    #[test]
+    fn patterns_are_needed() {
+        assert!(SearchBuilder::new().build().is_err());
+        let config = SearchBuilder::new().patterns(Vec::<String>::new()).pattern("eta").build().unwrap();
+        assert_eq!(matched_lines(&config), [b"beta".to_vec()]);
+    }
+}
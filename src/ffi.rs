@@ -0,0 +1,120 @@
+//! The C interface of the library, built with the `ffi` feature.
+//! The declarations for C and C++ are in `include/rzstd.h`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::fs::File;
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::Result;
+
+use crate::config::SearchBuilder;
+use crate::matches;
+
+/// A matched line given to the callback of `rzstd_search`.
+/// The pointers are only valid during the call of the callback.
+#[repr(C)]
+pub struct RzstdMatch {
+    /// The searched file, NUL terminated.
+    pub file: *const c_char,
+    /// The 1-based number of the line.
+    pub line_number: u64,
+    /// The offset of the line in the decompressed data.
+    pub byte_offset: u64,
+    /// The line, without its terminator. It isn't NUL terminated,
+    /// and may contain NUL bytes.
+    pub line: *const c_char,
+    pub line_len: usize,
+}
+
+/// Called with each matched line. Returning anything else than 0
+/// stops the search.
+pub type RzstdCallback = extern "C" fn(found: *const RzstdMatch, user_data: *mut c_void) -> c_int;
+
+thread_local! {
+    /// The error of the last call that failed on the thread.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Searches a file, compressed in any of the supported formats or not,
+/// for the regex pattern, and calls the callback with each matched line.
+/// Returns the number of lines given to the callback, or -1 if the
+/// search failed, see `rzstd_last_error`.
+///
+/// # Safety
+///
+/// The path and the pattern must be valid NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn rzstd_search(
+    path: *const c_char,
+    pattern: *const c_char,
+    callback: RzstdCallback,
+    user_data: *mut c_void,
+) -> i64 {
+    if path.is_null() || pattern.is_null() {
+        set_last_error("Error searching: the path and the pattern can't be null".to_string());
+        return -1;
+    }
+    let path = CStr::from_ptr(path);
+    let pattern = CStr::from_ptr(pattern);
+
+    // Panics must not unwind into the C code
+    let searched = panic::catch_unwind(AssertUnwindSafe(|| search(path, pattern, callback, user_data)));
+    match searched {
+        Ok(Ok(count)) => count as i64,
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            -1
+        }
+        Err(_) => {
+            set_last_error("Error searching: the search panicked".to_string());
+            -1
+        }
+    }
+}
+
+/// Returns the error of the last call of `rzstd_search` that failed
+/// on the calling thread, or NULL if none did. The string is valid
+/// until the next call that fails on the thread.
+#[no_mangle]
+pub extern "C" fn rzstd_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| match &*error.borrow() {
+        Some(error) => error.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
+
+fn search(path: &CStr, pattern: &CStr, callback: RzstdCallback, user_data: *mut c_void) -> Result<u64> {
+    let file_path = path.to_string_lossy();
+    let config = SearchBuilder::new().pattern(pattern.to_string_lossy()).build()?;
+    let file = match File::open(&*file_path) {
+        Ok(file) => file,
+        Err(e) => return Err(anyhow::anyhow!("Error opening file {}: {}", file_path, e)),
+    };
+
+    let mut matches = matches::search_input(&config, file, Some(&file_path))?;
+    let mut count = 0;
+    for found in matches.by_ref() {
+        let found = RzstdMatch {
+            file: path.as_ptr(),
+            line_number: found.line_number,
+            byte_offset: found.byte_offset,
+            line: found.line.as_ptr().cast(),
+            line_len: found.line.len(),
+        };
+        count += 1;
+        if callback(&found, user_data) != 0 {
+            return Ok(count);
+        }
+    }
+    match matches.error() {
+        Some(e) => Err(anyhow::anyhow!("Error searching file {}: {}", file_path, e)),
+        None => Ok(count),
+    }
+}
+
+fn set_last_error(message: String) {
+    // The messages never contain NUL bytes, except from the paths
+    let message = CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+}
@@ -0,0 +1,330 @@
+use std::io::{self, BufRead, BufReader, Chain, Cursor, Read};
+use std::path::Path;
+
+use brotli::Decompressor as BrotliDecoder;
+use bzip2::read::MultiBzDecoder;
+use clap::ValueEnum;
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+use lz4_flex::frame::FrameDecoder as Lz4Decoder;
+use snap::read::FrameDecoder as SnappyDecoder;
+use xz2::read::XzDecoder;
+use xz2::stream::Stream;
+
+use crate::archive;
+
+/// A compression format that rzstd can search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Format {
+    Zstd,
+    Gzip,
+    Bzip2,
+    Xz,
+    /// The legacy format of LZMA Utils, before xz.
+    Lzma,
+    /// The LZ4 frame format, not the raw LZ4 blocks.
+    Lz4,
+    Brotli,
+    /// The Snappy framing format, not the raw Snappy blocks.
+    Snappy,
+    /// A zlib stream, without the gzip header.
+    Zlib,
+    /// A raw deflate stream, without any header.
+    Deflate,
+    /// Data that isn't compressed, searched as it is.
+    #[value(name = "none")]
+    Uncompressed,
+}
+
+/// The size of the buffer brotli reads the compressed data into.
+const BROTLI_BUFFER_SIZE: usize = 64 * 1024;
+
+/// How the zstd frames are decoded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZstdParams<'a> {
+    /// The dictionary the frames were compressed with.
+    pub dictionary: Option<&'a [u8]>,
+    /// The largest window the frames can use, 2^window_log_max bytes,
+    /// which bounds the memory of each decoder. zstd accepts windows
+    /// of up to 2^27 bytes without it, the ones of `zstd --long`.
+    pub window_log_max: Option<u32>,
+}
+
+/// Returns the zstd decoder of the frames of the input.
+pub(crate) fn zstd_decoder<R: BufRead>(input: R, params: ZstdParams<'_>) -> io::Result<zstd::stream::read::Decoder<'static, R>> {
+    let mut decoder = match params.dictionary {
+        Some(dictionary) => zstd::stream::read::Decoder::with_dictionary(input, dictionary)?,
+        None => zstd::stream::read::Decoder::with_buffer(input)?,
+    };
+    if let Some(window_log_max) = params.window_log_max {
+        decoder.window_log_max(window_log_max)?;
+    }
+    Ok(decoder)
+}
+
+impl Format {
+    /// All the supported formats.
+    pub const ALL: &'static [Format] = &[
+        Format::Zstd,
+        Format::Gzip,
+        Format::Bzip2,
+        Format::Xz,
+        Format::Lzma,
+        Format::Lz4,
+        Format::Brotli,
+        Format::Snappy,
+        Format::Zlib,
+        Format::Deflate,
+        Format::Uncompressed,
+    ];
+
+    /// Returns the file extensions of the format, without the dot.
+    /// Bare zlib and deflate streams have no extension of their own,
+    /// they are only searched with --format.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Format::Zstd => &["zst", "zstd"],
+            Format::Gzip => &["gz"],
+            Format::Bzip2 => &["bz2"],
+            Format::Xz => &["xz"],
+            Format::Lzma => &["lzma"],
+            Format::Lz4 => &["lz4"],
+            Format::Brotli => &["br"],
+            Format::Snappy => &["sz"],
+            Format::Zlib | Format::Deflate | Format::Uncompressed => &[],
+        }
+    }
+
+    /// Returns the format of a file from its extension,
+    /// or None if the extension isn't the one of a format.
+    pub fn from_path(file_path: &str) -> Option<Format> {
+        let extension = Path::new(file_path).extension().and_then(|extension| extension.to_str())?;
+        Format::ALL.iter().copied().find(|format| format.extensions().contains(&extension))
+    }
+
+    /// Returns true if the files of the format always start with
+    /// magic bytes, so that a file without them isn't of the format
+    /// whatever its extension.
+    pub fn has_magic(self) -> bool {
+        !matches!(self, Format::Lzma | Format::Brotli | Format::Zlib | Format::Deflate | Format::Uncompressed)
+    }
+
+    /// Returns the format whose magic bytes start the data,
+    /// or None if the data doesn't start like any format.
+    /// Brotli, lzma, zlib and deflate have no reliable magic
+    /// bytes, they are only told by their extension or --format.
+    pub fn from_magic(prefix: &[u8]) -> Option<Format> {
+        let format = match prefix {
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Format::Zstd,
+            // Skippable frames, which can start a zstd file
+            [0x50..=0x5f, 0x2a, 0x4d, 0x18, ..] => Format::Zstd,
+            [0x1f, 0x8b, ..] => Format::Gzip,
+            [b'B', b'Z', b'h', ..] => Format::Bzip2,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Format::Xz,
+            [0x04, 0x22, 0x4d, 0x18, ..] => Format::Lz4,
+            [0xff, 0x06, 0x00, 0x00, b's', b'N', b'a', b'P', b'p', b'Y', ..] => Format::Snappy,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Returns the format of a file from the one of its magic bytes,
+    /// if any, or else from its extension. A file without the magic
+    /// bytes of the format of its extension isn't of that format, like
+    /// a text file named .zst. Files that don't look compressed at all
+    /// are searched as they are.
+    pub fn resolve(detected: Option<Format>, file_path: &str) -> Format {
+        detected.unwrap_or_else(|| {
+            Format::from_path(file_path)
+                .filter(|format| !format.has_magic())
+                .unwrap_or(Format::Uncompressed)
+        })
+    }
+
+    /// Wraps the compressed input in the decoder of the format,
+    /// which streams the decompressed data.
+    ///
+    /// Files made of several frames or members one after the other,
+    /// like the ones of `cat a.gz b.gz` or pzstd, are decoded until
+    /// the last one. Brotli, lzma, zlib and deflate streams can't be
+    /// concatenated, they end with their first stream.
+    ///
+    /// The zstd params are only used by zstd, the other formats
+    /// don't have any dictionary or window limit.
+    pub fn decoder<'a, R: Read + 'a>(self, input: R, zstd: ZstdParams<'_>) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            Format::Zstd => Ok(Box::new(zstd_decoder(BufReader::new(input), zstd)?)),
+            Format::Gzip => Ok(Box::new(MultiGzDecoder::new(input))),
+            Format::Bzip2 => Ok(Box::new(MultiBzDecoder::new(input))),
+            Format::Xz => Ok(Box::new(XzDecoder::new_multi_decoder(input))),
+            Format::Lzma => {
+                // No memory limit, like the xz command line tool by default
+                let stream = Stream::new_lzma_decoder(u64::MAX)?;
+                Ok(Box::new(XzDecoder::new_stream(input, stream)))
+            }
+            Format::Lz4 => Ok(Box::new(Lz4Frames(Lz4Decoder::new(BufReader::new(input))))),
+            Format::Brotli => Ok(Box::new(BrotliDecoder::new(input, BROTLI_BUFFER_SIZE))),
+            Format::Snappy => Ok(Box::new(SnappyDecoder::new(input))),
+            Format::Zlib => Ok(Box::new(ZlibDecoder::new(input))),
+            Format::Deflate => Ok(Box::new(DeflateDecoder::new(input))),
+            Format::Uncompressed => Ok(Box::new(input)),
+        }
+    }
+}
+
+/// Returns the extensions of all the supported formats and archives,
+/// which are the files searched in directories by default.
+pub fn default_extensions() -> Vec<String> {
+    Format::ALL
+        .iter()
+        .flat_map(|format| format.extensions())
+        .chain(archive::EXTENSIONS)
+        .map(|extension| extension.to_string())
+        .collect()
+}
+
+/// Decodes the LZ4 frames one after the other. The LZ4 decoder
+/// returns the end of the data at the end of each frame, and only
+/// starts the next frame when read again. The frames without any
+/// data are skipped until the input itself ends.
+struct Lz4Frames<R: Read>(Lz4Decoder<BufReader<R>>);
+
+impl<R: Read> Read for Lz4Frames<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.0.read(buf)? {
+                // Either the input ended too, or the next frame starts
+                0 if self.0.get_mut().fill_buf()?.is_empty() => return Ok(0),
+                0 => {}
+                read => return Ok(read),
+            }
+        }
+    }
+}
+
+/// The number of bytes read to find the magic bytes of a format.
+pub const MAGIC_LEN: u64 = 10;
+
+/// Detects the format of the input from its first bytes. Returns
+/// the format, if the magic bytes are the ones of a format, and
+/// the input to decode, which still starts with the read bytes.
+pub fn detect<R: Read>(input: R) -> io::Result<(Option<Format>, impl Read)> {
+    let (prefix, input) = peek(input, MAGIC_LEN)?;
+    if prefix.starts_with(archive::ZIP_MAGIC) {
+        // Zip archives need their central directory, at their end
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "zip archives can only be searched in files"));
+    }
+    if prefix.starts_with(archive::SEVEN_Z_MAGIC) {
+        // 7z archives need their header, at their end
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "7z archives can only be searched in files"));
+    }
+
+    Ok((Format::from_magic(&prefix), input))
+}
+
+/// An input whose first bytes were already read.
+pub type Peeked<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// Reads the first bytes of the input, at most len of them.
+/// Returns them and the input, which still starts with them.
+pub fn peek<R: Read>(mut input: R, len: u64) -> io::Result<(Vec<u8>, Peeked<R>)> {
+    let mut prefix = Vec::new();
+    input.by_ref().take(len).read_to_end(&mut prefix)?;
+    Ok((prefix.clone(), Cursor::new(prefix).chain(input)))
+}
+
+/// Gets the zstd frames decoded one by one by [`decode_zstd_frames`].
+pub(crate) trait FrameVisitor {
+    /// Starts a frame, at the offset in the compressed data.
+    fn frame(&mut self, compressed_offset: u64);
+    /// Gets decoded data of the last frame.
+    fn data(&mut self, data: &[u8]);
+}
+
+/// Decodes the zstd frames of the input one after the other, for what
+/// needs to know where each one starts, like the indexes. The skippable
+/// frames are frames without data.
+pub(crate) fn decode_zstd_frames<R: Read, V: FrameVisitor>(
+    input: R,
+    zstd: ZstdParams<'_>,
+    visitor: &mut V,
+) -> io::Result<()> {
+    let mut input = ConsumedReader {
+        inner: BufReader::new(input),
+        consumed: 0,
+    };
+    let mut buf = vec![0; 64 << 10];
+    while !input.fill_buf()?.is_empty() {
+        visitor.frame(input.consumed);
+        let mut decoder = zstd_decoder(&mut input, zstd)?.single_frame();
+        loop {
+            let read = decoder.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            visitor.data(&buf[..read]);
+        }
+    }
+    Ok(())
+}
+
+/// Counts the bytes consumed from a reader, for the offsets of the
+/// frames left to the decoder.
+struct ConsumedReader<R> {
+    inner: R,
+    consumed: u64,
+}
+
+impl<R: BufRead> Read for ConsumedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.consumed += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for ConsumedReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed += amount as u64;
+        self.inner.consume(amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn lz4_frame(data: &[u8]) -> Vec<u8> {
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn lz4_frames_after_an_empty_frame() {
+        let empty = lz4_frame(b"");
+        assert!(!empty.is_empty());
+        let data = [lz4_frame(b"first\n"), empty.clone(), empty, lz4_frame(b"second\n")].concat();
+        assert_eq!(Format::from_magic(&data), Some(Format::Lz4));
+
+        let mut decoded = Vec::new();
+        Format::Lz4.decoder(&data[..], ZstdParams::default()).unwrap().read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, b"first\nsecond\n");
+    }
+
+    #[test]
+    fn lz4_stream_ending_with_an_empty_frame() {
+        let data = [lz4_frame(b"only\n"), lz4_frame(b"")].concat();
+        let mut decoded = Vec::new();
+        Format::Lz4.decoder(&data[..], ZstdParams::default()).unwrap().read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, b"only\n");
+    }
+}
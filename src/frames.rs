@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::headers::{self, BlockHeader, FrameHeader, CHECKSUM_LEN, ZSTD_MAGIC};
+
+/// The most block headers read to add up the content sizes of the
+/// frames, so that only a little of a large file is read before it
+/// is searched.
+const MAX_BLOCKS: usize = 1024;
+
+/// Returns the decoded size of a zstd file, the sum of the content
+/// sizes in the headers of its frames, by following the frames from
+/// their headers without decoding anything. Returns None for the
+/// files that aren't zstd, as soon as a frame doesn't tell its
+/// content size, or when following the frames would read too many
+/// block headers, as the progress then shows the compressed data.
+/// A first frame with too many blocks to follow is taken for the
+/// only frame of the file, like the ones the zstd tool writes.
+/// The file is rewound to its start afterwards.
+pub fn content_size(mut file: &File) -> io::Result<Option<u64>> {
+    let size = sum_content_sizes(file);
+    file.rewind()?;
+    size
+}
+
+fn sum_content_sizes(mut input: &File) -> io::Result<Option<u64>> {
+    let mut size = 0;
+    let mut frames = 0;
+    let mut blocks = 0;
+    loop {
+        let mut magic = [0; 4];
+        match input.read_exact(&mut magic) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let magic = u32::from_le_bytes(magic);
+
+        if headers::is_skippable(magic) {
+            let skipped = u32::from_le_bytes(read_array(&mut input)?);
+            input.seek(SeekFrom::Current(i64::from(skipped)))?;
+            continue;
+        }
+        if magic != ZSTD_MAGIC {
+            return Ok(None);
+        }
+        let [descriptor] = read_array(&mut input)?;
+        let header = FrameHeader::new(descriptor);
+        let mut fields = vec![0; header.fields_len()];
+        input.read_exact(&mut fields)?;
+        let Some(frame_size) = header.content_size(&fields) else {
+            return Ok(None);
+        };
+        size += frame_size;
+        if !skip_blocks(&mut input, header, &mut blocks)? {
+            return Ok(Some(size).filter(|_| frames == 0));
+        }
+        frames += 1;
+    }
+    Ok(Some(size).filter(|_| frames > 0))
+}
+
+/// Skips the blocks of a zstd frame after its header, and its checksum.
+/// Returns false once more than [`MAX_BLOCKS`] block headers are read.
+fn skip_blocks(input: &mut &File, header: FrameHeader, blocks: &mut usize) -> io::Result<bool> {
+    loop {
+        *blocks += 1;
+        if *blocks > MAX_BLOCKS {
+            return Ok(false);
+        }
+        let block = BlockHeader::new(read_array(input)?);
+        input.seek(SeekFrom::Current(block.content_len as i64))?;
+        if block.last {
+            break;
+        }
+    }
+    if header.checksum() {
+        input.seek(SeekFrom::Current(CHECKSUM_LEN as i64))?;
+    }
+    Ok(true)
+}
+
+fn read_array<const N: usize, R: Read>(input: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,175 @@
+/// The magic numbers of skippable frames, whose last 4 bits are free.
+const SKIPPABLE_MAGIC_MASK: u32 = 0xffff_fff0;
+const SKIPPABLE_MAGIC: u32 = 0x184d_2a50;
+/// The magic number of the zstd frames.
+pub const ZSTD_MAGIC: u32 = 0xfd2f_b528;
+
+/// The size of the header of a block.
+pub const BLOCK_HEADER_LEN: usize = 3;
+/// The size of the checksum that ends the frames whose header has one.
+pub const CHECKSUM_LEN: usize = 4;
+
+/// The block type of the blocks made of a single repeated byte.
+const RLE_BLOCK: u8 = 1;
+
+/// Returns true if the magic number is the one of a skippable frame.
+pub fn is_skippable(magic: u32) -> bool {
+    magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC
+}
+
+/// The header of a zstd frame, as told by its descriptor, the byte
+/// after its magic number. See RFC 8878.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    descriptor: u8,
+}
+
+impl FrameHeader {
+    pub fn new(descriptor: u8) -> FrameHeader {
+        FrameHeader { descriptor }
+    }
+
+    fn single_segment(self) -> bool {
+        self.descriptor & 0x20 != 0
+    }
+
+    /// Returns true if the frame ends with a checksum of its content.
+    pub fn checksum(self) -> bool {
+        self.descriptor & 0x04 != 0
+    }
+
+    /// Returns the size of the fields of the header after the
+    /// descriptor: the window, the dictionary ID and the content size.
+    pub fn fields_len(self) -> usize {
+        let window_len = if self.single_segment() { 0 } else { 1 };
+        let dictionary_len = [0, 1, 2, 4][usize::from(self.descriptor & 0x03)];
+        window_len + dictionary_len + self.content_size_len()
+    }
+
+    fn content_size_len(self) -> usize {
+        match self.descriptor >> 6 {
+            0 if self.single_segment() => 1,
+            0 => 0,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        }
+    }
+
+    /// Returns the content size at the end of the fields of the
+    /// header, if the header has one.
+    pub fn content_size(self, fields: &[u8]) -> Option<u64> {
+        let field = &fields[fields.len() - self.content_size_len()..];
+        match *field {
+            [] => None,
+            [a] => Some(u64::from(a)),
+            // The 2 bytes sizes start at 256
+            [a, b] => Some(u64::from(u16::from_le_bytes([a, b])) + 256),
+            [a, b, c, d] => Some(u64::from(u32::from_le_bytes([a, b, c, d]))),
+            _ => Some(u64::from_le_bytes(field.try_into().ok()?)),
+        }
+    }
+}
+
+/// The header of a block of a zstd frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    /// True for the last block of the frame.
+    pub last: bool,
+    /// The size of the block after its header.
+    pub content_len: usize,
+}
+
+impl BlockHeader {
+    pub fn new(header: [u8; BLOCK_HEADER_LEN]) -> BlockHeader {
+        let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+        let block_type = ((header >> 1) & 0x03) as u8;
+        BlockHeader {
+            last: header & 1 != 0,
+            content_len: if block_type == RLE_BLOCK { 1 } else { (header >> 3) as usize },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_without_content_size() {
+        // The window descriptor alone
+        let header = FrameHeader::new(0x00);
+        assert_eq!(header.fields_len(), 1);
+        assert_eq!(header.content_size(&[0x58]), None);
+    }
+
+    #[test]
+    fn single_segment_content_size() {
+        // No window descriptor, and a 1 byte content size
+        let header = FrameHeader::new(0x20);
+        assert_eq!(header.fields_len(), 1);
+        assert_eq!(header.content_size(&[42]), Some(42));
+    }
+
+    #[test]
+    fn dictionary_id_sizes() {
+        for (flag, len) in [(0, 0), (1, 1), (2, 2), (3, 4)] {
+            assert_eq!(FrameHeader::new(flag).fields_len(), 1 + len);
+            assert_eq!(FrameHeader::new(0x20 | flag).fields_len(), len + 1);
+        }
+    }
+
+    #[test]
+    fn content_size_lengths() {
+        let header = FrameHeader::new(0x40);
+        assert_eq!(header.fields_len(), 3);
+        assert_eq!(header.content_size(&[0, 0, 0]), Some(256));
+        assert_eq!(header.content_size(&[0, 0xff, 0xff]), Some(0xffff + 256));
+
+        let header = FrameHeader::new(0x80);
+        assert_eq!(header.fields_len(), 5);
+        assert_eq!(header.content_size(&[0, 1, 2, 3, 4]), Some(0x0403_0201));
+
+        let header = FrameHeader::new(0xc0);
+        assert_eq!(header.fields_len(), 9);
+        assert_eq!(header.content_size(&[0, 1, 0, 0, 0, 1, 0, 0, 0]), Some(0x0000_0001_0000_0001));
+    }
+
+    #[test]
+    fn content_size_after_dictionary_id() {
+        // Single segment, a 4 bytes dictionary ID and an 8 bytes content size
+        let header = FrameHeader::new(0xe3);
+        assert_eq!(header.fields_len(), 12);
+        let fields = [0xff, 0xff, 0xff, 0xff, 7, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(header.content_size(&fields), Some(7));
+    }
+
+    #[test]
+    fn headers_written_by_zstd() {
+        for len in [0, 10, 255, 256, 300, 65_791, 65_792, 100_000] {
+            let frame = zstd::bulk::compress(&vec![b'a'; len], 3).unwrap();
+            assert_eq!(u32::from_le_bytes(frame[..4].try_into().unwrap()), ZSTD_MAGIC);
+            let header = FrameHeader::new(frame[4]);
+            let fields = &frame[5..5 + header.fields_len()];
+            assert_eq!(header.content_size(fields), Some(len as u64), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn block_headers() {
+        // A last raw block of 5 bytes
+        assert_eq!(BlockHeader::new([0x29, 0, 0]), BlockHeader { last: true, content_len: 5 });
+        // An RLE block of 1000 bytes is a single byte after its header
+        let header = (1000 << 3) | (u32::from(RLE_BLOCK) << 1);
+        let bytes = header.to_le_bytes();
+        assert_eq!(BlockHeader::new([bytes[0], bytes[1], bytes[2]]), BlockHeader { last: false, content_len: 1 });
+    }
+
+    #[test]
+    fn skippable_magic_numbers() {
+        assert!(is_skippable(0x184d_2a50));
+        assert!(is_skippable(0x184d_2a5f));
+        assert!(!is_skippable(ZSTD_MAGIC));
+        assert!(!is_skippable(0x184d_2a60));
+    }
+}
@@ -0,0 +1,598 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::format::{self, Format, FrameVisitor, ZstdParams};
+
+#[cfg(feature = "native")]
+use crate::search::LineStart;
+#[cfg(feature = "native")]
+use crate::seekable::Chunk;
+
+/// The extension of the index of a file, added to its name.
+pub const INDEX_EXTENSION: &str = "rzi";
+/// The first bytes of the indexes, with the version of their format.
+const INDEX_MAGIC: &[u8; 4] = b"RZI1";
+/// The most bytes of filter a frame gets from `rzstd index`, by default.
+pub const DEFAULT_BLOOM_SIZE: u64 = 64 << 10;
+/// The fewest bytes of filter a frame gets, however small it is.
+const MIN_BLOOM_SIZE: usize = 64;
+/// A frame gets a byte of filter for this many bytes of data,
+/// as the lines repeat most of their strings.
+const BYTES_PER_BLOOM_BYTE: u64 = 8;
+/// The number of bits each string sets in the filters of `rzstd index`.
+const BLOOM_HASHES: u8 = 4;
+
+/// The index of a zstd file made of several frames, kept next to it
+/// in a `.rzi` file, so that the searches only decode the frames
+/// that can have matches.
+///
+/// It lists the frames of the file, where the first line of each one
+/// starts and its number, and optionally a Bloom filter per frame of
+/// the 3 bytes long strings of its lines. A frame whose filter has
+/// none of the literals of the patterns is skipped.
+///
+/// The index is made of little-endian numbers: the magic `RZI1`, the
+/// size and modification time in nanoseconds of the file, the line
+/// terminator, the size of the filters and their number of hashes,
+/// the number of frames, the decompressed size of the file, and then
+/// each frame: its compressed offset, decompressed offset, the offset
+/// and number of its first line, and its filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    /// The size of the indexed file, to know that it didn't change.
+    pub file_len: u64,
+    /// The modification time of the file, in nanoseconds since the epoch.
+    pub file_modified: u64,
+    /// The byte that ends the lines of the file.
+    pub terminator: u8,
+    /// The number of bits each string sets in the filters.
+    pub bloom_hashes: u8,
+    /// The decompressed size of the file.
+    pub decompressed_len: u64,
+    pub frames: Vec<IndexFrame>,
+}
+
+/// A frame of an indexed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexFrame {
+    /// Where the frame starts in the compressed file.
+    pub compressed_offset: u64,
+    /// Where the frame starts in the decompressed data.
+    pub decompressed_offset: u64,
+    /// Where the first line that starts in the frame or after it starts.
+    pub first_line_offset: u64,
+    /// The 1-based number of that line.
+    pub first_line_number: u64,
+    /// The filter of the strings of the lines that start in the
+    /// frame, empty when the index has none.
+    pub bloom: Bloom,
+}
+
+impl Index {
+    /// Returns true if the index is the one of the file as it is now.
+    pub fn is_current(&self, metadata: &Metadata) -> bool {
+        self.file_len == metadata.len() && self.file_modified == modified_nanos(metadata)
+    }
+
+    /// Returns the decompressed size of a frame.
+    pub fn frame_size(&self, frame: usize) -> u64 {
+        let end = self
+            .frames
+            .get(frame + 1)
+            .map_or(self.decompressed_len, |next| next.decompressed_offset);
+        end - self.frames[frame].decompressed_offset
+    }
+
+    /// Returns true if a line that starts in the frame can contain
+    /// one of the literals, or if there are no literals or filters.
+    pub fn may_contain(&self, frame: usize, literals: Option<&[Vec<u8>]>) -> bool {
+        let bloom = &self.frames[frame].bloom;
+        match literals {
+            Some(literals) if !bloom.is_empty() => {
+                literals.iter().any(|literal| bloom.may_contain(literal, self.bloom_hashes))
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns the chunks of consecutive frames that can contain one
+    /// of the literals. The chunks have at most per_chunk frames, so
+    /// that they are searched in parallel.
+    #[cfg(feature = "native")]
+    pub(crate) fn chunks(&self, literals: Option<&[Vec<u8>]>, per_chunk: usize) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        let mut frame = 0;
+        while frame < self.frames.len() {
+            if !self.may_contain(frame, literals) {
+                frame += 1;
+                continue;
+            }
+            let first = frame;
+            while frame < self.frames.len() && frame - first < per_chunk.max(1) && self.may_contain(frame, literals) {
+                frame += 1;
+            }
+
+            // Like the chunks of the seekable files, decoding starts
+            // at the frame before, for the line that starts in it
+            let previous = first.checked_sub(1);
+            let start = &self.frames[first];
+            chunks.push(Chunk::new(
+                self.frames[previous.unwrap_or(first)].compressed_offset,
+                previous.map_or(0, |previous| self.frame_size(previous)),
+                (first..frame).map(|frame| self.frame_size(frame)).sum(),
+                LineStart {
+                    lines_before: start.first_line_number - 1,
+                    byte_offset: start.first_line_offset,
+                },
+            ));
+        }
+        chunks
+    }
+
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Index> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(invalid("not an rzstd index"));
+        }
+        let file_len = read_u64(input)?;
+        let file_modified = read_u64(input)?;
+        let terminator = read_u8(input)?;
+        let bloom_len = read_u32(input)? as usize;
+        let bloom_hashes = read_u8(input)?;
+        let frame_count = read_u32(input)?;
+        let decompressed_len = read_u64(input)?;
+
+        let mut frames = Vec::new();
+        for _ in 0..frame_count {
+            let compressed_offset = read_u64(input)?;
+            let decompressed_offset = read_u64(input)?;
+            let first_line_offset = read_u64(input)?;
+            let first_line_number = read_u64(input)?;
+            let mut bits = vec![0; bloom_len];
+            input.read_exact(&mut bits)?;
+            let previous = frames.last().map_or(0, |previous: &IndexFrame| previous.decompressed_offset);
+            if decompressed_offset < previous || decompressed_offset > decompressed_len || first_line_number == 0 {
+                return Err(invalid("the frames of the index are corrupted"));
+            }
+            frames.push(IndexFrame {
+                compressed_offset,
+                decompressed_offset,
+                first_line_offset,
+                first_line_number,
+                bloom: Bloom { bits },
+            });
+        }
+        Ok(Index {
+            file_len,
+            file_modified,
+            terminator,
+            bloom_hashes,
+            decompressed_len,
+            frames,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let bloom_len = self.frames.first().map_or(0, |frame| frame.bloom.bits.len());
+        output.write_all(INDEX_MAGIC)?;
+        output.write_all(&self.file_len.to_le_bytes())?;
+        output.write_all(&self.file_modified.to_le_bytes())?;
+        output.write_all(&[self.terminator])?;
+        output.write_all(&(bloom_len as u32).to_le_bytes())?;
+        output.write_all(&[self.bloom_hashes])?;
+        output.write_all(&(self.frames.len() as u32).to_le_bytes())?;
+        output.write_all(&self.decompressed_len.to_le_bytes())?;
+        for frame in &self.frames {
+            output.write_all(&frame.compressed_offset.to_le_bytes())?;
+            output.write_all(&frame.decompressed_offset.to_le_bytes())?;
+            output.write_all(&frame.first_line_offset.to_le_bytes())?;
+            output.write_all(&frame.first_line_number.to_le_bytes())?;
+            output.write_all(&frame.bloom.bits)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index to a file, and the directories it is in.
+    /// It is written to a temporary file first, so that the searches
+    /// reading it at the same time never see half of it.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(format!(".{}", std::process::id()));
+        let result = File::create(&temporary).and_then(|file| {
+            let mut output = BufWriter::new(file);
+            self.write_to(&mut output)?;
+            output.flush()
+        });
+        match result {
+            Ok(()) => fs::rename(&temporary, path),
+            Err(e) => {
+                let _ = fs::remove_file(&temporary);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Builds the index of a zstd file by decoding it once, with filters
+/// of up to max_bloom_size bytes per frame, or without filters for 0.
+/// The frames get filters as large as their data needs, up to that
+/// size, and the smaller ones are then repeated to the largest size,
+/// which keeps the same bits set for every string.
+pub fn build_index(
+    file_path: &str,
+    terminator: u8,
+    max_bloom_size: u64,
+    zstd: ZstdParams<'_>,
+) -> io::Result<Index> {
+    let file = File::open(file_path)?;
+    let metadata = file.metadata()?;
+    let (prefix, input) = format::peek(file, format::MAGIC_LEN)?;
+    if Format::from_magic(&prefix) != Some(Format::Zstd) {
+        return Err(invalid("not a zstd file"));
+    }
+
+    // The filters are folded in halves, so their sizes are powers of 2
+    let max_bloom_size = usize::try_from(max_bloom_size).unwrap_or(usize::MAX);
+    let max_bloom_size = max_bloom_size.checked_ilog2().map_or(0, |log| 1 << log);
+    let mut builder = IndexBuilder {
+        index: Index {
+            file_len: metadata.len(),
+            file_modified: modified_nanos(&metadata),
+            terminator,
+            bloom_hashes: BLOOM_HASHES,
+            decompressed_len: 0,
+            frames: Vec::new(),
+        },
+        max_bloom_size,
+        lines: 0,
+        without_line: 0,
+        folded: 0,
+        line_frame: None,
+        tail: Vec::new(),
+    };
+    format::decode_zstd_frames(input, zstd, &mut builder)?;
+    Ok(builder.finish())
+}
+
+/// Follows the lines of the decoded data of a file, frame by frame,
+/// for [`build_index`].
+struct IndexBuilder {
+    index: Index,
+    max_bloom_size: usize,
+    /// The number of lines that ended.
+    lines: u64,
+    /// The first of the frames in which no line started yet.
+    without_line: usize,
+    /// The number of frames whose filters are folded to their size.
+    folded: usize,
+    /// The frame in which the current line started, or None
+    /// when the next byte starts a line.
+    line_frame: Option<usize>,
+    /// The last 2 bytes of the current line, for the strings spread
+    /// over two pieces of data.
+    tail: Vec<u8>,
+}
+
+impl FrameVisitor for IndexBuilder {
+    fn frame(&mut self, compressed_offset: u64) {
+        self.index.frames.push(IndexFrame {
+            compressed_offset,
+            decompressed_offset: self.index.decompressed_len,
+            first_line_offset: 0,
+            first_line_number: 0,
+            bloom: Bloom::new(self.max_bloom_size),
+        });
+    }
+
+    /// Adds decoded data of the last frame.
+    fn data(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let frame = match self.line_frame {
+                Some(frame) => frame,
+                None => self.start_line(),
+            };
+            let terminator = data.iter().position(|&byte| byte == self.index.terminator);
+            let (line, rest) = data.split_at(terminator.map_or(data.len(), |terminator| terminator + 1));
+            self.insert(frame, line);
+            self.index.decompressed_len += line.len() as u64;
+            if terminator.is_some() {
+                self.lines += 1;
+                self.line_frame = None;
+            }
+            data = rest;
+        }
+    }
+}
+
+impl IndexBuilder {
+    /// Starts a line in the last frame, and returns that frame.
+    fn start_line(&mut self) -> usize {
+        let frame = self.index.frames.len() - 1;
+        for without_line in &mut self.index.frames[self.without_line..] {
+            without_line.first_line_offset = self.index.decompressed_len;
+            without_line.first_line_number = self.lines + 1;
+        }
+        self.without_line = self.index.frames.len();
+        // The lines of the frames before are all added
+        self.fold(frame);
+        self.line_frame = Some(frame);
+        self.tail.clear();
+        frame
+    }
+
+    /// Adds a piece of a line to the filter of the frame it started in.
+    fn insert(&mut self, frame: usize, piece: &[u8]) {
+        let bloom = &mut self.index.frames[frame].bloom;
+        if !self.tail.is_empty() {
+            let mut spread = self.tail.clone();
+            spread.extend_from_slice(&piece[..piece.len().min(2)]);
+            bloom.insert(&spread, self.index.bloom_hashes);
+        }
+        bloom.insert(piece, self.index.bloom_hashes);
+        self.tail.extend_from_slice(&piece[piece.len().saturating_sub(2)..]);
+        self.tail.drain(..self.tail.len().saturating_sub(2));
+    }
+
+    /// Folds the filters of the frames before the given one to the
+    /// size of their data.
+    fn fold(&mut self, until: usize) {
+        for frame in self.folded..until {
+            let size = self.index.frame_size(frame);
+            let bloom_size = usize::try_from(size / BYTES_PER_BLOOM_BYTE)
+                .unwrap_or(usize::MAX)
+                .checked_next_power_of_two()
+                .unwrap_or(usize::MAX)
+                .clamp(MIN_BLOOM_SIZE, self.max_bloom_size.max(MIN_BLOOM_SIZE));
+            self.index.frames[frame].bloom.fold(bloom_size);
+        }
+        self.folded = self.folded.max(until);
+    }
+
+    fn finish(mut self) -> Index {
+        let lines = self.lines + u64::from(self.line_frame.is_some());
+        for without_line in &mut self.index.frames[self.without_line..] {
+            without_line.first_line_offset = self.index.decompressed_len;
+            without_line.first_line_number = lines + 1;
+        }
+        self.fold(self.index.frames.len());
+        let bloom_size = self.index.frames.iter().map(|frame| frame.bloom.bits.len()).max().unwrap_or(0);
+        for frame in &mut self.index.frames {
+            frame.bloom.repeat(bloom_size);
+        }
+        self.index
+    }
+}
+
+/// A Bloom filter of the 3 bytes long strings of some data.
+/// A literal can only be in the data if all of its strings are
+/// in the filter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bloom {
+    bits: Vec<u8>,
+}
+
+impl Bloom {
+    /// Returns an empty filter of len bytes.
+    pub fn new(len: usize) -> Bloom {
+        Bloom { bits: vec![0; len] }
+    }
+
+    /// Returns true for the filters of the indexes without filters.
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    /// Adds the strings of the data to the filter.
+    pub fn insert(&mut self, data: &[u8], hashes: u8) {
+        if self.bits.is_empty() {
+            return;
+        }
+        for string in data.windows(3) {
+            for bit in self.bits_of(string, hashes) {
+                self.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+    }
+
+    /// Returns false if the literal can't be in the data of the filter.
+    pub fn may_contain(&self, literal: &[u8], hashes: u8) -> bool {
+        if self.bits.is_empty() {
+            return true;
+        }
+        literal.windows(3).all(|string| {
+            self.bits_of(string, hashes).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+        })
+    }
+
+    /// Halves the filter until it has len bytes, each bit set in the
+    /// second half setting the one in the first. Its len is a power of
+    /// 2, and the bits of the strings stay in their place modulo len.
+    fn fold(&mut self, len: usize) {
+        while self.bits.len() > len {
+            let half = self.bits.len() / 2;
+            let (first, second) = self.bits.split_at_mut(half);
+            for (byte, folded) in first.iter_mut().zip(second.iter()) {
+                *byte |= folded;
+            }
+            self.bits.truncate(half);
+        }
+    }
+
+    /// Repeats a folded filter until it has len bytes, a multiple of
+    /// its own, so that the filters of an index have the same size.
+    fn repeat(&mut self, len: usize) {
+        if !self.bits.is_empty() && self.bits.len() < len {
+            self.bits = self.bits.repeat(len / self.bits.len());
+        }
+    }
+
+    /// Returns the bits of a string, by double hashing.
+    fn bits_of(&self, string: &[u8], hashes: u8) -> impl Iterator<Item = usize> {
+        let string = u32::from_le_bytes([string[0], string[1], string[2], 0]);
+        let first = string.wrapping_mul(0x9e37_79b1);
+        let second = (string ^ 0x5bd1_e995).wrapping_mul(0x85eb_ca6b) | 1;
+        let len = self.bits.len() * 8;
+        (0..u32::from(hashes)).map(move |hash| first.wrapping_add(hash.wrapping_mul(second)) as usize % len)
+    }
+}
+
+/// Returns the path of the index of a file, next to it, or in the
+/// directory given with --index-dir under the absolute path of the
+/// file, for the files in read-only directories.
+pub fn index_path(file_path: &str, dir: Option<&Path>) -> PathBuf {
+    let mut path = match dir {
+        Some(dir) => {
+            let absolute = fs::canonicalize(file_path).unwrap_or_else(|_| PathBuf::from(file_path));
+            let relative: PathBuf = absolute
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect();
+            dir.join(relative).into_os_string()
+        }
+        None => file_path.into(),
+    };
+    path.push(format!(".{}", INDEX_EXTENSION));
+    PathBuf::from(path)
+}
+
+/// Reads the index of a file, if it has one that is current.
+/// An index made before the file changed is ignored.
+pub fn read_index(file_path: &str, file: &File, dir: Option<&Path>) -> io::Result<Option<Index>> {
+    let index_file = match File::open(index_path(file_path, dir)) {
+        Ok(index_file) => index_file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let index = Index::read_from(&mut BufReader::new(index_file))?;
+    Ok(Some(index).filter(|index| file.metadata().is_ok_and(|metadata| index.is_current(&metadata))))
+}
+
+/// Returns the modification time of a file in nanoseconds since the
+/// epoch, or 0 where the file systems don't keep it.
+pub fn modified_nanos(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos() as u64)
+}
+
+fn read_u8<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rzstd-index-{}-{}", name, std::process::id()))
+    }
+
+    /// Lines whose frames start and end in the middle of them.
+    fn frames() -> Vec<&'static [u8]> {
+        vec![b"alpha beta\ngam", b"ma delta\nepsilon ", b"zeta\neta\n", b"theta iota kappa\nlambda"]
+    }
+
+    fn write_frames(path: &Path) {
+        let file: Vec<u8> = frames().iter().flat_map(|frame| zstd::bulk::compress(frame, 3).unwrap()).collect();
+        fs::write(path, file).unwrap();
+    }
+
+    #[test]
+    fn bloom_keeps_its_strings_folded_and_repeated() {
+        let data = b"the quick brown fox jumps over the lazy dog";
+        let mut bloom = Bloom::new(1024);
+        bloom.insert(data, BLOOM_HASHES);
+        for len in [1024, 256, 64, 8] {
+            bloom.fold(len);
+            assert_eq!(bloom.bits.len(), len);
+            for string in data.windows(3) {
+                assert!(bloom.may_contain(string, BLOOM_HASHES), "{:?} after folding to {}", string, len);
+            }
+        }
+        bloom.repeat(512);
+        assert_eq!(bloom.bits.len(), 512);
+        assert!(data.windows(3).all(|string| bloom.may_contain(string, BLOOM_HASHES)));
+        assert!(bloom.may_contain(data, BLOOM_HASHES));
+    }
+
+    #[test]
+    fn bloom_without_filter_contains_everything() {
+        let mut bloom = Bloom::default();
+        bloom.insert(b"abc", BLOOM_HASHES);
+        assert!(bloom.is_empty());
+        assert!(bloom.may_contain(b"xyz", BLOOM_HASHES));
+    }
+
+    #[test]
+    fn every_string_hits_the_filter_of_its_frame() {
+        let path = temp_path("strings.zst");
+        write_frames(&path);
+        let index = build_index(path.to_str().unwrap(), b'\n', 1024, ZstdParams::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(index.frames.len(), 4);
+        let data = frames().concat();
+        let mut line_start = 0;
+        for line in data.split_inclusive(|&byte| byte == b'\n') {
+            // The line is in the filter of the frame it starts in
+            let frame = index.frames.iter().rposition(|frame| frame.decompressed_offset <= line_start).unwrap();
+            for string in line.windows(3) {
+                assert!(index.may_contain(frame, Some(&[string.to_vec()])), "{:?} in frame {}", string, frame);
+            }
+            line_start += line.len() as u64;
+        }
+        // The third frame starts with the end of `epsilon zeta`
+        assert_eq!(index.frames[2].first_line_offset, 36);
+        assert_eq!(index.frames[2].first_line_number, 4);
+    }
+
+    #[test]
+    fn stale_index_is_ignored() {
+        let path = temp_path("stale.zst");
+        write_frames(&path);
+        let file_path = path.to_str().unwrap();
+        let index = build_index(file_path, b'\n', 0, ZstdParams::default()).unwrap();
+        index.save(&index_path(file_path, None)).unwrap();
+        assert_eq!(read_index(file_path, &File::open(&path).unwrap(), None).unwrap(), Some(index.clone()));
+
+        // Another modification time, with the same length
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(UNIX_EPOCH).unwrap();
+        assert_eq!(read_index(file_path, &file, None).unwrap(), None);
+
+        // Another length, with the same modification time
+        index.save(&index_path(file_path, None)).unwrap();
+        file.set_len(index.file_len + 1).unwrap();
+        file.set_modified(UNIX_EPOCH + std::time::Duration::from_nanos(index.file_modified)).unwrap();
+        assert_eq!(read_index(file_path, &file, None).unwrap(), None);
+
+        fs::remove_file(index_path(file_path, None)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
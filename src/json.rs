@@ -0,0 +1,184 @@
+use std::io;
+use std::time::Duration;
+
+use grep_matcher::Matcher;
+use serde::Serialize;
+
+use crate::matcher::PatternMatcher;
+use crate::progress;
+use crate::search::FileSummary;
+
+/// A message of the --json output. Each message is printed
+/// as a single JSON object on its own line.
+///
+/// The messages follow the schema of ripgrep's --json output,
+/// so the tools built for ripgrep can read them.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Message<'a> {
+    /// A file is about to be printed.
+    Begin(Begin<'a>),
+    /// A matched line.
+    Match(Lines<'a>),
+    /// A context line.
+    Context(Lines<'a>),
+    /// A file is done.
+    End(End<'a>),
+    /// All the files are done.
+    Summary(Summary),
+}
+
+/// Some text, always valid UTF-8 in rzstd.
+#[derive(Debug, Serialize)]
+pub struct Text<'a> {
+    pub text: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Begin<'a> {
+    pub path: Text<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Lines<'a> {
+    pub path: Text<'a>,
+    /// The line, including its line terminator.
+    pub lines: Text<'a>,
+    pub line_number: Option<u64>,
+    /// The offset of the start of the line in the decompressed data.
+    pub absolute_offset: u64,
+    pub submatches: Vec<SubMatch<'a>>,
+}
+
+/// A match in a line. The offsets are relative to the start of the line.
+#[derive(Debug, Serialize)]
+pub struct SubMatch<'a> {
+    #[serde(rename = "match")]
+    pub matched: Text<'a>,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct End<'a> {
+    pub path: Text<'a>,
+    pub binary_offset: Option<u64>,
+    pub stats: Stats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub elapsed_total: Elapsed,
+    pub stats: Stats,
+}
+
+/// The statistics of one file, or of all of them in the summary.
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub elapsed: Elapsed,
+    pub searches: u64,
+    pub searches_with_match: u64,
+    pub bytes_searched: u64,
+    pub bytes_printed: u64,
+    pub matched_lines: u64,
+    pub matches: u64,
+}
+
+/// A duration, with a human readable version of it.
+#[derive(Debug, Default, Serialize)]
+pub struct Elapsed {
+    pub secs: u64,
+    pub nanos: u32,
+    pub human: String,
+}
+
+impl From<Duration> for Elapsed {
+    fn from(duration: Duration) -> Elapsed {
+        Elapsed {
+            secs: duration.as_secs(),
+            nanos: duration.subsec_nanos(),
+            human: format!("{:.6}s", duration.as_secs_f64()),
+        }
+    }
+}
+
+/// Returns the matches of the matcher in the line.
+pub fn submatches<'a>(matcher: &PatternMatcher, line: &'a str) -> Vec<SubMatch<'a>> {
+    let mut submatches = Vec::new();
+    let _ = matcher.find_iter(line.as_bytes(), |m| {
+        if !m.is_empty() {
+            submatches.push(SubMatch {
+                matched: Text { text: &line[m] },
+                start: m.start(),
+                end: m.end(),
+            });
+        }
+        true
+    });
+    submatches
+}
+
+/// Returns the message as a single line of JSON, with its newline.
+pub fn encode(message: &impl Serialize) -> io::Result<Vec<u8>> {
+    let mut line = match serde_json::to_vec(message) {
+        Ok(line) => line,
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    };
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Prints the message on its own line and returns the
+/// number of printed bytes. The line is written at once,
+/// so the messages of the concurrent tasks don't mix.
+pub fn print(message: &Message<'_>) -> io::Result<u64> {
+    let line = encode(message)?;
+    progress::print(&line)?;
+    Ok(line.len() as u64)
+}
+
+/// Returns the statistics of a single file.
+pub fn file_stats(summary: &FileSummary) -> Stats {
+    Stats {
+        elapsed: summary.elapsed.into(),
+        searches: 1,
+        searches_with_match: u64::from(summary.matched_lines > 0),
+        bytes_searched: summary.bytes_searched,
+        bytes_printed: summary.bytes_printed,
+        matched_lines: summary.matched_lines,
+        matches: summary.matches,
+    }
+}
+
+/// Prints the summary message of all the files.
+pub fn print_summary(summaries: &[FileSummary], elapsed_total: Duration) -> io::Result<()> {
+    print(&summary(summaries, elapsed_total))?;
+    Ok(())
+}
+
+/// Returns the summary message of all the files.
+/// The elapsed time of the stats is the sum of the time spent
+/// on each file, which is more than the total time since the
+/// files are searched concurrently.
+pub fn summary(summaries: &[FileSummary], elapsed_total: Duration) -> Message<'static> {
+    let mut stats = Stats::default();
+    let mut elapsed = Duration::ZERO;
+    for summary in summaries {
+        elapsed += summary.elapsed;
+        // The members of the archives count as searches
+        for searched in summary.searched() {
+            stats.searches += 1;
+            stats.searches_with_match += u64::from(searched.matched_lines > 0);
+        }
+        stats.bytes_searched += summary.bytes_searched;
+        stats.bytes_printed += summary.bytes_printed;
+        stats.matched_lines += summary.matched_lines;
+        stats.matches += summary.matches;
+    }
+    stats.elapsed = elapsed.into();
+
+    Message::Summary(Summary {
+        elapsed_total: elapsed_total.into(),
+        stats,
+    })
+}
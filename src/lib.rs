@@ -0,0 +1,80 @@
+//! rzstd does a grep over compressed files. The rzstd command line
+//! tool is built on this library, which other programs can use to
+//! search compressed data without running the tool.
+//!
+//! [`search`] decodes an input in any of the supported formats, told
+//! apart by its first bytes, and returns its matched lines:
+//!
+//! ```no_run
+//! use std::fs::File;
+//!
+//! let config = rzstd::SearchBuilder::new().pattern("error").build()?;
+//! let file = File::open("app.log.zst")?;
+//! for found in rzstd::search(&config, file)? {
+//!     println!("{}:{}", found.line_number, String::from_utf8_lossy(&found.line));
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! [`search_file_stream`] searches a file the same way for the programs
+//! running on tokio, and returns its matched lines as a stream.
+//!
+//! Both take a [`SearchConfig`] made by a [`SearchBuilder`], which is
+//! also what [`search::process_file`] takes to search whole files and
+//! archives like the tool does. The modules give the rest of the tool.
+//!
+//! The `native` feature, on by default, gives the tool, the stream and
+//! the parallel search of seekable zstd files, which need tokio and
+//! threads. Without it, the library builds for `wasm32-wasip1`.
+//!
+//! With the `ffi` feature, the [`ffi`] module gives a C interface
+//! to the search, declared in `include/rzstd.h`. With the `python`
+//! feature, the library is also the `rzstd` Python module.
+
+mod archive;
+#[cfg(feature = "native")]
+pub mod bench;
+pub mod cache;
+pub mod cli;
+pub mod color;
+pub mod compress;
+mod config;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+mod frames;
+mod headers;
+pub mod index;
+pub mod json;
+pub mod matcher;
+mod matches;
+mod output;
+#[cfg(feature = "native")]
+mod parallel;
+#[cfg(feature = "python")]
+mod python;
+mod prefilter;
+pub mod progress;
+pub mod search;
+#[cfg(feature = "native")]
+mod seekable;
+#[cfg(all(feature = "native", unix))]
+pub mod serve;
+pub mod sink;
+mod skippable;
+#[cfg(feature = "native")]
+mod stream;
+#[cfg(feature = "vectorscan")]
+mod vectorscan;
+pub mod verify;
+pub mod walk;
+#[cfg(feature = "native")]
+pub mod watch;
+
+pub use config::{SearchBuilder, SearchConfig};
+pub use format::Format;
+pub use matcher::{CaseMode, MatcherOptions};
+pub use matches::{search, Match, Matches};
+#[cfg(feature = "native")]
+pub use stream::{search_file_stream, MatchEvent};
+pub use sink::{OutputSink, SinkFactory, SinkLine};
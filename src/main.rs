@@ -1,129 +1,745 @@
-use std::fs::File;
-use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
 use std::process;
-
-use grep_regex::RegexMatcher;
-use grep_matcher::Matcher;
-use grep_searcher::Searcher;
-use grep_searcher::sinks::UTF8;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use futures::future::join_all;
-use colored::Colorize;
+use clap::Parser;
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+use rzstd::compress::{self, CompressOptions, Samples};
+use rzstd::format::ZstdParams;
+use rzstd::{bench, cli, index, json, progress, search, verify, walk, watch, SearchConfig};
 
+/// The exit status of the commands other than the search
+/// when they succeed.
+const EXIT_SUCCESS: i32 = 0;
+/// The exit status when at least one line matched.
+const EXIT_MATCH: i32 = 0;
+/// The exit status when nothing matched.
+const EXIT_NO_MATCH: i32 = 1;
+/// The exit status when an error occurred.
+/// Usage errors reported by clap use the same status.
+const EXIT_ERROR: i32 = 2;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Collect file paths from command line arguments
-    let args: Vec<String> = env::args().collect();
-
-    // Check that we have at least one file path
-    if args.len() < 2 {
-        eprintln!("Usage: rzstd <regex> <file1> <file2> ...");
-        process::exit(1);
-    }
-
-    let regex = &args[1];
-    let files = &args[2..];
-
-    // handles is a vector of futures that will be executed concurrently
-    let mut handles = Vec::new();
-    for file_path in files {
-        let regex = regex.clone(); // Clone regex for each task
-        let file_path = file_path.clone(); // Clone file_path for each
-                                           
-        // Spawn a task to process for the file
-        let handle = tokio::spawn(async move {
-            match process_file(&file_path, &regex).await {
-                Ok(_) => (),
-                Err(e) => {
-                    eprintln!("Error processing file {}: {}", file_path, e);
-                    process::exit(1);
-                }
+    // Parse the command line arguments.
+    // clap prints the usage and exits if they are invalid.
+    let args = cli::Args::parse();
+    if let Some(command) = &args.command {
+        process::exit(run_command(command).await);
+    }
+    let started = Instant::now();
+
+    // Colors are turned on or off for the whole output at once,
+    // whatever the colored crate would have guessed
+    colored::control::set_override(args.color_enabled());
+
+    // The config, with the compiled matcher, is shared by all the tasks.
+    let config = match args.search_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+    let files = args.files();
+    let cancel = search::CancelToken::default(); // Shared by all the tasks
+
+    let walk_options = match args.walk_options() {
+        Ok(walk_options) => walk_options,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    // The files are watched before they are searched,
+    // so that the changes made meanwhile are seen too
+    let watcher = if args.watch {
+        match watch::PathWatcher::new(&files, args.recursive) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(EXIT_ERROR);
             }
-        });
-        // Add the task to the vector of tasks
-        handles.push(handle);
+        }
+    } else {
+        None
+    };
+
+    let mut spawner = Spawner {
+        config,
+        walk_options,
+        cancel,
+        recursive: args.recursive,
+        permits: Arc::new(Semaphore::new(args.jobs().min(Semaphore::MAX_PERMITS))),
+        handles: Vec::new(),
+        errors: Vec::new(),
+    };
+    for file_path in &files {
+        spawner.add(file_path);
+    }
+    if let Some((list, separator)) = args.file_list() {
+        if let Err(e) = add_files_from(&mut spawner, list, separator) {
+            eprintln!("{}", e);
+            spawner.errors.push(e.to_string());
+        }
     }
 
-    // Join all the tasks and wait for them all to complete
-    let _ = join_all(handles).await;
+    let (summaries, errors) = join_searches(&mut spawner).await;
+    finish_progress(spawner.config.options());
+    let printed = progress::stdout_closed() || print_results(&summaries, &args, spawner.config.options(), started);
+    print_errors(&summaries, &errors);
+    let failed = !errors.is_empty() || !printed;
+    if let Some(mut watcher) = watcher {
+        process::exit(watch_files(&mut watcher, &mut spawner, &files, &args).await);
+    }
+    let search_options = spawner.config.options();
 
+    // Exit like grep does, so scripts can rely on the status.
+    // A match found with -q wins over errors, like in grep. The
+    // reader of the output only closes it once lines are printed.
+    let matched = progress::stdout_closed() || summaries.iter().any(|summary| summary.matched_lines > 0);
+    let status = if failed && !(matched && search_options.quiet) {
+        EXIT_ERROR
+    } else if matched {
+        EXIT_MATCH
+    } else {
+        EXIT_NO_MATCH
+    };
 
-    Ok(())
+    // process::exit doesn't run destructors, flush the output first
+    let _ = io::stdout().flush();
+    process::exit(status);
+}
+
+/// Waits for the searches of the spawner to complete and returns the
+/// summaries of the files, and the errors of the files that couldn't
+/// be found, walked or searched, or whose task panicked, as they have
+/// no summary. The errors are already reported as they happened.
+async fn join_searches(spawner: &mut Spawner) -> (Vec<search::FileSummary>, Vec<String>) {
+    let mut summaries = Vec::new();
+    let mut errors = std::mem::take(&mut spawner.errors);
+    for result in join_all(std::mem::take(&mut spawner.handles)).await {
+        match result {
+            Ok(Ok(summary)) => summaries.push(summary),
+            Ok(Err(e)) => errors.push(e.to_string()),
+            Err(e) => {
+                let e = format!("Error joining task: {}", e);
+                eprintln!("{}", e);
+                errors.push(e);
+            }
+        }
+    }
+    (summaries, errors)
+}
+
+/// Prints the errors of the files that failed again once all the files
+/// are searched, with how many of them failed, so that they aren't lost
+/// among the output of a large search. The error of a single file is
+/// only printed once.
+fn print_errors(summaries: &[search::FileSummary], errors: &[String]) {
+    let files = summaries.len() + errors.len();
+    if errors.is_empty() || files < 2 {
+        return;
+    }
+    eprintln!();
+    eprintln!("{} of {} files failed:", errors.len(), files);
+    for error in errors {
+        eprintln!("  {}", error);
+    }
+}
+
+/// Removes the progress bars once the files are read, before
+/// the summaries are printed.
+fn finish_progress(options: &search::SearchOptions) {
+    if let Some(progress) = &options.progress {
+        progress.finish();
+    }
+}
+
+/// Prints what is only known once the files are searched, the
+/// summaries and the stats. Returns false if they couldn't be
+/// printed, but not when stdout is closed meanwhile.
+fn print_results(
+    summaries: &[search::FileSummary],
+    args: &cli::Args,
+    options: &search::SearchOptions,
+    started: Instant,
+) -> bool {
+    if options.json {
+        match json::print_summary(summaries, started.elapsed()) {
+            Ok(()) => {}
+            // The reader of the output is gone, like head, it doesn't
+            // want the summary either
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(e) => {
+                eprintln!("Error printing the summary: {}", e);
+                return false;
+            }
+        }
+    } else {
+        print_summaries(summaries, options);
+        if args.stats {
+            print_stats(summaries, started.elapsed());
+        }
+    }
+    true
 }
 
-/// Processes a single file. 
-/// It will stream the file into a decoder and stream the 
-/// decoded data into a searcher. The searcher will then
-/// perform a regext "grep" and print the results to stdout.
-async fn process_file(file_path: &str, regex: &str) -> Result<()> {
-    let file = match File::open(file_path){
-        Ok(file) => file,
+/// Searches the changed files again each time some change, for
+/// --watch, and prints their results like for the first search.
+/// Only returns, with the exit status, once the files can't be
+/// watched anymore or stdout is closed.
+async fn watch_files(watcher: &mut watch::PathWatcher, spawner: &mut Spawner, files: &[String], args: &cli::Args) -> i32 {
+    let _ = io::stdout().flush();
+    loop {
+        if progress::stdout_closed() {
+            return EXIT_MATCH;
+        }
+        let changed = match watcher.changed().await {
+            Ok(changed) => changed,
+            Err(e) => {
+                eprintln!("{}", e);
+                return EXIT_ERROR;
+            }
+        };
+        let started = Instant::now();
+        for file_path in files {
+            spawner.add_selected(file_path, &|path: &str| watch::is_changed(path, &changed));
+        }
+        let (summaries, errors) = join_searches(spawner).await;
+        finish_progress(spawner.config.options());
+        print_results(&summaries, args, spawner.config.options(), started);
+        print_errors(&summaries, &errors);
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Runs a command other than the search and returns its exit status.
+async fn run_command(command: &cli::Command) -> i32 {
+    match command {
+        cli::Command::Index(index_args) => index_files(index_args),
+        cli::Command::Cat(cat_args) => cat_files(cat_args),
+        cli::Command::Test(test_args) => test_files(test_args),
+        cli::Command::Compress(compress_args) => compress_files(compress_args),
+        cli::Command::TrainDict(train_args) => train_dictionary(train_args),
+        cli::Command::Serve(serve_args) => serve(serve_args).await,
+        cli::Command::Bench(bench_args) => bench_files(bench_args),
+    }
+}
+
+/// Measures the throughput of each stage of `rzstd bench` for each
+/// number of threads and buffer size, and prints them as a table.
+fn bench_files(args: &cli::BenchArgs) -> i32 {
+    let options = match args.search_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let bench = match bench::Bench::new(args.files.clone(), &args.regexps, options) {
+        Ok(bench) => bench,
         Err(e) => {
-            let e = anyhow::anyhow!("Error opening file {}: {}", file_path, e);
-            return Err(e.into());
+            eprintln!("{}", e);
+            return EXIT_ERROR;
         }
-    
     };
 
-    if file.metadata()?.len() == 0 {
-        // File is empty, nothing to do
-        return Ok(());
+    let decoded = bench.decoded_len();
+    println!("{} files, {} bytes decompressed, best of {} runs", args.files.len(), decoded, args.runs);
+    println!();
+    print!("{:>8} {:>8}", "threads", "buffer");
+    for stage in bench::Stage::ALL {
+        print!(" {:>15}", stage.name());
+    }
+    println!();
+    for threads in args.threads() {
+        for &buffer_size in &args.buffer_sizes {
+            print!("{:>8} {:>8}", threads, bench::format_size(buffer_size));
+            for stage in bench::Stage::ALL {
+                let mut fastest = Duration::MAX;
+                for _ in 0..args.runs {
+                    let buffer_len = usize::try_from(buffer_size).unwrap_or(usize::MAX);
+                    match bench.run(stage, threads, buffer_len) {
+                        Ok(elapsed) => fastest = fastest.min(elapsed),
+                        Err(e) => {
+                            println!();
+                            eprintln!("{}", e);
+                            return EXIT_ERROR;
+                        }
+                    }
+                }
+                let secs = fastest.as_secs_f64();
+                let throughput = if secs > 0.0 { decoded as f64 / secs / 1_000_000.0 } else { 0.0 };
+                print!(" {:>10.2} MB/s", throughput);
+            }
+            println!();
+        }
     }
+    EXIT_SUCCESS
+}
 
-    if file.metadata()?.file_type().is_dir() {
-        // File is a directory, nothing to do
-        return Err(anyhow::anyhow!("{} is a directory", file_path));
+/// Answers the searches sent to the socket of `rzstd serve`, until
+/// the server is stopped or can't listen anymore.
+#[cfg(unix)]
+async fn serve(args: &cli::ServeArgs) -> i32 {
+    let options = match args.search_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let server = rzstd::serve::Server::new(options, args.walk_options());
+    match server.serve(&args.socket).await {
+        Ok(()) => EXIT_SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_ERROR
+        }
     }
+}
+
+/// The server listens on a Unix socket, there is none elsewhere.
+#[cfg(not(unix))]
+async fn serve(_args: &cli::ServeArgs) -> i32 {
+    eprintln!("Error serving: rzstd serve needs Unix sockets");
+    EXIT_ERROR
+}
 
-    if file.metadata()?.file_type().is_symlink() {
-        // File is a symlink, nothing to do
-        // we don't follow symlinks
-        return Err(anyhow::anyhow!("{} is a symlink", file_path));
+/// Trains a dictionary on the samples given to `rzstd train-dict`,
+/// and writes it to its output file. The samples that can't be read
+/// are reported, and the dictionary is still trained on the others.
+fn train_dictionary(args: &cli::TrainDictArgs) -> i32 {
+    let walk_options = match args.walk_options() {
+        Ok(walk_options) => walk_options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let options = args.search_options();
+    let sample_size = args.sample_size.map(|size| usize::try_from(size).unwrap_or(usize::MAX));
+
+    let mut status = EXIT_SUCCESS;
+    let mut files = Vec::new();
+    for sample in &args.samples {
+        if !Path::new(sample).is_dir() {
+            files.push(sample.clone());
+            continue;
+        }
+        walk::walk(Path::new(sample), &walk_options, &mut |found| match found {
+            Ok(file_path) => files.push(file_path),
+            Err(e) => {
+                eprintln!("{}", e);
+                status = EXIT_ERROR;
+            }
+        });
     }
 
-    // Read zstd encoded data from stdin and decode
-    let decoder = match zstd::stream::read::Decoder::new(file){
-        Ok(decoder) => decoder,
+    let mut samples = Samples::default();
+    for file_path in &files {
+        let mut data = Vec::new();
+        let result = search::decode_file(file_path, &options).and_then(|mut decoder| {
+            decoder
+                .read_to_end(&mut data)
+                .map_err(|e| anyhow::anyhow!("Error decoding file {}: {}", file_path, e))
+        });
+        match result {
+            Ok(_) => samples.add(&data, sample_size),
+            Err(e) => {
+                eprintln!("{}", e);
+                status = EXIT_ERROR;
+            }
+        }
+    }
+    if samples.is_empty() {
+        eprintln!("Error training dictionary: no samples to train it on");
+        return EXIT_ERROR;
+    }
+
+    let max_size = usize::try_from(args.max_size).unwrap_or(usize::MAX);
+    let dictionary = match samples.train(max_size) {
+        Ok(dictionary) => dictionary,
         Err(e) => {
-            let e = anyhow::anyhow!("Error creating decoder for file {}: {}", file_path, e);
-            return Err(e.into());
+            eprintln!("Error training dictionary on {} samples: {}", samples.len(), e);
+            return EXIT_ERROR;
         }
     };
+    if let Err(e) = fs::write(&args.output, dictionary) {
+        eprintln!("Error writing dictionary {}: {}", args.output.display(), e);
+        return EXIT_ERROR;
+    }
+    status
+}
 
-    let matcher = match RegexMatcher::new(&regex){
-        Ok(matcher) => matcher,
+/// Compresses each file given to `rzstd compress`. A file that can't
+/// be compressed is reported, and the next ones are still compressed.
+fn compress_files(args: &cli::CompressArgs) -> i32 {
+    let options = match args.compress_options() {
+        Ok(options) => options,
         Err(e) => {
-            let e = anyhow::anyhow!("Error compiling regex {}: {}", regex, e);
-            return Err(e.into());
+            eprintln!("{}", e);
+            return EXIT_ERROR;
         }
     };
+    let files = args.files();
+    if args.output.is_some() && files.len() > 1 {
+        eprintln!("Error compressing: only one file can be compressed to --output");
+        return EXIT_ERROR;
+    }
 
-    match Searcher::new().search_reader(&matcher, decoder, UTF8(|_lnum, line| {
-        // Color the matched string to red.
-        let matched_str = match matcher.find(line.as_bytes()) {
-            Ok(matched_str) => matched_str,
-            Err(_) => return Ok(true), // Return true in the lambda function to continue searching
+    let mut status = EXIT_SUCCESS;
+    for file_path in &files {
+        let output_path = args.output_path(file_path);
+        if let Err(e) = compress_file(file_path, output_path.as_deref(), args.force, &options) {
+            eprintln!("{}", e);
+            status = EXIT_ERROR;
+        }
+    }
+    status
+}
+
+/// Compresses a file to the output path, or to stdout for None.
+/// An output file that couldn't be written whole is removed.
+fn compress_file(file_path: &str, output_path: Option<&str>, force: bool, options: &CompressOptions) -> Result<()> {
+    let (input, size): (Box<dyn Read>, Option<u64>) = if file_path == cli::STDIN_PATH {
+        (Box::new(io::stdin().lock()), None)
+    } else {
+        match File::open(file_path) {
+            // The size is written in the frame, for the decoders
+            Ok(file) => {
+                let size = file.metadata().ok().map(|metadata| metadata.len());
+                (Box::new(file), size)
+            }
+            Err(e) => return Err(anyhow::anyhow!("Error opening file {}: {}", file_path, e)),
+        }
+    };
+
+    let output_path = match output_path {
+        Some(output_path) => output_path,
+        None => {
+            return match compress::compress(input, BufWriter::new(io::stdout().lock()), size, options) {
+                // The program reading the output stopped, like head does
+                Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                    Err(anyhow::anyhow!("Error compressing file {}: {}", file_path, e))
+                }
+                _ => Ok(()),
+            };
+        }
+    };
+    let output = if force {
+        File::create(output_path)
+    } else {
+        File::create_new(output_path)
+    };
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => return Err(anyhow::anyhow!("Error creating file {}: {}", output_path, e)),
+    };
+    match compress::compress(input, BufWriter::new(output), size, options) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(output_path);
+            Err(anyhow::anyhow!("Error compressing file {}: {}", file_path, e))
+        }
+    }
+}
+
+/// Decodes each file given to `rzstd test`, and reports the corrupted
+/// ones. Fails if any file is corrupted or can't be read.
+fn test_files(args: &cli::TestArgs) -> i32 {
+    let options = match args.search_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_ERROR;
+        }
+    };
+
+    let mut status = EXIT_SUCCESS;
+    for file_path in args.files() {
+        match verify::verify_file(&file_path, &options) {
+            Ok(decoded) if args.verbose => println!("{}: {} bytes", search::display_path(&file_path), decoded),
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{}", e);
+                status = EXIT_ERROR;
+            }
+        }
+    }
+    status
+}
+
+/// Writes the index of each file given to `rzstd index`. A file that
+/// can't be indexed is reported, and the others are still indexed.
+fn index_files(args: &cli::IndexArgs) -> i32 {
+    let dictionary = match args.dictionary() {
+        Ok(dictionary) => dictionary,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_ERROR;
+        }
+    };
+
+    let mut status = EXIT_SUCCESS;
+    for file_path in &args.files {
+        let zstd = ZstdParams {
+            dictionary: dictionary.as_deref().map(Vec::as_slice),
+            window_log_max: args.max_window,
         };
-        let matched_str = match matched_str {
-            Some(matched_str) => matched_str,
-            None => return Ok(true), // Return true in the lambda function to continue searching
+        let built = index::build_index(file_path, args.line_terminator(), args.bloom_size, zstd);
+        let result = match built {
+            Ok(built) => built
+                .save(&index::index_path(file_path, args.index_dir.as_deref()))
+                .map_err(|e| anyhow::anyhow!("Error writing index of file {}: {}", file_path, e)),
+            Err(e) => Err(anyhow::anyhow!("Error indexing file {}: {}", file_path, e)),
         };
-        let colored_line = line.replace(&line[matched_str], &line[matched_str].red().to_string());
-
-        // Print the line to stdout
-        // Here we use print!() instead of println!() because
-        // each line already has a newline character at the end.
-        print!("{}", colored_line);
-        Ok(true) // Return true in the lambda function to continue searching
-    })){
-        Ok(_) => (),
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            status = EXIT_ERROR;
+        }
+    }
+    status
+}
+
+/// Writes the decoded data of each file given to `rzstd cat` to
+/// stdout. A file that can't be decoded is reported, and the next
+/// ones are still written, like zcat does.
+fn cat_files(args: &cli::CatArgs) -> i32 {
+    let options = match args.search_options() {
+        Ok(options) => options,
         Err(e) => {
-            let e = anyhow::anyhow!("Error searching file {}: {}", file_path, e);
-            return Err(e.into());
+            eprintln!("{}", e);
+            return EXIT_ERROR;
         }
     };
 
+    let mut status = EXIT_SUCCESS;
+    let mut stdout = progress::SuspendedWriter(io::stdout().lock());
+    for file_path in args.files() {
+        let mut decoder = match search::decode_file(&file_path, &options) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                eprintln!("{}", e);
+                status = EXIT_ERROR;
+                continue;
+            }
+        };
+        match io::copy(&mut decoder, &mut stdout) {
+            Ok(_) => {}
+            // The program reading the output stopped, like head does
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return status,
+            Err(e) => {
+                eprintln!("Error decoding file {}: {}", file_path, e);
+                status = EXIT_ERROR;
+            }
+        }
+    }
+    finish_progress(&options);
+    match stdout.flush() {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+            eprintln!("Error writing the output: {}", e);
+            EXIT_ERROR
+        }
+        _ => status,
+    }
+}
+
+/// Spawns the tasks that search the files, in the order
+/// the files are added. Directories are walked when
+/// searching recursively.
+struct Spawner {
+    config: SearchConfig,
+    walk_options: walk::WalkOptions,
+    cancel: search::CancelToken,
+    recursive: bool,
+    /// The files searched at once with -j, each task takes
+    /// a permit before opening its file.
+    permits: Arc<Semaphore>,
+    /// handles is a vector of futures that will be executed concurrently
+    handles: Vec<JoinHandle<Result<search::FileSummary>>>,
+    /// The errors of the files that couldn't be found or walked,
+    /// until the searches are joined.
+    errors: Vec<String>,
+}
+
+impl Spawner {
+    /// Spawns the tasks that search a path given by the user.
+    fn add(&mut self, file_path: &str) {
+        self.add_selected(file_path, &|_| true);
+    }
+
+    /// Spawns the tasks that search the files of a path given by the
+    /// user that are selected, like the changed ones with --watch.
+    fn add_selected(&mut self, file_path: &str, selected: &dyn Fn(&str) -> bool) {
+        if self.recursive && Path::new(file_path).is_dir() {
+            // Spawn a task for each file found in the directory
+            walk::walk(Path::new(file_path), &self.walk_options, &mut |found| match found {
+                Ok(path) if selected(&path) => {
+                    self.handles.push(spawn_search(path, &self.config, &self.cancel, &self.permits))
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("{}", e);
+                    self.errors.push(e.to_string());
+                }
+            });
+            return;
+        }
+        if file_path != cli::STDIN_PATH && self.walk_options.globs.selects(Path::new(file_path)) == Some(false) {
+            // The file is filtered out by the globs
+            return;
+        }
+        if !selected(file_path) {
+            return;
+        }
+        let handle = spawn_search(file_path.to_string(), &self.config, &self.cancel, &self.permits);
+        self.handles.push(handle);
+    }
+}
+
+/// Adds the files listed in a file, split by the separator.
+/// A list of `-` is read from stdin. Each file is spawned as soon
+/// as it is read, so the searches start before the whole list is read.
+fn add_files_from(spawner: &mut Spawner, list: &str, separator: u8) -> Result<()> {
+    let reader: Box<dyn BufRead> = if list == cli::STDIN_PATH {
+        Box::new(io::stdin().lock())
+    } else {
+        match File::open(list) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => return Err(anyhow::anyhow!("Error opening file list {}: {}", list, e)),
+        }
+    };
+
+    for file_path in reader.split(separator) {
+        let file_path = match file_path {
+            Ok(file_path) => file_path,
+            Err(e) => return Err(anyhow::anyhow!("Error reading file list {}: {}", list, e)),
+        };
+        if !file_path.is_empty() {
+            spawner.add(&String::from_utf8_lossy(&file_path));
+        }
+    }
     Ok(())
 }
+
+/// Spawns a task that searches a single file.
+/// The task returns the summary of the file, or the error that
+/// stopped its search once reported, so that the other files are
+/// still searched. Once stdout is closed, all the searches stop. It waits for a permit first, so that only -j
+/// files are searched at once.
+fn spawn_search(
+    file_path: String,
+    config: &SearchConfig,
+    cancel: &search::CancelToken,
+    permits: &Arc<Semaphore>,
+) -> JoinHandle<Result<search::FileSummary>> {
+    let follow_tail = config.options().follow_tail;
+    let config = config.clone(); // Clone the config for each task
+    let cancel = cancel.clone(); // Clone the cancel token for each task
+    let permits = permits.clone();
+
+    let search = async move {
+        // The semaphore is never closed
+        let _permit = permits.acquire_owned().await.ok();
+        let result = search::process_file(&file_path, &config, &cancel).await;
+        if result.is_err() && progress::stdout_closed() {
+            // The reader of the output is gone, like head, which
+            // stops the search of all the files without an error
+            cancel.cancel();
+            return Ok(search::FileSummary {
+                path: file_path,
+                ..Default::default()
+            });
+        }
+        if let Err(e) = &result {
+            // The bars of the other files would be drawn over the error
+            if let Some(progress) = &config.options().progress {
+                progress.clear();
+            }
+            eprintln!("Error processing file {}: {}", file_path, e);
+        }
+        result
+    };
+    if follow_tail {
+        // A followed file waits for its data on a thread of its own,
+        // the other files are still searched meanwhile
+        let runtime = Handle::current();
+        return tokio::task::spawn_blocking(move || runtime.block_on(search));
+    }
+    tokio::spawn(search)
+}
+
+/// Prints what is only known once the files are searched,
+/// like the counts of -c. The summaries are printed
+/// in the order the files were given.
+fn print_summaries(summaries: &[search::FileSummary], options: &search::SearchOptions) {
+    if options.quiet {
+        // Only the exit status tells if anything matched
+        return;
+    }
+
+    for file in summaries {
+        // The members of an archive are always shown with their path
+        let with_filename = options.with_filename || !file.members.is_empty();
+        for summary in file.searched() {
+            if options.files_with_matches {
+                if summary.matched_lines > 0 {
+                    println!("{}", options.colors.path.paint(summary.display_path()));
+                }
+            } else if options.files_without_match {
+                if summary.matched_lines == 0 {
+                    println!("{}", options.colors.path.paint(summary.display_path()));
+                }
+            } else if options.count_matches {
+                print_count(summary, summary.matches, with_filename, options);
+            } else if options.count {
+                print_count(summary, summary.matched_lines, with_filename, options);
+            }
+        }
+    }
+}
+
+/// Prints the statistics of --stats, summed over all the files.
+/// The throughput is the decompressed bytes per second of the
+/// whole run, as the files are searched concurrently.
+fn print_stats(summaries: &[search::FileSummary], elapsed: Duration) {
+    let matched_lines: u64 = summaries.iter().map(|summary| summary.matched_lines).sum();
+    // The members of the archives count as files
+    let searched = summaries.iter().flat_map(search::FileSummary::searched);
+    let files_searched = searched.clone().count();
+    let files_matched = searched.filter(|summary| summary.matched_lines > 0).count();
+    let bytes_searched: u64 = summaries.iter().map(|summary| summary.bytes_searched).sum();
+    let bytes_read: u64 = summaries.iter().map(|summary| summary.bytes_read).sum();
+    let secs = elapsed.as_secs_f64();
+    let throughput = if secs > 0.0 { bytes_searched as f64 / secs / 1_000_000.0 } else { 0.0 };
+
+    println!();
+    println!("{} matched lines", matched_lines);
+    println!("{} files contained matches", files_matched);
+    println!("{} files searched", files_searched);
+    println!("{} bytes decompressed", bytes_searched);
+    println!("{} compressed bytes read", bytes_read);
+    println!("{:.6} seconds", secs);
+    println!("{:.2} MB/s decompressed", throughput);
+}
+
+/// Prints the count of a file, prefixed with its path
+/// when the lines would be prefixed with it.
+fn print_count(summary: &search::FileSummary, count: u64, with_filename: bool, options: &search::SearchOptions) {
+    if with_filename {
+        println!("{}:{}", options.colors.path.paint(summary.display_path()), count);
+    } else {
+        println!("{}", count);
+    }
+}
@@ -0,0 +1,315 @@
+use std::io;
+
+use grep_matcher::{ByteSet, Captures, LineMatchKind, LineTerminator, Match, Matcher};
+use grep_regex::{RegexCaptures, RegexMatcher, RegexMatcherBuilder};
+
+use anyhow::Result;
+
+#[cfg(feature = "vectorscan")]
+use crate::vectorscan::VectorscanMatcher;
+
+/// How the case of letters is taken into account when matching.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseMode {
+    /// Letters only match letters of the same case.
+    #[default]
+    Sensitive,
+    /// Letters match both upper and lower case variants.
+    Insensitive,
+    /// Insensitive unless the pattern contains an uppercase letter.
+    Smart,
+}
+
+/// Options that change how the patterns are matched.
+#[derive(Debug, Clone, Default)]
+pub struct MatcherOptions {
+    /// How the case of letters is matched.
+    pub case: CaseMode,
+    /// Match the patterns literally instead of as regexes.
+    pub fixed_strings: bool,
+    /// Only match whole words.
+    pub word: bool,
+    /// Only match whole lines.
+    pub whole_line: bool,
+    /// Match ASCII only: `\w`, `\d`, `\s`, `\b` and the case folding
+    /// of [`CaseMode::Insensitive`] only know the ASCII characters, `.`
+    /// matches any byte and `\xFF` the byte 0xFF. This is faster on the
+    /// data known to be ASCII. The PCRE2 and vectorscan patterns always
+    /// match ASCII only.
+    pub ascii_only: bool,
+    /// The most bytes the compiled regex can take, 100M by default.
+    /// The patterns compiled to a larger regex, like `\w{1000}` or
+    /// thousands of alternatives, fail to build.
+    pub size_limit: Option<usize>,
+    /// The most bytes the lazy DFA of the regex can take for each
+    /// search at once, 1000M by default. A smaller DFA doesn't fail,
+    /// but the searches of the large regexes then get slower.
+    pub dfa_size_limit: Option<usize>,
+    /// Let the matches span several lines, with `\n` or `\s` in the
+    /// patterns, and `^` and `$` match at the start and end of each
+    /// line. The iterator of [`search`](crate::search()) still
+    /// matches each line on its own.
+    pub multiline: bool,
+    /// Compile the patterns with PCRE2 instead of the regex crate,
+    /// for their backreferences and look-around. This needs the
+    /// `pcre2` feature.
+    pub pcre2: bool,
+    /// Compile the patterns with vectorscan, for the large sets of
+    /// patterns. This needs the `vectorscan` feature.
+    pub vectorscan: bool,
+}
+
+/// The matcher of the patterns, compiled by the regex crate,
+/// by PCRE2 with [`MatcherOptions::pcre2`], or by vectorscan with
+/// [`MatcherOptions::vectorscan`].
+#[derive(Debug, Clone)]
+pub enum PatternMatcher {
+    Regex(RegexMatcher),
+    #[cfg(feature = "pcre2")]
+    Pcre2(grep_pcre2::RegexMatcher),
+    #[cfg(feature = "vectorscan")]
+    Vectorscan(VectorscanMatcher),
+}
+
+/// The capture groups of a match of a [`PatternMatcher`].
+#[derive(Debug)]
+pub enum PatternCaptures {
+    Regex(RegexCaptures),
+    #[cfg(feature = "pcre2")]
+    Pcre2(grep_pcre2::RegexCaptures),
+    /// vectorscan has no groups, only the whole match.
+    #[cfg(feature = "vectorscan")]
+    Vectorscan(Option<Match>),
+}
+
+impl Captures for PatternCaptures {
+    fn len(&self) -> usize {
+        match self {
+            PatternCaptures::Regex(caps) => caps.len(),
+            #[cfg(feature = "pcre2")]
+            PatternCaptures::Pcre2(caps) => caps.len(),
+            #[cfg(feature = "vectorscan")]
+            PatternCaptures::Vectorscan(_) => 1,
+        }
+    }
+
+    fn get(&self, i: usize) -> Option<Match> {
+        match self {
+            PatternCaptures::Regex(caps) => caps.get(i),
+            #[cfg(feature = "pcre2")]
+            PatternCaptures::Pcre2(caps) => caps.get(i),
+            #[cfg(feature = "vectorscan")]
+            PatternCaptures::Vectorscan(found) => found.filter(|_| i == 0),
+        }
+    }
+}
+
+/// The errors of PCRE2, like its match limits, and of vectorscan end
+/// the search of the file. The regex crate doesn't have any.
+///
+/// The vectorscan scans here each make their own scratch space, the
+/// searches of the files keep theirs in their
+/// [`FileMatcher`](crate::prefilter::FileMatcher).
+impl Matcher for PatternMatcher {
+    type Captures = PatternCaptures;
+    type Error = io::Error;
+
+    fn find_at(&self, haystack: &[u8], at: usize) -> io::Result<Option<Match>> {
+        match self {
+            PatternMatcher::Regex(regex) => regex.find_at(haystack, at).map_err(match_error),
+            #[cfg(feature = "pcre2")]
+            PatternMatcher::Pcre2(regex) => regex.find_at(haystack, at).map_err(match_error),
+            #[cfg(feature = "vectorscan")]
+            PatternMatcher::Vectorscan(vectorscan) => vectorscan.scanner()?.find_at(haystack, at),
+        }
+    }
+
+    fn new_captures(&self) -> io::Result<PatternCaptures> {
+        match self {
+            PatternMatcher::Regex(regex) => regex.new_captures().map(PatternCaptures::Regex).map_err(match_error),
+            #[cfg(feature = "pcre2")]
+            PatternMatcher::Pcre2(regex) => regex.new_captures().map(PatternCaptures::Pcre2).map_err(match_error),
+            #[cfg(feature = "vectorscan")]
+            PatternMatcher::Vectorscan(_) => Ok(PatternCaptures::Vectorscan(None)),
+        }
+    }
+
+    fn capture_count(&self) -> usize {
+        match self {
+            PatternMatcher::Regex(regex) => regex.capture_count(),
+            #[cfg(feature = "pcre2")]
+            PatternMatcher::Pcre2(regex) => regex.capture_count(),
+            #[cfg(feature = "vectorscan")]
+            PatternMatcher::Vectorscan(_) => 1,
+        }
+    }
+
+    fn capture_index(&self, name: &str) -> Option<usize> {
+        match self {
+            PatternMatcher::Regex(regex) => regex.capture_index(name),
+            #[cfg(feature = "pcre2")]
+            PatternMatcher::Pcre2(regex) => regex.capture_index(name),
+            #[cfg(feature = "vectorscan")]
+            PatternMatcher::Vectorscan(_) => None,
+        }
+    }
+
+    fn captures_at(&self, haystack: &[u8], at: usize, caps: &mut PatternCaptures) -> io::Result<bool> {
+        match (self, caps) {
+            (PatternMatcher::Regex(regex), PatternCaptures::Regex(caps)) => regex.captures_at(haystack, at, caps).map_err(match_error),
+            #[cfg(feature = "pcre2")]
+            (PatternMatcher::Pcre2(regex), PatternCaptures::Pcre2(caps)) => regex.captures_at(haystack, at, caps).map_err(match_error),
+            #[cfg(feature = "vectorscan")]
+            (PatternMatcher::Vectorscan(vectorscan), PatternCaptures::Vectorscan(found)) => {
+                *found = vectorscan.scanner()?.find_at(haystack, at)?;
+                Ok(found.is_some())
+            }
+            // The captures are always made by the same matcher
+            #[cfg(any(feature = "pcre2", feature = "vectorscan"))]
+            _ => Err(io::Error::other("captures of another matcher")),
+        }
+    }
+
+    fn shortest_match_at(&self, haystack: &[u8], at: usize) -> io::Result<Option<usize>> {
+        match self {
+            PatternMatcher::Regex(regex) => regex.shortest_match_at(haystack, at).map_err(match_error),
+            #[cfg(feature = "pcre2")]
+            PatternMatcher::Pcre2(regex) => regex.shortest_match_at(haystack, at).map_err(match_error),
+            #[cfg(feature = "vectorscan")]
+            PatternMatcher::Vectorscan(vectorscan) => vectorscan.scanner()?.shortest_match_at(haystack, at),
+        }
+    }
+
+    fn non_matching_bytes(&self) -> Option<&ByteSet> {
+        match self {
+            PatternMatcher::Regex(regex) => regex.non_matching_bytes(),
+            #[cfg(feature = "pcre2")]
+            PatternMatcher::Pcre2(regex) => regex.non_matching_bytes(),
+            #[cfg(feature = "vectorscan")]
+            PatternMatcher::Vectorscan(_) => None,
+        }
+    }
+
+    fn line_terminator(&self) -> Option<LineTerminator> {
+        match self {
+            PatternMatcher::Regex(regex) => regex.line_terminator(),
+            #[cfg(feature = "pcre2")]
+            PatternMatcher::Pcre2(regex) => regex.line_terminator(),
+            // The lines are scanned one by one, its patterns
+            // may match the line breaks
+            #[cfg(feature = "vectorscan")]
+            PatternMatcher::Vectorscan(_) => None,
+        }
+    }
+
+    fn find_candidate_line(&self, haystack: &[u8]) -> io::Result<Option<LineMatchKind>> {
+        match self {
+            PatternMatcher::Regex(regex) => regex.find_candidate_line(haystack).map_err(match_error),
+            #[cfg(feature = "pcre2")]
+            PatternMatcher::Pcre2(regex) => regex.find_candidate_line(haystack).map_err(match_error),
+            #[cfg(feature = "vectorscan")]
+            PatternMatcher::Vectorscan(vectorscan) => {
+                let found = vectorscan.scanner()?.shortest_match_at(haystack, 0)?;
+                Ok(found.map(LineMatchKind::Candidate))
+            }
+        }
+    }
+}
+
+fn match_error(e: impl ToString) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Builds the matcher used to search every file.
+/// Multiple patterns are combined into a single matcher,
+/// so a line matches if any of the patterns match. Without any
+/// pattern, no line matches, which only the default engine knows.
+pub fn build_matcher(patterns: &[String], options: &MatcherOptions) -> Result<PatternMatcher> {
+    if options.pcre2 && !patterns.is_empty() {
+        return build_pcre2(patterns, options);
+    }
+    if options.vectorscan && !patterns.is_empty() {
+        return build_vectorscan(patterns, options);
+    }
+    let mut builder = RegexMatcherBuilder::new();
+    builder
+        .case_insensitive(options.case == CaseMode::Insensitive)
+        .case_smart(options.case == CaseMode::Smart)
+        .fixed_strings(options.fixed_strings)
+        .word(options.word)
+        .whole_line(options.whole_line)
+        .multi_line(options.multiline)
+        .unicode(!options.ascii_only);
+    if let Some(size_limit) = options.size_limit {
+        builder.size_limit(size_limit);
+    }
+    if let Some(dfa_size_limit) = options.dfa_size_limit {
+        builder.dfa_size_limit(dfa_size_limit);
+    }
+
+    match builder.build_many(patterns) {
+        Ok(matcher) => Ok(PatternMatcher::Regex(matcher)),
+        Err(e) => {
+            let e = anyhow::anyhow!("Error compiling regex {}: {}", patterns.join(", "), regex_error(&e));
+            Err(e)
+        }
+    }
+}
+
+/// Returns the message of an error building a regex. The regex
+/// crate only tells the limit the compiled regex exceeds, not how
+/// to raise it.
+fn regex_error(e: &grep_regex::Error) -> String {
+    let message = e.to_string();
+    if message.starts_with("compiled regex exceeds size limit") {
+        return format!("{}, raise it with --regex-size-limit, like --regex-size-limit 1G", message);
+    }
+    message
+}
+
+/// Builds the matcher of the patterns with PCRE2. The patterns match
+/// bytes rather than UTF-8 characters, so that the lines that aren't
+/// valid UTF-8 are still searched.
+#[cfg(feature = "pcre2")]
+fn build_pcre2(patterns: &[String], options: &MatcherOptions) -> Result<PatternMatcher> {
+    let mut builder = grep_pcre2::RegexMatcherBuilder::new();
+    builder
+        .caseless(options.case == CaseMode::Insensitive)
+        .case_smart(options.case == CaseMode::Smart)
+        .fixed_strings(options.fixed_strings)
+        .word(options.word)
+        .whole_line(options.whole_line)
+        .multi_line(options.multiline)
+        .jit_if_available(true);
+
+    match builder.build_many(patterns) {
+        Ok(matcher) => Ok(PatternMatcher::Pcre2(matcher)),
+        Err(e) => Err(anyhow::anyhow!("Error compiling regex {}: {}", patterns.join(", "), e)),
+    }
+}
+
+#[cfg(not(feature = "pcre2"))]
+fn build_pcre2(patterns: &[String], _options: &MatcherOptions) -> Result<PatternMatcher> {
+    let message = "rzstd is built without PCRE2, see the pcre2 feature";
+    Err(anyhow::anyhow!("Error compiling regex {}: {}", patterns.join(", "), message))
+}
+
+/// Builds the matcher of the patterns with vectorscan, which doesn't
+/// let the matches span several lines.
+#[cfg(feature = "vectorscan")]
+fn build_vectorscan(patterns: &[String], options: &MatcherOptions) -> Result<PatternMatcher> {
+    if options.multiline {
+        let message = "vectorscan doesn't search with --multiline";
+        return Err(anyhow::anyhow!("Error compiling regex {}: {}", patterns.join(", "), message));
+    }
+    match VectorscanMatcher::new(patterns, options) {
+        Ok(matcher) => Ok(PatternMatcher::Vectorscan(matcher)),
+        Err(e) => Err(anyhow::anyhow!("Error compiling regex {}: {}", patterns.join(", "), e)),
+    }
+}
+
+#[cfg(not(feature = "vectorscan"))]
+fn build_vectorscan(patterns: &[String], _options: &MatcherOptions) -> Result<PatternMatcher> {
+    let message = "rzstd is built without vectorscan, see the vectorscan feature";
+    Err(anyhow::anyhow!("Error compiling regex {}: {}", patterns.join(", "), message))
+}
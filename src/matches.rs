@@ -0,0 +1,165 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::Range;
+use std::str;
+
+use anyhow::Result;
+use encoding_rs_io::DecodeReaderBytes;
+use grep_matcher::Matcher;
+
+use crate::config::SearchConfig;
+use crate::format::{self, Format};
+use crate::prefilter::LineMatcher;
+use crate::search::{self, InvalidUtf8};
+
+/// A matched line of the decompressed input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// The 1-based number of the line.
+    pub line_number: u64,
+    /// The offset of the line in the decompressed data.
+    pub byte_offset: u64,
+    /// The line, without its terminator.
+    pub line: Vec<u8>,
+    /// Where the patterns match in the line.
+    /// Empty for the lines returned with invert_match.
+    pub matches: Vec<Range<usize>>,
+}
+
+/// Searches the input, compressed in any of the supported formats
+/// or not at all, and returns its matched lines as they are decoded.
+/// The format is told by the first bytes of the input, unless the
+/// config gives it. Only the options of the config about matching
+/// and decoding the lines are used, nothing is printed.
+///
+/// Fails if the input can't be decoded. The errors met while reading the input end the matches,
+/// see [`Matches::error`].
+pub fn search<'a, R: Read + 'a>(config: &SearchConfig, input: R) -> Result<Matches<'a>> {
+    search_input(config, input, None)
+}
+
+/// Searches the input of [`search`], whose format is told by the
+/// extension of its file too, if the input is a file.
+pub(crate) fn search_input<'a, R: Read + 'a>(
+    config: &SearchConfig,
+    input: R,
+    file_path: Option<&str>,
+) -> Result<Matches<'a>> {
+    let options = config.options();
+    let name = file_path.map_or("input".to_string(), |file_path| format!("file {}", file_path));
+    let (detected, input) = match format::detect(input) {
+        Ok(detected) => detected,
+        Err(e) => return Err(anyhow::anyhow!("Error reading {}: {}", name, e)),
+    };
+    let format = match file_path {
+        Some(file_path) => Format::resolve(options.format.or(detected), file_path),
+        None => options.format.or(detected).unwrap_or(Format::Uncompressed),
+    };
+    let decoder = match format.decoder(input, options.zstd()) {
+        Ok(decoder) => decoder,
+        Err(e) => return Err(anyhow::anyhow!("Error creating decoder for {}: {}", name, e)),
+    };
+
+    // A BOM tells the encoding even without one in the options
+    let decoder = search::transcoded(decoder, options);
+
+    Ok(Matches {
+        decoded: BufReader::new(decoder),
+        matcher: config.line_matcher().clone(),
+        invert_match: options.invert_match,
+        skip_invalid_utf8: options.invalid_utf8 == InvalidUtf8::Skip,
+        left: options.max_count,
+        terminator: options.line_terminator(),
+        line_number: 0,
+        byte_offset: 0,
+        error: None,
+    })
+}
+
+/// The matched lines of an input, returned by [`search`].
+/// The input is decoded and searched as the lines are iterated.
+pub struct Matches<'a> {
+    decoded: BufReader<DecodeReaderBytes<Box<dyn Read + 'a>, Vec<u8>>>,
+    matcher: LineMatcher,
+    invert_match: bool,
+    /// Skip the lines that aren't valid UTF-8.
+    skip_invalid_utf8: bool,
+    /// The lines still to return with max_count.
+    left: Option<u64>,
+    terminator: u8,
+    /// The number of the last read line.
+    line_number: u64,
+    /// The offset of the next line.
+    byte_offset: u64,
+    error: Option<io::Error>,
+}
+
+impl Matches<'_> {
+    /// Returns the error that ended the matches, if the input
+    /// couldn't be read or decoded until its end.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Returns where the patterns match in the line.
+    fn find_matches(&self, line: &[u8]) -> io::Result<Vec<Range<usize>>> {
+        let mut matches = Vec::new();
+        if !self.matcher.may_match(line) {
+            return Ok(matches);
+        }
+        let found = self.matcher.regex().find_iter(line, |found| {
+            matches.push(found.start()..found.end());
+            true
+        });
+        match found {
+            Ok(()) => Ok(matches),
+            Err(e) => Err(io::Error::other(e.to_string())),
+        }
+    }
+}
+
+impl Iterator for Matches<'_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        let mut line = Vec::new();
+        while self.error.is_none() && self.left != Some(0) {
+            line.clear();
+            match self.decoded.read_until(self.terminator, &mut line) {
+                Ok(0) => return None,
+                Ok(read) => {
+                    self.line_number += 1;
+                    let byte_offset = self.byte_offset;
+                    self.byte_offset += read as u64;
+                    if line.last() == Some(&self.terminator) {
+                        line.pop();
+                    }
+
+                    let matches = match self.find_matches(&line) {
+                        Ok(matches) => matches,
+                        Err(e) => {
+                            self.error = Some(e);
+                            return None;
+                        }
+                    };
+                    if matches.is_empty() != self.invert_match {
+                        continue;
+                    }
+                    if self.skip_invalid_utf8 && str::from_utf8(&line).is_err() {
+                        continue;
+                    }
+                    if let Some(left) = &mut self.left {
+                        *left -= 1;
+                    }
+                    return Some(Match {
+                        line_number: self.line_number,
+                        byte_offset,
+                        line,
+                        matches,
+                    });
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::cli;
+use crate::progress;
+
+/// True once a file printed its heading. The next headings
+/// are then separated from the previous file by a blank line.
+static HEADING_PRINTED: AtomicBool = AtomicBool::new(false);
+
+/// The output files of --output-dir created by this search, with the
+/// input written to each, so that two inputs never share one.
+static CREATED: Mutex<BTreeMap<PathBuf, String>> = Mutex::new(BTreeMap::new());
+
+/// The bytes of lines a chunk keeps before sending them at once.
+const CHUNK_BATCH: usize = 64 << 10;
+
+/// Where the lines of a chunk are sent, to be printed in order with
+/// the lines of the other chunks of its file. Fails once they aren't
+/// printed anymore.
+pub type ChunkLines = Box<dyn FnMut(Vec<u8>) -> io::Result<()> + Send>;
+
+/// The printed lines of a single file.
+///
+/// Without headings, the lines are written to stdout one by one,
+/// so the lines of the files searched concurrently never mix.
+/// With headings, the lines of the whole file are kept until the
+/// file is done, and then written at once under its heading.
+/// With --output-dir, the lines are written to a file of their
+/// own instead of stdout.
+pub struct FileOutput {
+    buffer: Vec<u8>,
+    heading: bool,
+    /// Where the lines are sent instead, for the chunks of a file.
+    chunk: Option<ChunkLines>,
+    /// Ends every printed line, `\n` or NUL with --null-data.
+    terminator: u8,
+    /// The file the lines are written to instead of stdout,
+    /// and the input whose lines they are.
+    path: Option<(PathBuf, String)>,
+    /// The opened file, created with the first line so that
+    /// files without any printed line don't get one.
+    file: Option<BufWriter<File>>,
+}
+
+impl FileOutput {
+    pub fn new(heading: bool, terminator: u8) -> FileOutput {
+        FileOutput {
+            buffer: Vec::new(),
+            heading,
+            chunk: None,
+            terminator,
+            path: None,
+            file: None,
+        }
+    }
+
+    /// Returns an output that sends its lines in batches, for the
+    /// chunks of a file that are searched in parallel and printed
+    /// in order. The rest is sent by finish.
+    #[cfg(feature = "native")]
+    pub fn chunk(terminator: u8, send: ChunkLines) -> FileOutput {
+        FileOutput {
+            chunk: Some(send),
+            ..FileOutput::new(false, terminator)
+        }
+    }
+
+    /// Writes the lines of the input to the file at the path
+    /// instead of stdout.
+    pub fn into_file(self, path: PathBuf, file_path: &str) -> FileOutput {
+        FileOutput {
+            path: Some((path, file_path.to_string())),
+            ..self
+        }
+    }
+
+    /// Adds some lines to the output. A line terminator is
+    /// added if the lines don't end with one, which happens
+    /// for the last line of a file. The lines are printed as
+    /// they are, whether they are valid UTF-8 or not.
+    pub fn lines(&mut self, lines: &[u8]) -> io::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        self.buffer.extend_from_slice(lines);
+        if !lines.ends_with(&[self.terminator]) {
+            self.buffer.push(self.terminator);
+        }
+
+        if self.heading {
+            return Ok(());
+        }
+        if let Some(send) = &mut self.chunk {
+            if self.buffer.len() >= CHUNK_BATCH {
+                send(std::mem::take(&mut self.buffer))?;
+            }
+            return Ok(());
+        }
+        let written = match &self.path {
+            Some((path, file_path)) => self.write_file(path.clone(), &file_path.clone()),
+            None => progress::print(&self.buffer),
+        };
+        self.buffer.clear();
+        written
+    }
+
+    /// Writes the buffer to the output file, creating it and
+    /// its directories first if needed. Creating the directories
+    /// succeeds even when other tasks create them at the same time.
+    fn write_file(&mut self, path: PathBuf, file_path: &str) -> io::Result<()> {
+        if self.file.is_none() {
+            let created = match path.parent() {
+                Some(parent) => fs::create_dir_all(parent).and_then(|_| create_file(&path, file_path)),
+                None => create_file(&path, file_path),
+            };
+            match created {
+                Ok(file) => self.file = Some(BufWriter::new(file)),
+                Err(e) => {
+                    let message = format!("Error creating output file {}: {}", path.display(), e);
+                    return Err(io::Error::new(e.kind(), message));
+                }
+            }
+        }
+        match &mut self.file {
+            Some(file) => file.write_all(&self.buffer),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the lines kept for the heading under the heading, or
+    /// sends the last lines of a chunk. Nothing is written for files
+    /// without any printed line.
+    pub fn finish(&mut self, heading: &str) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+        if let Some(send) = &mut self.chunk {
+            if !self.buffer.is_empty() {
+                send(std::mem::take(&mut self.buffer))?;
+            }
+            return Ok(());
+        }
+        if !self.heading || self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.write_heading(heading)
+    }
+
+    /// Writes the heading and the lines kept under it to stdout.
+    fn write_heading(&mut self, heading: &str) -> io::Result<()> {
+        let mut printed = Vec::with_capacity(heading.len() + self.buffer.len() + 2);
+        if HEADING_PRINTED.swap(true, Ordering::Relaxed) {
+            printed.push(self.terminator);
+        }
+        printed.extend_from_slice(heading.as_bytes());
+        printed.push(self.terminator);
+        printed.append(&mut self.buffer);
+        progress::print(&printed)
+    }
+}
+
+/// Creates the output file of an input. A file that already exists
+/// is an error, whether another input of the search was written to it
+/// or an earlier search was, rather than overwriting its lines. Only
+/// the input that created it can write it again, when --watch
+/// searches it again.
+fn create_file(path: &Path, file_path: &str) -> io::Result<File> {
+    let mut created = CREATED.lock().unwrap_or_else(|e| e.into_inner());
+    match created.get(path) {
+        Some(input) if input == file_path => return File::create(path),
+        Some(input) => {
+            let message = format!("already written with the lines of {}", input);
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, message));
+        }
+        None => {}
+    }
+    let file = File::create_new(path)?;
+    created.insert(path.to_path_buf(), file_path.to_string());
+    Ok(file)
+}
+
+/// Returns the path of the file the lines of an input are written
+/// to with --output-dir, `<dir>/<input>.matches`. The input keeps
+/// its directories once `.` and `..` are resolved, like `a/../b.txt`
+/// to `b.txt`, without the ones that would leave the output
+/// directory like a leading `..` or a root.
+pub fn output_path(dir: &Path, file_path: &str) -> PathBuf {
+    if file_path == cli::STDIN_PATH {
+        return dir.join("stdin.matches");
+    }
+
+    let mut relative = PathBuf::new();
+    for component in Path::new(file_path).components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::ParentDir => {
+                relative.pop();
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    let mut name = relative.into_os_string();
+    name.push(".matches");
+    dir.join(name)
+}
@@ -0,0 +1,391 @@
+use std::io::{self, BufRead, Read};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::format::{self, Format, ZstdParams};
+use crate::headers::{self, BlockHeader, FrameHeader, BLOCK_HEADER_LEN, CHECKSUM_LEN, ZSTD_MAGIC};
+
+/// The size of the pieces of compressed and decoded data
+/// sent between the threads.
+const PIECE_LEN: usize = 64 * 1024;
+/// The number of pieces a thread sends ahead of the one receiving
+/// them, which bounds the memory used by each frame.
+const PIECES_AHEAD: usize = 16;
+
+/// A piece of compressed or decoded data.
+type Piece = io::Result<Vec<u8>>;
+
+/// The compressed data of a frame, and where its decoded data goes.
+type FrameJob = (Receiver<Piece>, SyncSender<Piece>);
+
+/// Decodes the frames of a zstd stream on several threads at once,
+/// and returns the decoded data in order.
+///
+/// zstd decodes the blocks of a frame one after the other, but the
+/// frames of a file, like the ones pzstd writes, don't depend on each
+/// other. A thread reads the compressed data and follows its frames
+/// from their headers, and the frames are decoded by `threads`
+/// threads, each one decoding a frame after the other. A file made
+/// of a single frame is still decoded on another thread than the one
+/// searching it.
+pub struct ParallelFrames {
+    /// The decoded data of each frame, in the order of the frames.
+    frames: Receiver<Receiver<Piece>>,
+    /// The frame being read.
+    frame: Option<PieceReader>,
+}
+
+impl ParallelFrames {
+    pub fn new<R: Read + Send + 'static>(
+        input: R,
+        threads: usize,
+        dictionary: Option<Arc<Vec<u8>>>,
+        window_log_max: Option<u32>,
+    ) -> ParallelFrames {
+        // The frame being read is no longer in the channel. The frames
+        // started are never more than the threads, so that each one
+        // has a thread, and the frame being read is always decoded
+        let (frames, receiver) = mpsc::sync_channel(threads.max(1) - 1);
+        let (jobs, queued) = mpsc::channel();
+        let queued = Arc::new(Mutex::new(queued));
+        for _ in 0..threads.max(1) {
+            let queued = queued.clone();
+            let dictionary = dictionary.clone();
+            thread::spawn(move || decode_frames(&queued, dictionary, window_log_max));
+        }
+        thread::spawn(move || {
+            let mut splitter = FrameSplitter {
+                input,
+                frames,
+                jobs,
+                frame: None,
+                buffer: Vec::new(),
+            };
+            if let Err(e) = splitter.split() {
+                splitter.fail(e);
+            }
+        });
+        ParallelFrames {
+            frames: receiver,
+            frame: None,
+        }
+    }
+}
+
+impl Read for ParallelFrames {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let frame = match &mut self.frame {
+                Some(frame) => frame,
+                None => match self.frames.recv() {
+                    Ok(frame) => self.frame.insert(PieceReader::new(frame)),
+                    // All the frames are read
+                    Err(_) => return Ok(0),
+                },
+            };
+            match frame.read(buf)? {
+                // The frame is read until its end
+                0 if !buf.is_empty() => self.frame = None,
+                read => return Ok(read),
+            }
+        }
+    }
+}
+
+/// Decodes a file on a thread of its own while it is searched, so
+/// that decoding and matching the lines each have a core.
+///
+/// The pieces decoded ahead of the search are bounded, the decoding
+/// thread waits for the search when it is ahead, and stops once the
+/// reader is dropped.
+pub struct DecodedAhead {
+    decoded: PieceReader,
+}
+
+impl DecodedAhead {
+    /// Starts decoding the input in the format. The error of a decoder
+    /// that can't be created is returned here rather than when reading.
+    pub fn new<R: Read + Send + 'static>(
+        format: Format,
+        input: R,
+        dictionary: Option<Arc<Vec<u8>>>,
+        window_log_max: Option<u32>,
+    ) -> io::Result<DecodedAhead> {
+        let (created, creation) = mpsc::sync_channel(1);
+        let (decoded, pieces) = mpsc::sync_channel(PIECES_AHEAD);
+        thread::spawn(move || {
+            let zstd = ZstdParams {
+                dictionary: dictionary.as_deref().map(Vec::as_slice),
+                window_log_max,
+            };
+            match format.decoder(input, zstd) {
+                Ok(decoder) => {
+                    let _ = created.send(Ok(()));
+                    send_decoded(decoder, &decoded);
+                }
+                Err(e) => {
+                    let _ = created.send(Err(e));
+                }
+            }
+        });
+        match creation.recv() {
+            Ok(created) => created?,
+            Err(_) => return Err(io::Error::other("the decoding thread stopped")),
+        }
+        Ok(DecodedAhead {
+            decoded: PieceReader::new(pieces),
+        })
+    }
+}
+
+impl Read for DecodedAhead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoded.read(buf)
+    }
+}
+
+/// Reads the pieces received from another thread, until the sender
+/// is dropped.
+struct PieceReader {
+    pieces: Receiver<Piece>,
+    piece: Vec<u8>,
+    /// The offset of the next byte to read in the piece.
+    offset: usize,
+}
+
+impl PieceReader {
+    fn new(pieces: Receiver<Piece>) -> PieceReader {
+        PieceReader {
+            pieces,
+            piece: Vec::new(),
+            offset: 0,
+        }
+    }
+}
+
+impl Read for PieceReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = buf.len().min(available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for PieceReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.offset == self.piece.len() {
+            match self.pieces.recv() {
+                Ok(piece) => {
+                    self.piece = piece?;
+                    self.offset = 0;
+                }
+                Err(_) => break,
+            }
+        }
+        Ok(&self.piece[self.offset..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.offset += amount;
+    }
+}
+
+/// Follows the frames of a zstd stream from their headers to know
+/// where each one ends, without decoding anything, and sends the
+/// compressed data of each frame to the thread decoding it.
+struct FrameSplitter<R> {
+    input: R,
+    /// The decoded data of each frame, for the reader.
+    frames: SyncSender<Receiver<Piece>>,
+    /// The frames to decode, for the threads decoding them.
+    jobs: Sender<FrameJob>,
+    /// The compressed data of the frame being read, for its thread.
+    frame: Option<SyncSender<Piece>>,
+    /// The compressed data not sent yet.
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> FrameSplitter<R> {
+    /// Reads the frames until the input ends or the reader is dropped.
+    fn split(&mut self) -> io::Result<()> {
+        loop {
+            self.buffer.clear();
+            let read = self.read(4)?;
+            if read == 0 {
+                return Ok(());
+            }
+            let magic = match self.buffer[..] {
+                [a, b, c, d] => u32::from_le_bytes([a, b, c, d]),
+                _ => 0,
+            };
+
+            if headers::is_skippable(magic) {
+                self.buffer.clear();
+                self.read_exact(4)?;
+                let size = u32::from_le_bytes([self.buffer[0], self.buffer[1], self.buffer[2], self.buffer[3]]);
+                let skipped = io::copy(&mut (&mut self.input).take(u64::from(size)), &mut io::sink())?;
+                if skipped < u64::from(size) {
+                    return Err(truncated());
+                }
+                continue;
+            }
+
+            if !self.start_frame() {
+                return Ok(());
+            }
+            if magic != ZSTD_MAGIC {
+                // Other data is left to the decoder, which fails like
+                // it does when it decodes the whole file
+                self.input.read_to_end(&mut self.buffer)?;
+                self.send();
+                return Ok(());
+            }
+            self.read_frame()?;
+            if !self.send() {
+                return Ok(());
+            }
+            self.frame = None;
+        }
+    }
+
+    /// Reads the rest of a zstd frame after its magic number.
+    fn read_frame(&mut self) -> io::Result<()> {
+        self.read_exact(1)?;
+        let header = FrameHeader::new(self.buffer[self.buffer.len() - 1]);
+        self.read_exact(header.fields_len())?;
+
+        loop {
+            if self.buffer.len() >= PIECE_LEN && !self.send() {
+                return Ok(());
+            }
+            let start = self.buffer.len();
+            self.read_exact(BLOCK_HEADER_LEN)?;
+            let block = BlockHeader::new([self.buffer[start], self.buffer[start + 1], self.buffer[start + 2]]);
+            self.read_exact(block.content_len)?;
+            if block.last {
+                break;
+            }
+        }
+        if header.checksum() {
+            self.read_exact(CHECKSUM_LEN)?;
+        }
+        Ok(())
+    }
+
+    /// Gives a frame to the threads decoding the frames, and returns
+    /// false if the reader is dropped.
+    fn start_frame(&mut self) -> bool {
+        let (compressed, pieces) = mpsc::sync_channel(PIECES_AHEAD);
+        let (decoded, frame) = mpsc::sync_channel(PIECES_AHEAD);
+        // Waits until fewer than the threads are decoding frames
+        if self.frames.send(frame).is_err() || self.jobs.send((pieces, decoded)).is_err() {
+            return false;
+        }
+        self.frame = Some(compressed);
+        true
+    }
+
+    /// Sends the compressed data read to the thread decoding the frame,
+    /// and returns false if the thread is done.
+    fn send(&mut self) -> bool {
+        let piece = std::mem::take(&mut self.buffer);
+        match &self.frame {
+            Some(frame) => frame.send(Ok(piece)).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Gives the error to the reader, in the frame being read if any.
+    fn fail(&mut self, e: io::Error) {
+        if self.frame.is_none() && !self.start_frame() {
+            return;
+        }
+        self.send();
+        if let Some(frame) = &self.frame {
+            let _ = frame.send(Err(e));
+        }
+    }
+
+    /// Adds up to len bytes of the input to the buffer and returns
+    /// how many were read.
+    fn read(&mut self, len: usize) -> io::Result<usize> {
+        (&mut self.input).take(len as u64).read_to_end(&mut self.buffer)
+    }
+
+    fn read_exact(&mut self, len: usize) -> io::Result<()> {
+        match self.read(len)? {
+            read if read == len => Ok(()),
+            _ => Err(truncated()),
+        }
+    }
+}
+
+/// Decodes the frames given to the threads one after the other,
+/// until the frames of the stream are all given.
+fn decode_frames(queued: &Mutex<Receiver<FrameJob>>, dictionary: Option<Arc<Vec<u8>>>, window_log_max: Option<u32>) {
+    loop {
+        // The lock is only held while waiting for the next frame
+        let job = queued.lock().unwrap_or_else(|e| e.into_inner()).recv();
+        let Ok((pieces, decoded)) = job else {
+            return;
+        };
+        let zstd = ZstdParams {
+            dictionary: dictionary.as_deref().map(Vec::as_slice),
+            window_log_max,
+        };
+        decode_frame(PieceReader::new(pieces), zstd, &decoded);
+    }
+}
+
+/// Decodes a frame and sends its data in pieces, until the frame
+/// ends or the reader is dropped.
+fn decode_frame(compressed: PieceReader, zstd: ZstdParams<'_>, decoded: &SyncSender<Piece>) {
+    match format::zstd_decoder(compressed, zstd) {
+        Ok(decoder) => send_decoded(decoder.single_frame(), decoded),
+        Err(e) => {
+            let _ = decoded.send(Err(e));
+        }
+    }
+}
+
+/// Sends the data of the decoder in pieces, until it ends or fails,
+/// or the reader is dropped.
+fn send_decoded<R: Read>(mut decoder: R, decoded: &SyncSender<Piece>) {
+    loop {
+        let mut piece = vec![0; PIECE_LEN];
+        let piece = match read_piece(&mut decoder, &mut piece) {
+            Ok(0) => return,
+            Ok(len) => {
+                piece.truncate(len);
+                Ok(piece)
+            }
+            Err(e) => Err(e),
+        };
+        let failed = piece.is_err();
+        if decoded.send(piece).is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Fills the piece as much as the decoder can, so that the pieces
+/// sent aren't made of a few bytes.
+fn read_piece<R: Read>(decoder: &mut R, piece: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < piece.len() {
+        match decoder.read(&mut piece[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete frame")
+}
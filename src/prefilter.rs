@@ -0,0 +1,363 @@
+#[cfg(feature = "vectorscan")]
+use std::cell::RefCell;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::io;
+#[cfg(feature = "native")]
+use std::sync::Arc;
+
+use grep_matcher::{ByteSet, LineMatchKind, LineTerminator, Match, Matcher};
+use regex_automata::meta::Regex;
+use regex_syntax::hir::{Hir, HirKind};
+use regex_syntax::ParserBuilder;
+
+use crate::matcher::{CaseMode, MatcherOptions, PatternCaptures, PatternMatcher};
+#[cfg(feature = "vectorscan")]
+use crate::vectorscan::Scanner;
+
+/// The shortest literals worth looking for before running the regex.
+/// Shorter ones are found on too many lines to save anything.
+const MIN_LITERAL_LEN: usize = 3;
+/// The most literals looked for at once.
+const MAX_LITERALS: usize = 64;
+/// The number of lines found with the literals after which a file
+/// checks how far apart they are.
+const SAMPLE_CANDIDATES: u64 = 64;
+/// The fewest bytes skipped on average before each of these lines for
+/// the literals to be worth looking for in the rest of the file.
+const MIN_SKIPPED: u64 = 1024;
+
+/// The matcher the files are searched with: the regex, and the
+/// literals that every match contains.
+///
+/// The decoded data is first scanned for the literals, and the regex
+/// only runs on the lines that contain one of them. The regex crate
+/// already looks for the literals that start the matches, but not
+/// for the ones inside them, like `omega` in `delta.*omega`, which
+/// are often much rarer.
+#[derive(Debug, Clone)]
+pub struct LineMatcher {
+    regex: PatternMatcher,
+    literals: Option<Regex>,
+    /// The literals themselves, for the indexes of the files.
+    #[cfg(feature = "native")]
+    required: Option<Arc<[Vec<u8>]>>,
+}
+
+impl LineMatcher {
+    pub fn new(regex: PatternMatcher, patterns: &[String], options: &MatcherOptions) -> LineMatcher {
+        // A regex of the literals alone picks the fastest way
+        // to look for them
+        let required = required_literals(patterns, options);
+        let literals = required.as_ref().and_then(|literals| {
+            let literals = Hir::alternation(literals.iter().map(|literal| Hir::literal(literal.as_slice())).collect());
+            Regex::builder().build_from_hir(&literals).ok()
+        });
+        LineMatcher {
+            regex,
+            literals,
+            #[cfg(feature = "native")]
+            required: required.map(Arc::from),
+        }
+    }
+
+    /// Returns literals one of which is in every line the patterns
+    /// match, if there are any worth looking for.
+    #[cfg(feature = "native")]
+    pub fn required_literals(&self) -> Option<&[Vec<u8>]> {
+        self.required.as_deref()
+    }
+
+    /// Returns the regex, for what is done with the matched lines.
+    pub fn regex(&self) -> &PatternMatcher {
+        &self.regex
+    }
+
+    /// Returns the matcher for the search of a file.
+    pub fn for_file(&self) -> FileMatcher<'_> {
+        FileMatcher {
+            matcher: self,
+            candidates: Cell::new(0),
+            skipped: Cell::new(0),
+            regex_only: Cell::new(false),
+            #[cfg(feature = "vectorscan")]
+            scanner: RefCell::new(None),
+        }
+    }
+
+    /// Returns false if the regex can't match anywhere in the haystack.
+    pub fn may_match(&self, haystack: &[u8]) -> bool {
+        match &self.literals {
+            Some(literals) => literals.is_match(haystack),
+            None => true,
+        }
+    }
+}
+
+/// The matcher of the search of a file, which stops looking for the
+/// literals if they are on too many lines of the file.
+///
+/// Each line found with a literal is matched again by the regex, which
+/// is slower than the regex alone when most lines have one, like `user`
+/// in `user.*(id|name)` in the logs of a web server.
+pub struct FileMatcher<'a> {
+    matcher: &'a LineMatcher,
+    /// The lines found with the literals.
+    candidates: Cell<u64>,
+    /// The bytes skipped before the lines found with the literals.
+    skipped: Cell<u64>,
+    /// Set once the literals are no longer looked for.
+    regex_only: Cell<bool>,
+    /// The scanner of the vectorscan patterns, made for the first
+    /// line of the file.
+    #[cfg(feature = "vectorscan")]
+    scanner: RefCell<Option<Scanner<'a>>>,
+}
+
+impl<'a> FileMatcher<'a> {
+    /// Runs a scan of the vectorscan patterns of the file with its
+    /// scanner, or returns None when they aren't vectorscan patterns.
+    #[cfg(feature = "vectorscan")]
+    fn scan<T>(&self, scan: impl FnOnce(&mut Scanner<'a>) -> io::Result<T>) -> Option<io::Result<T>> {
+        let PatternMatcher::Vectorscan(vectorscan) = &self.matcher.regex else {
+            return None;
+        };
+        let mut scanner = self.scanner.borrow_mut();
+        let scanner = match &mut *scanner {
+            Some(scanner) => scanner,
+            None => match vectorscan.scanner() {
+                Ok(made) => scanner.insert(made),
+                Err(e) => return Some(Err(e)),
+            },
+        };
+        Some(scan(scanner))
+    }
+
+    /// Counts a line found at the offset of the haystack, and gives up
+    /// on the literals if the lines found so far are too close.
+    fn count_candidate(&self, offset: usize) {
+        let candidates = self.candidates.get() + 1;
+        let skipped = self.skipped.get() + offset as u64;
+        self.candidates.set(candidates);
+        self.skipped.set(skipped);
+        if candidates == SAMPLE_CANDIDATES && skipped / candidates < MIN_SKIPPED {
+            self.regex_only.set(true);
+        }
+    }
+}
+
+impl Matcher for FileMatcher<'_> {
+    type Captures = PatternCaptures;
+    type Error = io::Error;
+
+    fn find_at(&self, haystack: &[u8], at: usize) -> io::Result<Option<Match>> {
+        #[cfg(feature = "vectorscan")]
+        if let Some(found) = self.scan(|scanner| scanner.find_at(haystack, at)) {
+            return found;
+        }
+        self.matcher.regex.find_at(haystack, at)
+    }
+
+    fn new_captures(&self) -> io::Result<PatternCaptures> {
+        self.matcher.regex.new_captures()
+    }
+
+    fn capture_count(&self) -> usize {
+        self.matcher.regex.capture_count()
+    }
+
+    fn capture_index(&self, name: &str) -> Option<usize> {
+        self.matcher.regex.capture_index(name)
+    }
+
+    fn captures_at(&self, haystack: &[u8], at: usize, caps: &mut PatternCaptures) -> io::Result<bool> {
+        self.matcher.regex.captures_at(haystack, at, caps)
+    }
+
+    fn shortest_match_at(&self, haystack: &[u8], at: usize) -> io::Result<Option<usize>> {
+        #[cfg(feature = "vectorscan")]
+        if let Some(found) = self.scan(|scanner| scanner.shortest_match_at(haystack, at)) {
+            return found;
+        }
+        self.matcher.regex.shortest_match_at(haystack, at)
+    }
+
+    fn non_matching_bytes(&self) -> Option<&ByteSet> {
+        self.matcher.regex.non_matching_bytes()
+    }
+
+    fn line_terminator(&self) -> Option<LineTerminator> {
+        self.matcher.regex.line_terminator()
+    }
+
+    /// Returns a line that contains one of the literals, which the
+    /// searcher then matches against the regex.
+    fn find_candidate_line(&self, haystack: &[u8]) -> io::Result<Option<LineMatchKind>> {
+        match &self.matcher.literals {
+            Some(literals) if !self.regex_only.get() => Ok(literals.find(haystack).map(|found| {
+                self.count_candidate(found.start());
+                LineMatchKind::Candidate(found.start())
+            })),
+            _ => self.matcher.regex.find_candidate_line(haystack),
+        }
+    }
+}
+
+/// Returns literals one of which is in every match of the patterns,
+/// or None if there aren't any worth looking for.
+///
+/// The case insensitive patterns have none, their literals are
+/// matched by the regex in too many ways. Neither have the PCRE2 and
+/// vectorscan patterns, which the regex crate can't parse.
+fn required_literals(patterns: &[String], options: &MatcherOptions) -> Option<Vec<Vec<u8>>> {
+    if options.case != CaseMode::Sensitive || options.pcre2 || options.vectorscan {
+        return None;
+    }
+
+    let mut literals = Vec::new();
+    for pattern in patterns {
+        let hir = if options.fixed_strings {
+            Hir::literal(pattern.as_bytes())
+        } else {
+            ParserBuilder::new().unicode(!options.ascii_only).utf8(false).build().parse(pattern).ok()?
+        };
+        literals.extend(required(&hir)?);
+    }
+    let shortest = literals.iter().map(Vec::len).min()?;
+    if shortest < MIN_LITERAL_LEN || literals.len() > MAX_LITERALS {
+        return None;
+    }
+    Some(literals)
+}
+
+/// Returns literals one of which is in every match of the regex.
+fn required(hir: &Hir) -> Option<Vec<Vec<u8>>> {
+    match hir.kind() {
+        HirKind::Literal(literal) => Some(vec![literal.0.to_vec()]),
+        HirKind::Capture(capture) => required(&capture.sub),
+        HirKind::Repetition(repetition) if repetition.min > 0 => required(&repetition.sub),
+        HirKind::Concat(parts) => {
+            // The parts matching a few strings are joined, like
+            // `user (id|name)` into `user id` and `user name`
+            let mut sets = Vec::new();
+            let mut joined: Option<Vec<Vec<u8>>> = None;
+            for part in parts {
+                match (exact(part), joined.take()) {
+                    (Some(strings), Some(previous)) => match join(&previous, &strings) {
+                        Some(both) => joined = Some(both),
+                        None => {
+                            sets.push(previous);
+                            joined = Some(strings);
+                        }
+                    },
+                    (Some(strings), None) => joined = Some(strings),
+                    (None, previous) => {
+                        sets.extend(previous);
+                        sets.extend(required(part));
+                    }
+                }
+            }
+            sets.extend(joined);
+            // Any of the sets will do, the one with the longest
+            // literals is likely found on the fewest lines
+            sets.into_iter()
+                .filter(|literals| !literals.is_empty())
+                .max_by_key(|literals| (literals.iter().map(Vec::len).min(), Reverse(literals.len())))
+        }
+        HirKind::Alternation(branches) => {
+            let literals = branches.iter().map(required).collect::<Option<Vec<_>>>()?;
+            Some(literals.concat())
+        }
+        _ => None,
+    }
+}
+
+/// Returns all the strings the regex matches, if there are only a few.
+fn exact(hir: &Hir) -> Option<Vec<Vec<u8>>> {
+    match hir.kind() {
+        HirKind::Empty => Some(vec![Vec::new()]),
+        HirKind::Literal(literal) => Some(vec![literal.0.to_vec()]),
+        HirKind::Capture(capture) => exact(&capture.sub),
+        HirKind::Concat(parts) => parts.iter().try_fold(vec![Vec::new()], |joined, part| join(&joined, &exact(part)?)),
+        HirKind::Alternation(branches) => {
+            let strings = branches.iter().map(exact).collect::<Option<Vec<_>>>()?.concat();
+            (strings.len() <= MAX_LITERALS).then_some(strings)
+        }
+        _ => None,
+    }
+}
+
+/// Returns each of the strings followed by each of the others,
+/// if there aren't too many.
+fn join(strings: &[Vec<u8>], others: &[Vec<u8>]) -> Option<Vec<Vec<u8>>> {
+    if strings.len() * others.len() > MAX_LITERALS {
+        return None;
+    }
+    Some(strings.iter().flat_map(|string| others.iter().map(move |other| [&string[..], other].concat())).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literals(patterns: &[&str], options: &MatcherOptions) -> Option<Vec<String>> {
+        let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        let literals = required_literals(&patterns, options)?;
+        Some(literals.into_iter().map(|literal| String::from_utf8(literal).unwrap()).collect())
+    }
+
+    fn sensitive(patterns: &[&str]) -> Option<Vec<String>> {
+        literals(patterns, &MatcherOptions::default())
+    }
+
+    #[test]
+    fn literal_patterns() {
+        assert_eq!(sensitive(&["error"]), Some(vec!["error".to_string()]));
+        assert_eq!(sensitive(&["error", "warning"]), Some(vec!["error".to_string(), "warning".to_string()]));
+        // Too short to save anything
+        assert_eq!(sensitive(&["ab"]), None);
+        assert_eq!(sensitive(&["error", "ab"]), None);
+    }
+
+    #[test]
+    fn alternations() {
+        assert_eq!(sensitive(&["foo|bar"]), Some(vec!["foo".to_string(), "bar".to_string()]));
+        assert_eq!(sensitive(&["user (id|name)"]), Some(vec!["user id".to_string(), "user name".to_string()]));
+        // A branch without literals can match anything
+        assert_eq!(sensitive(&[r"foo|\d+"]), None);
+        assert_eq!(sensitive(&["foo|ba"]), None);
+    }
+
+    #[test]
+    fn classes_and_repetitions() {
+        assert_eq!(sensitive(&["[ab]cde"]), Some(vec!["cde".to_string()]));
+        assert_eq!(sensitive(&[r"\d+ errors?"]), Some(vec![" error".to_string()]));
+        assert_eq!(sensitive(&["(abc)+x"]), Some(vec!["abc".to_string()]));
+        assert_eq!(sensitive(&["[a-z]+"]), None);
+        assert_eq!(sensitive(&["(abc)*x"]), None);
+        assert_eq!(sensitive(&["abc.*defgh"]), Some(vec!["defgh".to_string()]));
+    }
+
+    #[test]
+    fn fixed_strings_are_not_parsed() {
+        let options = MatcherOptions {
+            fixed_strings: true,
+            ..MatcherOptions::default()
+        };
+        assert_eq!(literals(&["a.b|c"], &options), Some(vec!["a.b|c".to_string()]));
+    }
+
+    #[test]
+    fn case_insensitive_patterns() {
+        for case in [CaseMode::Insensitive, CaseMode::Smart] {
+            let options = MatcherOptions {
+                case,
+                ..MatcherOptions::default()
+            };
+            assert_eq!(literals(&["error"], &options), None);
+        }
+        // The flag in the pattern makes a class of each letter
+        assert_eq!(sensitive(&["(?i)error"]), None);
+        assert_eq!(sensitive(&["(?i:x)error"]), Some(vec!["error".to_string()]));
+    }
+}
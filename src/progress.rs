@@ -0,0 +1,498 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, IsTerminal, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use console::Term;
+use indicatif::style::ProgressTracker;
+use indicatif::{FormattedDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use serde::Serialize;
+
+use crate::json;
+
+use crate::search;
+
+/// The template of the bars, after the path of their file.
+const BAR_TEMPLATE: &str = "{wide_msg} [{bar:20}] {percent:>3}% {bytes:>10}/{total_bytes:<10} {bytes_per_sec:>12}";
+/// The template of the bar of stdin, whose size isn't known.
+const STDIN_TEMPLATE: &str = "{wide_msg} {bytes:>10} {bytes_per_sec:>12}";
+/// The template of the bar of all the files, with the rates of the
+/// last few seconds and the time left under it, see [`RateWindow`].
+const TOTAL_TEMPLATE: &str = "{wide_msg} [{bar:20}] {percent:>3}% {bytes:>10}/{total_bytes:<10}\n  {rates}";
+
+/// How many times a second the bars are drawn at most.
+const DRAW_RATE: u8 = 20;
+/// How often the lines kept while the bars are shown are written.
+const PRINT_INTERVAL: Duration = Duration::from_millis(1000 / DRAW_RATE as u64);
+/// The most bytes of lines kept while the bars are shown.
+const PRINT_BUFFER_LEN: usize = 64 << 10;
+/// How often the records of --progress-json are written for a file.
+const RECORD_INTERVAL: Duration = Duration::from_secs(1);
+/// How far back the rates of the total are measured.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+/// How often the reads are sampled for the rates.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The bars being shown, which are cleared while lines are
+/// printed to stdout, see [`suspend`].
+static SHOWN: OnceLock<MultiProgress> = OnceLock::new();
+
+/// True once stdout is closed by its reader, like head does.
+static STDOUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// True if stdout is a terminal, which is then the one of the bars.
+static STDOUT_TERMINAL: OnceLock<bool> = OnceLock::new();
+
+/// The lines printed to stdout while the bars are shown, which are
+/// written together so that the bars aren't cleared and drawn again
+/// for each line, see [`print`].
+static PRINTED: Mutex<Printed> = Mutex::new(Printed { lines: Vec::new(), written: None });
+
+struct Printed {
+    lines: Vec<u8>,
+    /// When the lines were last written.
+    written: Option<Instant>,
+}
+
+/// The progress bars shown on stderr while the files are read, with
+/// --progress: one bar for each file being read, with how much of its
+/// compressed data is read and how fast, and one more for all the
+/// files at the bottom. The bar of a file is removed once it is done.
+///
+/// The bars are only drawn as the files are read, so nothing runs
+/// while the reads wait, and a file that fails ends its bar like one
+/// that is done, as its reader is dropped either way.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    bars: MultiProgress,
+    total: ProgressBar,
+    /// The bytes decoded from all the files.
+    decoded: Arc<AtomicU64>,
+    /// Held while a file is added, so that the total is shown and
+    /// started again only once.
+    adding: Arc<Mutex<()>>,
+    /// Write the records of --progress-json instead of the bars.
+    json: bool,
+}
+
+impl Default for Progress {
+    fn default() -> Progress {
+        Progress::new()
+    }
+}
+
+impl Progress {
+    /// Returns the bars, which are only drawn when stderr is a terminal.
+    pub fn new() -> Progress {
+        Progress::with_draw_target(ProgressDrawTarget::stderr())
+    }
+
+    /// Returns the bars, which are drawn even when stderr isn't a
+    /// terminal, for --progress.
+    pub fn forced() -> Progress {
+        Progress::with_draw_target(ProgressDrawTarget::term_like_with_hz(Box::new(Term::buffered_stderr()), DRAW_RATE))
+    }
+
+    /// Returns the progress of --progress-json, which writes records
+    /// of the files being read on stderr instead of drawing bars.
+    pub fn json() -> Progress {
+        Progress {
+            json: true,
+            ..Progress::with_draw_target(ProgressDrawTarget::hidden())
+        }
+    }
+
+    /// Returns the bars of --progress and --no-progress, which are
+    /// shown by default when stderr is a terminal.
+    pub fn from_flags(progress: bool, no_progress: bool) -> Option<Progress> {
+        if no_progress {
+            None
+        } else if progress {
+            Some(Progress::forced())
+        } else if io::stderr().is_terminal() {
+            Some(Progress::new())
+        } else {
+            None
+        }
+    }
+
+    fn with_draw_target(target: ProgressDrawTarget) -> Progress {
+        let hidden = target.is_hidden();
+        let bars = MultiProgress::with_draw_target(target);
+        let decoded = Arc::new(AtomicU64::new(0));
+        let rates = RateWindow {
+            decoded: decoded.clone(),
+            samples: VecDeque::new(),
+        };
+        // The total is only shown once a file is added, so that
+        // nothing is drawn when no file can be read
+        let total = ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::hidden());
+        let total = total.with_style(style(TOTAL_TEMPLATE).with_key("rates", rates));
+        total.set_message("total");
+        if !hidden {
+            let _ = SHOWN.set(bars.clone());
+        }
+        Progress {
+            bars,
+            total,
+            decoded,
+            adding: Arc::default(),
+            json: false,
+        }
+    }
+
+    /// Adds the bar of a file with len bytes of compressed data, whose
+    /// bytes are added to the total too. The bar shows the decoded
+    /// data instead when its decoded size is known, like from the
+    /// headers of the zstd frames. Stdin, whose size isn't known,
+    /// only shows how many bytes are read.
+    pub fn add_file(&self, file_path: &str, len: Option<u64>, decoded_len: Option<u64>) -> FileProgress {
+        // The files searched again with --watch start a new total
+        let _adding = self.adding.lock().unwrap();
+        if self.total.is_hidden() {
+            self.bars.add(self.total.clone());
+        } else if self.total.is_finished() {
+            self.total.reset();
+            self.total.set_length(0);
+            self.decoded.store(0, Ordering::Relaxed);
+        }
+        if let Some(len) = len {
+            self.total.inc_length(len);
+        }
+        let bar = match decoded_len.or(len) {
+            Some(len) => ProgressBar::new(len).with_style(style(BAR_TEMPLATE)),
+            None => ProgressBar::no_length().with_style(style(STDIN_TEMPLATE)),
+        };
+        let bar = self.bars.insert_before(&self.total, bar);
+        bar.set_message(search::display_path(file_path).to_string());
+        let records = self.json.then(|| Records {
+            file_path: file_path.to_string(),
+            started: Instant::now(),
+            written: Mutex::new(None),
+        });
+        FileProgress {
+            bar,
+            total: self.total.clone(),
+            decoded: self.decoded.clone(),
+            len,
+            read: AtomicU64::new(0),
+            decoded_len,
+            records,
+        }
+    }
+
+    /// Removes the bars once all the files are done.
+    /// Adding files afterwards shows the bars again.
+    pub fn finish(&self) {
+        let _ = flush_printed(false);
+        self.total.finish_and_clear();
+    }
+
+    /// Removes the bars of the files still being read, before
+    /// rzstd exits without waiting for them.
+    pub fn clear(&self) {
+        let _ = flush_printed(false);
+        self.total.finish_and_clear();
+        let _ = self.bars.clear();
+    }
+}
+
+/// Returns the style of a bar. The templates are the ones above,
+/// which are valid.
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ")
+}
+
+/// The bar of a single file, see [`Progress::add_file`].
+#[derive(Debug)]
+pub struct FileProgress {
+    bar: ProgressBar,
+    total: ProgressBar,
+    /// The bytes decoded from all the files.
+    decoded: Arc<AtomicU64>,
+    len: Option<u64>,
+    /// The compressed bytes read so far.
+    read: AtomicU64,
+    decoded_len: Option<u64>,
+    records: Option<Records>,
+}
+
+/// When the records of --progress-json of a file are written.
+#[derive(Debug)]
+struct Records {
+    file_path: String,
+    started: Instant,
+    /// When the last record was written, if any.
+    written: Mutex<Option<Instant>>,
+}
+
+/// A record of --progress-json, written on stderr as a line of JSON.
+/// The bytes are the compressed data of the file, whose total isn't
+/// known for stdin.
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    file: &'a str,
+    bytes_read: u64,
+    total: Option<u64>,
+    percent: Option<f64>,
+    /// The bytes read per second since the file was opened.
+    rate: f64,
+    /// True for the last record of the file.
+    done: bool,
+}
+
+impl FileProgress {
+    fn inc(&self, read: u64) {
+        self.read.fetch_add(read, Ordering::Relaxed);
+        if self.decoded_len.is_none() {
+            self.bar.inc(read);
+        }
+        if self.len.is_some() {
+            self.total.inc(read);
+        }
+        self.write_record(false);
+        // The lines of a file that matches rarely aren't kept long
+        let _ = flush_printed(true);
+    }
+
+    /// Writes a record of --progress-json, at most once a second
+    /// while the file is read, and once it is done.
+    fn write_record(&self, done: bool) {
+        let Some(records) = &self.records else {
+            return;
+        };
+        // The chunks of a file share its records, one of them is enough
+        let Ok(mut written) = records.written.try_lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if !done && written.is_some_and(|written| now.duration_since(written) < RECORD_INTERVAL) {
+            return;
+        }
+        *written = Some(now);
+
+        let bytes_read = self.read.load(Ordering::Relaxed);
+        let secs = now.duration_since(records.started).as_secs_f64();
+        let record = Record {
+            file: search::display_path(&records.file_path),
+            bytes_read,
+            total: self.len,
+            percent: self.len.filter(|len| *len > 0).map(|len| bytes_read as f64 * 100.0 / len as f64),
+            rate: if secs > 0.0 { bytes_read as f64 / secs } else { 0.0 },
+            done,
+        };
+        if let Ok(line) = json::encode(&record) {
+            let _ = io::stderr().lock().write_all(&line);
+        }
+    }
+
+    fn inc_decoded(&self, decoded: u64) {
+        self.decoded.fetch_add(decoded, Ordering::Relaxed);
+        if self.decoded_len.is_some() {
+            self.bar.inc(decoded);
+        }
+    }
+}
+
+impl Drop for FileProgress {
+    /// The rest of a file that isn't read whole, like with -m, is
+    /// still added to the total, so that the total ends at 100%.
+    fn drop(&mut self) {
+        self.write_record(true);
+        if let Some(len) = self.len {
+            self.total.inc(len.saturating_sub(self.read.load(Ordering::Relaxed)));
+        }
+        self.bar.finish_and_clear();
+    }
+}
+
+/// Reads the compressed data of a file and moves its progress bar,
+/// which the readers of the chunks of a file share. Without a bar,
+/// the data is read as it is.
+pub(crate) struct ProgressReader<R> {
+    inner: R,
+    progress: Option<Arc<FileProgress>>,
+}
+
+impl<R> ProgressReader<R> {
+    pub(crate) fn new(inner: R, progress: Option<Arc<FileProgress>>) -> ProgressReader<R> {
+        ProgressReader { inner, progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(progress) = &self.progress {
+            progress.inc(read as u64);
+        }
+        Ok(read)
+    }
+}
+
+/// Reads the decoded data of a file, which moves its progress bar
+/// when its decoded size is known, and is counted for the rates of
+/// the total.
+pub(crate) struct DecodedReader<R> {
+    inner: R,
+    progress: Option<Arc<FileProgress>>,
+}
+
+impl<R> DecodedReader<R> {
+    pub(crate) fn new(inner: R, progress: Option<Arc<FileProgress>>) -> DecodedReader<R> {
+        DecodedReader { inner, progress }
+    }
+}
+
+impl<R: Read> Read for DecodedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(progress) = &self.progress {
+            progress.inc_decoded(read as u64);
+        }
+        Ok(read)
+    }
+}
+
+/// Shows the rates of the total, of the compressed data and of the
+/// decoded data, and the time left at the compressed rate. They are
+/// measured over the last few seconds rather than since the start,
+/// so that they follow the files being read, which can be decoded
+/// much faster or slower than the ones before.
+#[derive(Debug, Clone)]
+struct RateWindow {
+    decoded: Arc<AtomicU64>,
+    /// When the reads were sampled, with the compressed and the
+    /// decoded bytes read by then, the oldest first.
+    samples: VecDeque<(Instant, u64, u64)>,
+}
+
+impl RateWindow {
+    /// Returns the rates in bytes per second, once they can be told.
+    fn rates(&self) -> Option<(f64, f64)> {
+        let (first, last) = (self.samples.front()?, self.samples.back()?);
+        let secs = last.0.duration_since(first.0).as_secs_f64();
+        if secs == 0.0 {
+            return None;
+        }
+        // The total starts again at 0 with --watch
+        let (read, decoded) = (last.1.saturating_sub(first.1), last.2.saturating_sub(first.2));
+        Some((read as f64 / secs, decoded as f64 / secs))
+    }
+}
+
+impl ProgressTracker for RateWindow {
+    fn clone_box(&self) -> Box<dyn ProgressTracker> {
+        Box::new(self.clone())
+    }
+
+    fn tick(&mut self, state: &ProgressState, now: Instant) {
+        if self.samples.back().is_some_and(|last| now.duration_since(last.0) < SAMPLE_INTERVAL) {
+            return;
+        }
+        while self.samples.len() > 1 && self.samples.front().is_some_and(|first| now.duration_since(first.0) > RATE_WINDOW) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, state.pos(), self.decoded.load(Ordering::Relaxed)));
+    }
+
+    fn reset(&mut self, _: &ProgressState, _: Instant) {
+        self.samples.clear();
+    }
+
+    fn write(&self, state: &ProgressState, w: &mut dyn fmt::Write) {
+        let Some((read, decoded)) = self.rates() else {
+            return;
+        };
+        let _ = write!(w, "{:.1} MB/s, {:.1} MB/s decoded", read / 1e6, decoded / 1e6);
+        let left = state.len().unwrap_or(0).saturating_sub(state.pos());
+        if read > 0.0 && left > 0 {
+            let _ = write!(w, ", ETA {}", FormattedDuration(Duration::from_secs_f64(left as f64 / read)));
+        }
+    }
+}
+
+/// Returns the bars while they are drawn on the terminal of stdout.
+/// The lines written to a pipe or a file never mix with the bars.
+fn shown() -> Option<&'static MultiProgress> {
+    let stdout_terminal = *STDOUT_TERMINAL.get_or_init(|| io::stdout().is_terminal());
+    SHOWN.get().filter(|bars| stdout_terminal && !bars.is_hidden())
+}
+
+/// Runs f with the progress bars cleared, for f to write to stdout.
+/// When stdout is the terminal of the bars, the lines would otherwise
+/// be cleared with the bars the next time they are drawn. Otherwise
+/// f just runs.
+pub(crate) fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    match shown() {
+        Some(bars) => bars.suspend(f),
+        None => f(),
+    }
+}
+
+/// Prints the lines to stdout at once. While the bars are drawn, the
+/// lines are kept and written together with the bars cleared, at most
+/// as often as the bars are drawn, as clearing and drawing the bars
+/// again for each line is much slower than writing it.
+pub(crate) fn print(lines: &[u8]) -> io::Result<()> {
+    if stdout_closed() {
+        return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+    }
+    if shown().is_none() {
+        return closing(io::stdout().lock().write_all(lines));
+    }
+    let mut printed = PRINTED.lock().unwrap_or_else(|e| e.into_inner());
+    printed.lines.extend_from_slice(lines);
+    if printed.lines.len() < PRINT_BUFFER_LEN && printed.written.is_some_and(|written| written.elapsed() < PRINT_INTERVAL) {
+        return Ok(());
+    }
+    write_printed(&mut printed)
+}
+
+/// Writes the lines kept by [`print`], or only once they are kept
+/// for long enough when due.
+fn flush_printed(due: bool) -> io::Result<()> {
+    let mut printed = PRINTED.lock().unwrap_or_else(|e| e.into_inner());
+    if printed.lines.is_empty() || due && printed.written.is_some_and(|written| written.elapsed() < PRINT_INTERVAL) {
+        return Ok(());
+    }
+    write_printed(&mut printed)
+}
+
+fn write_printed(printed: &mut Printed) -> io::Result<()> {
+    printed.written = Some(Instant::now());
+    let lines = std::mem::take(&mut printed.lines);
+    closing(suspend(|| io::stdout().lock().write_all(&lines)))
+}
+
+/// Returns true once a line couldn't be printed as the reader of
+/// stdout is gone. The search can then stop, without any error.
+pub fn stdout_closed() -> bool {
+    STDOUT_CLOSED.load(Ordering::Relaxed)
+}
+
+/// Remembers if a write to stdout failed as it is closed.
+fn closing(written: io::Result<()>) -> io::Result<()> {
+    if written.as_ref().is_err_and(|e| e.kind() == io::ErrorKind::BrokenPipe) {
+        STDOUT_CLOSED.store(true, Ordering::Relaxed);
+    }
+    written
+}
+
+/// Writes to stdout with the progress bars cleared, see [`suspend`],
+/// like the decoded data of `rzstd cat`.
+pub struct SuspendedWriter<W>(pub W);
+
+impl<W: Write> Write for SuspendedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        suspend(|| self.0.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        suspend(|| self.0.flush())
+    }
+}
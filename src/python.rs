@@ -0,0 +1,142 @@
+//! The `rzstd` Python module, built with the `python` feature.
+
+use std::fs::File;
+use std::ops::Range;
+
+use clap::ValueEnum;
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::config::SearchBuilder;
+use crate::format::Format;
+use crate::matcher::{CaseMode, MatcherOptions};
+use crate::matches::{self, Matches};
+
+/// A matched line of a searched file.
+#[pyclass(name = "Match", frozen, get_all)]
+struct PyMatch {
+    /// The searched file.
+    file: String,
+    /// The 1-based number of the line.
+    line_number: u64,
+    /// The offset of the line in the decompressed data.
+    byte_offset: u64,
+    /// The line, without its terminator.
+    line: Vec<u8>,
+    /// Where the patterns match in the line, as (start, end) offsets.
+    matches: Vec<(usize, usize)>,
+}
+
+#[pymethods]
+impl PyMatch {
+    /// Returns the line decoded as UTF-8, with the invalid bytes replaced.
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.line).into_owned()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Match(file={:?}, line_number={}, line={:?})", self.file, self.line_number, self.text())
+    }
+}
+
+/// The matched lines of a file, decoded and searched as they are iterated.
+#[pyclass(name = "Matches", unsendable)]
+struct PyMatches {
+    file: String,
+    matches: Matches<'static>,
+}
+
+#[pymethods]
+impl PyMatches {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<PyMatch>> {
+        if let Some(found) = self.matches.next() {
+            return Ok(Some(PyMatch {
+                file: self.file.clone(),
+                line_number: found.line_number,
+                byte_offset: found.byte_offset,
+                line: found.line,
+                matches: found.matches.into_iter().map(|Range { start, end }| (start, end)).collect(),
+            }));
+        }
+        match self.matches.error() {
+            Some(e) => Err(PyOSError::new_err(format!("Error searching file {}: {}", self.file, e))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Searches a file, compressed in any of the supported formats or not,
+/// for the regex pattern and returns an iterator of its matched lines.
+#[pyfunction]
+#[pyo3(signature = (
+    path,
+    pattern,
+    *,
+    ignore_case = false,
+    smart_case = false,
+    fixed_strings = false,
+    word = false,
+    invert_match = false,
+    max_count = None,
+    null_data = false,
+    format = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn search(
+    path: String,
+    pattern: String,
+    ignore_case: bool,
+    smart_case: bool,
+    fixed_strings: bool,
+    word: bool,
+    invert_match: bool,
+    max_count: Option<u64>,
+    null_data: bool,
+    format: Option<&str>,
+) -> PyResult<PyMatches> {
+    let case = match (ignore_case, smart_case) {
+        (true, _) => CaseMode::Insensitive,
+        (false, true) => CaseMode::Smart,
+        (false, false) => CaseMode::Sensitive,
+    };
+    let mut builder = SearchBuilder::new()
+        .pattern(pattern)
+        .matcher_options(MatcherOptions {
+            case,
+            fixed_strings,
+            word,
+            ..MatcherOptions::default()
+        })
+        .invert_match(invert_match)
+        .null_data(null_data);
+    if let Some(max_count) = max_count {
+        builder = builder.max_count(max_count);
+    }
+    if let Some(format) = format {
+        match Format::from_str(format, true) {
+            Ok(format) => builder = builder.format(format),
+            Err(e) => return Err(PyValueError::new_err(format!("Error parsing format {}: {}", format, e))),
+        }
+    }
+    let config = builder.build().map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => return Err(PyOSError::new_err(format!("Error opening file {}: {}", path, e))),
+    };
+    let matches = matches::search_input(&config, file, Some(&path)).map_err(|e| PyOSError::new_err(e.to_string()))?;
+    Ok(PyMatches { file: path, matches })
+}
+
+/// grep over compressed files.
+#[pymodule]
+fn rzstd(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyMatch>()?;
+    module.add_class::<PyMatches>()?;
+    module.add_function(wrap_pyfunction!(search, module)?)?;
+    Ok(())
+}
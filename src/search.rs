@@ -0,0 +1,1344 @@
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::str;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+
+use memmap2::Mmap;
+use grep_matcher::LineTerminator;
+use grep_searcher::{BinaryDetection, Searcher, SearcherBuilder, Sink, SinkContext, SinkFinish, SinkMatch};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use encoding_rs::Encoding;
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+
+use crate::archive::{self, FileArchive};
+use crate::cache::BlockCache;
+use crate::cli;
+use crate::color::ColorConfig;
+use crate::config::SearchConfig;
+use crate::format::{self, Format, ZstdParams};
+use crate::frames;
+use crate::output::{self, FileOutput};
+use crate::prefilter::LineMatcher;
+use crate::progress::{DecodedReader, FileProgress, Progress, ProgressReader};
+#[cfg(feature = "native")]
+use crate::parallel;
+#[cfg(feature = "native")]
+use crate::index;
+#[cfg(feature = "native")]
+use crate::seekable;
+#[cfg(feature = "native")]
+use crate::output::ChunkLines;
+use crate::sink::{self, OutputSink, SinkFactory, SinkLine};
+use crate::skippable::SkippableFrames;
+use crate::walk::GlobFilter;
+
+/// The most bytes the nested archives of a file decode to by
+/// default, see --max-expansion.
+pub const DEFAULT_MAX_EXPANSION: u64 = 1 << 30;
+
+/// What is printed of the files whose decoded data has NUL bytes,
+/// which grep takes for binary data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BinaryMode {
+    /// Print the matched lines before the first NUL byte, then
+    /// `binary file <name> matches` at the first match after it
+    /// instead of the binary lines, and stop searching the file.
+    #[default]
+    Suppress,
+    /// Print the matched lines of the binary data too, which are
+    /// split at the NUL bytes as well as at the line terminators.
+    Search,
+    /// Search the binary data as text, and print its lines as they are.
+    Text,
+}
+
+/// What is done with the lines that aren't valid UTF-8, like the
+/// latin-1 lines of the older logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum InvalidUtf8 {
+    /// Keep the lines. The --json messages replace their invalid
+    /// sequences with U+FFFD, and the other lines are printed as
+    /// they are.
+    #[default]
+    Lossy,
+    /// Skip the lines, as if they didn't match. They aren't printed
+    /// as context lines either.
+    Skip,
+}
+
+/// Options that change how each file is searched.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// Print the lines that don't match instead of the ones that do.
+    pub invert_match: bool,
+    /// Only count the matched lines instead of printing them.
+    pub count: bool,
+    /// Only count every match instead of printing the lines.
+    pub count_matches: bool,
+    /// Only print the names of the files that match.
+    pub files_with_matches: bool,
+    /// Only print the names of the files that don't match.
+    pub files_without_match: bool,
+    /// Stop searching a file after this many matched lines.
+    pub max_count: Option<u64>,
+    /// Prefix each printed line with its line number.
+    pub line_number: bool,
+    /// Prefix each printed line with the column of its first match.
+    pub column: bool,
+    /// Prefix each printed line with its byte offset in the decompressed data.
+    pub byte_offset: bool,
+    /// Print each match on its own line instead of the whole line.
+    pub only_matching: bool,
+    /// The number of lines to print after each match.
+    pub after_context: usize,
+    /// The number of lines to print before each match.
+    pub before_context: usize,
+    /// Print nothing and stop all the searches at the first match.
+    pub quiet: bool,
+    /// Prefix each printed line with the path of its file.
+    pub with_filename: bool,
+    /// Search the files that symlinks point to.
+    pub follow_links: bool,
+    /// The styles of the printed text.
+    pub colors: ColorConfig,
+    /// Print the results as JSON Lines, like ripgrep's --json.
+    pub json: bool,
+    /// Print each match as `file:line:column:text` for editors.
+    pub vimgrep: bool,
+    /// Print the path of each file once above its lines,
+    /// instead of before each line.
+    pub heading: bool,
+    /// The lines are separated by NUL bytes instead of newlines,
+    /// both in the searched data and in the output.
+    pub null_data: bool,
+    /// Search the whole decoded data of each file at once, for the
+    /// matches that span several lines. It follows the matcher
+    /// options when the search is built.
+    pub multiline: bool,
+    /// What is printed of the binary data, see -a and --binary.
+    pub binary: BinaryMode,
+    /// What is done with the lines that aren't valid UTF-8.
+    pub invalid_utf8: InvalidUtf8,
+    /// The text encoding of the decoded data, like UTF-16LE, which
+    /// is transcoded to UTF-8 before it is searched. None for UTF-8.
+    pub encoding: Option<&'static Encoding>,
+    /// Print this instead of each match, with the capture
+    /// groups like `$1` or `${name}` expanded.
+    pub replace: Option<String>,
+    /// Write the lines of each file to a file in this directory
+    /// instead of stdout.
+    pub output_dir: Option<PathBuf>,
+    /// The format of all the files, instead of telling it
+    /// from their extension.
+    pub format: Option<Format>,
+    /// The dictionary the zstd files were compressed with.
+    /// It is shared by all the tasks instead of being copied.
+    pub dictionary: Option<Arc<Vec<u8>>>,
+    /// The log2 of the largest window the zstd frames can use,
+    /// instead of the 2^27 bytes zstd accepts by default.
+    pub max_window: Option<u32>,
+    /// Print the skippable frames of the zstd files to stderr.
+    pub show_skippable: bool,
+    /// The levels of archives and compressed files found inside
+    /// archives that are opened too, 0 to search the members as they are.
+    pub search_archives: usize,
+    /// The most bytes decoded from the nested archives of a file.
+    pub max_expansion: u64,
+    /// The globs given with --member-glob, which select the
+    /// members of the archives that are searched.
+    pub member_globs: GlobFilter,
+    /// Makes the sinks that get the lines of the files instead
+    /// of printing them, for the programs using the library.
+    pub sink: Option<SinkFactory>,
+    /// The number of threads a single file is searched with,
+    /// or None for the number of CPUs.
+    pub threads: Option<usize>,
+    /// The number of threads the frames of a zstd file are decoded
+    /// with, 0 or 1 to decode them on the thread searching the file.
+    pub decompress_threads: usize,
+    /// Memory map the files instead of reading them.
+    pub mmap: bool,
+    /// Keeps the decoded data of the files for the next searches.
+    pub cache: Option<BlockCache>,
+    /// The directory of the indexes of the files, instead of
+    /// next to them.
+    pub index_dir: Option<PathBuf>,
+    /// Wait for more data to be appended to the files once their
+    /// end is reached, like `tail -f`, instead of ending the search.
+    pub follow_tail: bool,
+    /// The progress bars of the files, shown on stderr.
+    pub progress: Option<Progress>,
+}
+
+impl SearchOptions {
+    /// Returns true if the matched lines are printed, as opposed to
+    /// only printing a summary of each file like -c and -l do.
+    pub fn prints_lines(&self) -> bool {
+        !(self.quiet
+            || self.count
+            || self.count_matches
+            || self.files_with_matches
+            || self.files_without_match)
+    }
+
+    /// Returns true if the chunks of a seekable zstd file can be
+    /// searched in parallel. Line numbers, byte offsets, context
+    /// lines and --max-count depend on the lines before each line,
+    /// which the chunks don't know about.
+    #[cfg(feature = "native")]
+    fn searches_chunks(&self) -> bool {
+        !(self.line_number
+            || self.byte_offset
+            || self.after_context > 0
+            || self.before_context > 0
+            || self.max_count.is_some()
+            || self.json
+            || self.show_skippable
+            || self.follow_tail
+            || self.sink.is_some()
+            || self.encoding.is_some()
+            || self.multiline)
+            && self.format.is_none_or(|format| format == Format::Zstd)
+    }
+
+    /// Returns true if the frames of the indexed zstd files that can't
+    /// have matches can be skipped. The chunks of frames that are
+    /// searched know their line numbers and byte offsets from the
+    /// index, but not the context lines before them, the lines before
+    /// them counted by --max-count, or the lines that don't match.
+    #[cfg(feature = "native")]
+    fn skips_frames(&self) -> bool {
+        !(self.invert_match
+            || self.after_context > 0
+            || self.before_context > 0
+            || self.max_count.is_some()
+            || self.json
+            || self.show_skippable
+            || self.follow_tail
+            || self.sink.is_some()
+            || self.encoding.is_some()
+            || self.multiline)
+            && self.format.is_none_or(|format| format == Format::Zstd)
+    }
+
+    /// Returns how the searcher handles the NUL bytes. They end the
+    /// lines with -z, so they aren't binary data, and the counts of
+    /// the lines are the same whatever the data.
+    fn binary_detection(&self) -> BinaryDetection {
+        let detects = match self.binary {
+            BinaryMode::Suppress => self.prints_lines(),
+            BinaryMode::Search => true,
+            BinaryMode::Text => false,
+        };
+        match detects && !self.null_data {
+            true => BinaryDetection::convert(b'\0'),
+            false => BinaryDetection::none(),
+        }
+    }
+
+    /// Returns true if the line is skipped for not being valid UTF-8.
+    pub(crate) fn skips_line(&self, line: &[u8]) -> bool {
+        self.invalid_utf8 == InvalidUtf8::Skip && str::from_utf8(line).is_err()
+    }
+
+    /// Returns the byte that ends the searched and printed lines.
+    pub fn line_terminator(&self) -> u8 {
+        if self.null_data {
+            b'\0'
+        } else {
+            b'\n'
+        }
+    }
+
+    /// Returns how the zstd frames of the files are decoded.
+    pub fn zstd(&self) -> ZstdParams<'_> {
+        ZstdParams {
+            dictionary: self.dictionary.as_deref().map(Vec::as_slice),
+            window_log_max: self.max_window,
+        }
+    }
+}
+
+/// Where the searched data starts in the decompressed data of its
+/// file, for the line numbers and byte offsets of the chunks of its
+/// frames. It is zero when the whole file is searched.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LineStart {
+    /// The number of lines before the searched data.
+    pub lines_before: u64,
+    /// The offset of the first searched line.
+    pub byte_offset: u64,
+}
+
+/// Stops the searches of all the files once cancelled.
+/// Clones share the same state, so every task gets a clone.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Stops all the searches that use this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if the searches should stop.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Wraps a reader to stop reading once the token is cancelled.
+/// The reader then acts as if the input ended, so the decoder
+/// and the searcher stop at their next read.
+struct CancellableReader<R> {
+    inner: R,
+    cancel: CancelToken,
+}
+
+impl<R: Read> Read for CancellableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.is_cancelled() {
+            return Ok(0);
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// How long a followed file is waited for between two reads once its
+/// end is reached.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Reads a file that is still being written to, like `tail -f`. Once
+/// the end of the file is reached, it waits for more data instead of
+/// ending, until the search is cancelled. A truncated file, like a log
+/// rotated in place, is an error, as its data before is gone.
+struct FollowReader {
+    file: File,
+    /// The offset of the next byte to read.
+    offset: u64,
+    cancel: CancelToken,
+}
+
+impl FollowReader {
+    fn new(file: File, cancel: CancelToken) -> FollowReader {
+        FollowReader { file, offset: 0, cancel }
+    }
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 || buf.is_empty() || self.cancel.is_cancelled() {
+                self.offset += read as u64;
+                return Ok(read);
+            }
+            if self.file.metadata()?.len() < self.offset {
+                return Err(io::Error::other("the file was truncated while it was followed"));
+            }
+            std::thread::sleep(FOLLOW_INTERVAL);
+        }
+    }
+}
+
+/// Adds the progress bar of a file, or of stdin without a file, when
+/// the progress is shown. The decoded size of a zstd file is read from
+/// the headers of its frames, unless the file is followed and grows.
+fn file_progress(file_path: &str, file: Option<&File>, options: &SearchOptions) -> Option<Arc<FileProgress>> {
+    let progress = options.progress.as_ref()?;
+    let len = file.and_then(|file| file.metadata().ok()).map(|metadata| metadata.len());
+    let decoded_len = match file {
+        Some(file) if !options.follow_tail && options.format.is_none_or(|format| format == Format::Zstd) => {
+            frames::content_size(file).ok().flatten()
+        }
+        _ => None,
+    };
+    Some(Arc::new(progress.add_file(file_path, len, decoded_len)))
+}
+
+/// Wraps a reader to count the bytes read from it.
+/// The count is shared, so that it can still be read once
+/// the reader is moved into a decoder.
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Wraps the data decoded from nested archives and compressed files
+/// to count it, and fails once more than the limit of --max-expansion
+/// is decoded, which guards against decompression bombs. The count is
+/// shared by all the nested data of a searched file.
+struct ExpansionGuard<R> {
+    inner: R,
+    expanded: Arc<AtomicU64>,
+    limit: u64,
+}
+
+impl<R: Read> Read for ExpansionGuard<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let expanded = self.expanded.fetch_add(read as u64, Ordering::Relaxed) + read as u64;
+        if expanded > self.limit {
+            let message = format!("nested archives expand to more than {} bytes, see --max-expansion", self.limit);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        Ok(read)
+    }
+}
+
+/// The outcome of searching a single file.
+#[derive(Debug, Clone, Default)]
+pub struct FileSummary {
+    /// The path of the searched file.
+    pub path: String,
+    /// The number of lines that matched.
+    pub matched_lines: u64,
+    /// The number of matches. Only counted with --count-matches and --json.
+    pub matches: u64,
+    /// The number of decompressed bytes that were searched.
+    pub bytes_searched: u64,
+    /// The number of compressed bytes read from the file.
+    pub bytes_read: u64,
+    /// The number of bytes printed. Only counted with --json.
+    pub bytes_printed: u64,
+    /// The offset of the first byte that looked binary, if any.
+    pub binary_offset: Option<u64>,
+    /// How long the search of the file took.
+    pub elapsed: Duration,
+    /// The summaries of the members, when the file is an archive.
+    /// The counts of the archive are the sums of the ones of its members.
+    pub members: Vec<FileSummary>,
+}
+
+impl FileSummary {
+    fn new(path: &str) -> FileSummary {
+        FileSummary {
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the path to show to the user.
+    pub fn display_path(&self) -> &str {
+        display_path(&self.path)
+    }
+
+    /// Returns the summaries of what was searched in the file,
+    /// which are the ones of its members for an archive.
+    pub fn searched(&self) -> &[FileSummary] {
+        if self.members.is_empty() {
+            return std::slice::from_ref(self);
+        }
+        &self.members
+    }
+
+    /// Adds the counts of a part of the file to the ones of the file.
+    fn add(&mut self, part: &FileSummary) {
+        self.matched_lines += part.matched_lines;
+        self.matches += part.matches;
+        self.bytes_searched += part.bytes_searched;
+        self.bytes_read += part.bytes_read;
+        self.bytes_printed += part.bytes_printed;
+    }
+}
+
+/// Returns the path to show to the user for a file path.
+/// Stdin has no name, so it is shown the way grep shows it.
+pub fn display_path(file_path: &str) -> &str {
+    if file_path == cli::STDIN_PATH {
+        return "(standard input)";
+    }
+    file_path
+}
+
+/// Processes a single file. A file path of `-` reads from stdin.
+/// It will stream the file into a decoder and stream the 
+/// decoded data into a searcher. The searcher will then
+/// perform a regext "grep" and print the results to stdout.
+pub async fn process_file(file_path: &str, config: &SearchConfig, cancel: &CancelToken) -> Result<FileSummary> {
+    let (matcher, options) = (config.line_matcher(), config.options());
+    let started = Instant::now();
+    if cancel.is_cancelled() {
+        // Another task already found what we are looking for
+        return Ok(FileSummary::new(file_path));
+    }
+
+    let (input, progress): (Box<dyn Read + Send>, _) = if file_path == cli::STDIN_PATH {
+        (Box::new(io::stdin()), file_progress(file_path, None, options))
+    } else {
+        match open_file(file_path, options)? {
+            Some(mut file) => {
+                let file_archive = match archive::file_archive(&mut file) {
+                    Ok(file_archive) => file_archive.filter(|_| options.format.is_none()),
+                    Err(e) => return Err(anyhow::anyhow!("Error reading file {}: {}", file_path, e)),
+                };
+                if let Some(file_archive) = file_archive {
+                    let mut summary = FileSummary::new(file_path);
+                    let search = ArchiveSearch::new(matcher, options, cancel);
+                    search.search_indexed(file_archive, &mut summary, file_path, file)?;
+                    summary.elapsed = started.elapsed();
+                    return Ok(summary);
+                }
+                // Only the frames of an indexed file whose lines can
+                // have the literals of the patterns are searched
+                #[cfg(feature = "native")]
+                if options.skips_frames() {
+                    let index = match index::read_index(file_path, &file, options.index_dir.as_deref()) {
+                        Ok(index) => index.filter(|index| index.terminator == options.line_terminator()),
+                        Err(e) => return Err(anyhow::anyhow!("Error reading index of file {}: {}", file_path, e)),
+                    };
+                    if let Some(index) = index.filter(|_| !starts_with_utf16_bom(&mut file, options)) {
+                        let per_chunk = index.frames.len().div_ceil(chunk_workers(options));
+                        let chunks = index.chunks(matcher.required_literals(), per_chunk);
+                        return search_chunks(file_path, chunks, config, cancel, started).await;
+                    }
+                }
+                // Without tokio, seekable files are decoded one frame
+                // after the other like the other zstd files
+                #[cfg(feature = "native")]
+                if options.searches_chunks() {
+                    let frames = match seekable::read_seek_table(&mut file) {
+                        Ok(frames) => frames,
+                        Err(e) => return Err(anyhow::anyhow!("Error reading seek table of file {}: {}", file_path, e)),
+                    };
+                    let frames = frames.filter(|frames| frames.len() > 1);
+                    if let Some(frames) = frames.filter(|_| !starts_with_utf16_bom(&mut file, options)) {
+                        let chunks = seekable::chunks(&frames, chunk_workers(options));
+                        return search_chunks(file_path, chunks, config, cancel, started).await;
+                    }
+                }
+                let progress = file_progress(file_path, Some(&file), options);
+                if options.follow_tail {
+                    (Box::new(FollowReader::new(file, cancel.clone())), progress)
+                } else {
+                    (file_input(file, options), progress)
+                }
+            }
+            // File is empty, nothing to do
+            None => return Ok(FileSummary::new(file_path)),
+        }
+    };
+
+    let bytes_read = Arc::new(AtomicU64::new(0));
+    let input = CountingReader {
+        inner: CancellableReader {
+            inner: ProgressReader::new(input, progress.clone()),
+            cancel: cancel.clone(),
+        },
+        count: bytes_read.clone(),
+    };
+
+    // Find the format of the input from its magic bytes, then from
+    // its extension. --format wins over both.
+    let (detected, input) = match format::detect(input) {
+        Ok(detected) => detected,
+        Err(e) => return Err(anyhow::anyhow!("Error reading file {}: {}", file_path, e)),
+    };
+    let format = Format::resolve(options.format.or(detected), file_path);
+
+    let input: Box<dyn Read + Send> = if options.show_skippable && format == Format::Zstd {
+        Box::new(SkippableFrames::new(input, file_path))
+    } else {
+        Box::new(input)
+    };
+
+    // Read the compressed data from the input and decode it
+    // with the decoder of its format
+    let decoder = match cached_decoder(file_path, format, input, options) {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            let e = anyhow::anyhow!("Error creating decoder for file {}: {}", file_path, e);
+            return Err(e);
+        }
+    };
+    let decoder = DecodedReader::new(decoder, progress);
+
+    // Tar archives are searched member by member. A followed file is
+    // a log being written, whose first lines are printed before it is
+    // long enough to be told from a tar archive.
+    let peeked = if options.follow_tail { 0 } else { archive::TAR_BLOCK_LEN };
+    let (prefix, decoder) = match format::peek(decoder, peeked) {
+        Ok(peeked) => peeked,
+        Err(e) => return Err(anyhow::anyhow!("Error searching file {}: {}", file_path, e)),
+    };
+    let mut summary = if archive::is_tar(&prefix) {
+        let mut summary = FileSummary::new(file_path);
+        ArchiveSearch::new(matcher, options, cancel).search_tar(&mut summary, file_path, decoder)?;
+        summary
+    } else {
+        search_to_sink(file_path, decoder, matcher, options, cancel, started)?
+    };
+    summary.bytes_read = bytes_read.load(Ordering::Relaxed);
+    summary.elapsed = started.elapsed();
+    Ok(summary)
+}
+
+/// Returns the decoded data of a file, whose format is told by its
+/// first bytes and extension like for the searched files, for
+/// `rzstd cat`. A file path of `-` reads from stdin. The archives are
+/// decoded but not opened, like zcat does.
+pub fn decode_file<'a>(file_path: &str, options: &'a SearchOptions) -> Result<Box<dyn Read + 'a>> {
+    let (format, input, progress) = open_input(file_path, options)?;
+    match cached_decoder(file_path, format, input, options) {
+        Ok(decoder) => Ok(Box::new(DecodedReader::new(decoder, progress))),
+        Err(e) => Err(anyhow::anyhow!("Error creating decoder for file {}: {}", file_path, e)),
+    }
+}
+
+/// A file opened by [`open_input`]: its format, its compressed data,
+/// and its progress bar when the progress is shown.
+pub(crate) type OpenedInput = (Format, Box<dyn Read + Send>, Option<Arc<FileProgress>>);
+
+/// Opens a file given to a command other than the search, or stdin
+/// for `-`, and returns its format and its compressed data.
+pub(crate) fn open_input(file_path: &str, options: &SearchOptions) -> Result<OpenedInput> {
+    let (input, progress): (Box<dyn Read + Send>, _) = if file_path == cli::STDIN_PATH {
+        (Box::new(io::stdin()), file_progress(file_path, None, options))
+    } else {
+        match open_file(file_path, options)? {
+            Some(file) => {
+                let progress = file_progress(file_path, Some(&file), options);
+                (file_input(file, options), progress)
+            }
+            None => return Ok((Format::Uncompressed, Box::new(io::empty()), None)),
+        }
+    };
+    let input = ProgressReader::new(input, progress.clone());
+
+    let (detected, input) = match format::detect(input) {
+        Ok(detected) => detected,
+        Err(e) => return Err(anyhow::anyhow!("Error reading file {}: {}", file_path, e)),
+    };
+    Ok((Format::resolve(options.format.or(detected), file_path), Box::new(input), progress))
+}
+
+/// Searches the members of an archive, each file as if it was a file
+/// of its own named `archive:member`. With --search-archives, the
+/// archives and compressed files found in the members are opened too,
+/// and their members are added to the same summary.
+struct ArchiveSearch<'a> {
+    matcher: &'a LineMatcher,
+    /// The options of the members, see member_options.
+    options: SearchOptions,
+    cancel: &'a CancelToken,
+    /// How deep the archive is nested, 0 for a searched file.
+    depth: usize,
+    /// The bytes decoded from the nested archives and compressed
+    /// files so far, shared by all the levels of the searched file.
+    expanded: Arc<AtomicU64>,
+}
+
+impl<'a> ArchiveSearch<'a> {
+    fn new(matcher: &'a LineMatcher, options: &SearchOptions, cancel: &'a CancelToken) -> ArchiveSearch<'a> {
+        ArchiveSearch {
+            matcher,
+            options: member_options(options),
+            cancel,
+            depth: 0,
+            expanded: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the search of an archive found in a member.
+    fn nested(&self) -> ArchiveSearch<'a> {
+        ArchiveSearch {
+            matcher: self.matcher,
+            options: self.options.clone(),
+            cancel: self.cancel,
+            depth: self.depth + 1,
+            expanded: self.expanded.clone(),
+        }
+    }
+
+    /// Wraps data decoded from a nested archive to fail once
+    /// too much is decoded, see [`ExpansionGuard`].
+    fn guard<R: Read>(&self, input: R) -> ExpansionGuard<R> {
+        ExpansionGuard {
+            inner: input,
+            expanded: self.expanded.clone(),
+            limit: self.options.max_expansion,
+        }
+    }
+
+    /// Returns true if the include globs of --member-glob select the
+    /// member, or if there are none.
+    fn selects(&self, name: &str) -> bool {
+        self.options.member_globs.selects(archive::member_name(name)) != Some(false)
+    }
+
+    /// Searches the files of a zip or 7z archive.
+    fn search_indexed<R: Read + Seek>(
+        &self,
+        file_archive: FileArchive,
+        summary: &mut FileSummary,
+        file_path: &str,
+        input: R,
+    ) -> Result<()> {
+        match file_archive {
+            FileArchive::Zip => self.search_zip(summary, file_path, input),
+            FileArchive::SevenZ => self.search_7z(summary, file_path, input),
+        }
+    }
+
+    /// Searches the files of a tar archive. The other members,
+    /// like directories and links, are skipped.
+    fn search_tar<R: Read>(&self, summary: &mut FileSummary, file_path: &str, decoded: R) -> Result<()> {
+        let mut tar = tar::Archive::new(decoded);
+        let entries = match tar.entries() {
+            Ok(entries) => entries,
+            Err(e) => return Err(anyhow::anyhow!("Error reading archive {}: {}", file_path, e)),
+        };
+
+        for entry in entries {
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            let mut entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Err(anyhow::anyhow!("Error reading archive {}: {}", file_path, e)),
+            };
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let name = entry.path()?.to_string_lossy().into_owned();
+            self.search_member(summary, file_path, &name, &mut entry)?;
+        }
+        if !self.cancel.is_cancelled() {
+            // The padding after the last entry is read too, so that
+            // --cache-dir knows where the decoded data ends
+            let _ = io::copy(&mut tar.into_inner(), &mut io::sink());
+        }
+        Ok(())
+    }
+
+    /// Searches the files of a zip archive. Unlike tar archives, zip archives
+    /// are read from their central directory at their end, so they can't
+    /// be streamed.
+    fn search_zip<R: Read + Seek>(&self, summary: &mut FileSummary, file_path: &str, input: R) -> Result<()> {
+        let mut zip = match zip::ZipArchive::new(input) {
+            Ok(zip) => zip,
+            Err(e) => return Err(anyhow::anyhow!("Error reading archive {}: {}", file_path, e)),
+        };
+
+        for index in 0..zip.len() {
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            let mut entry = match zip.by_index(index) {
+                Ok(entry) => entry,
+                Err(e) => return Err(anyhow::anyhow!("Error reading archive {}: {}", file_path, e)),
+            };
+            if !entry.is_file() {
+                continue;
+            }
+
+            // The compressed bytes of nested archives were already counted
+            if self.depth == 0 {
+                summary.bytes_read += entry.compressed_size();
+            }
+            let name = entry.name().to_string();
+            self.search_member(summary, file_path, &name, &mut entry)?;
+        }
+        Ok(())
+    }
+
+    /// Searches the files of a 7z archive. Like zip archives, 7z archives
+    /// are read from their header at their end. The files of solid archives
+    /// are decoded together, so they are all decoded even when few are searched.
+    fn search_7z<R: Read + Seek>(&self, summary: &mut FileSummary, file_path: &str, mut input: R) -> Result<()> {
+        let len = match input.seek(SeekFrom::End(0)).and_then(|len| input.rewind().map(|_| len)) {
+            Ok(len) => len,
+            Err(e) => return Err(anyhow::anyhow!("Error reading archive {}: {}", file_path, e)),
+        };
+        let mut archive = match sevenz_rust::SevenZReader::new(input, len, sevenz_rust::Password::empty()) {
+            Ok(archive) => archive,
+            Err(e) => return Err(anyhow::anyhow!("Error reading archive {}: {}", file_path, e)),
+        };
+
+        // The errors of the search stop the archive, and are returned after it
+        let mut searched = Ok(());
+        let read = archive.for_each_entries(|entry, member| {
+            if self.cancel.is_cancelled() {
+                return Ok(false);
+            }
+            if entry.is_directory() {
+                return Ok(true);
+            }
+            searched = self.search_member(summary, file_path, entry.name(), member);
+            Ok(searched.is_ok())
+        });
+        searched?;
+        if let Err(e) = read {
+            return Err(anyhow::anyhow!("Error reading archive {}: {}", file_path, e));
+        }
+        if self.depth == 0 {
+            summary.bytes_read = len;
+        }
+        Ok(())
+    }
+
+    /// Searches a member of the archive and adds it to the summary,
+    /// unless --member-glob skips it.
+    fn search_member(
+        &self,
+        summary: &mut FileSummary,
+        file_path: &str,
+        name: &str,
+        member: &mut dyn Read,
+    ) -> Result<()> {
+        let opened = self.depth < self.options.search_archives;
+        let globs = &self.options.member_globs;
+        if globs.excludes(archive::member_name(name)) {
+            return Ok(());
+        }
+        // The members that may be archives are opened whatever the
+        // include globs, which then select the files found in them
+        if !(self.selects(name) || opened && archive::is_archive_name(name)) {
+            return Ok(());
+        }
+
+        let member_path = archive::member_path(file_path, name);
+        let member_path = member_path.as_str();
+        // The members of nested archives are decoded from their archive
+        let mut guarded;
+        let member: &mut dyn Read = if self.depth > 0 {
+            guarded = self.guard(member);
+            &mut guarded
+        } else {
+            member
+        };
+        if opened {
+            return self.nested().search_nested(summary, member_path, name, member);
+        }
+
+        let member = search_to_sink(member_path, member, self.matcher, &self.options, self.cancel, Instant::now())?;
+        summary.add(&member);
+        summary.members.push(member);
+        Ok(())
+    }
+
+    /// Searches a member that may be an archive or a compressed file,
+    /// told by its first bytes or its extension like the searched files.
+    /// Zip and 7z archives are read into memory to be searched.
+    fn search_nested(
+        &self,
+        summary: &mut FileSummary,
+        member_path: &str,
+        name: &str,
+        member: &mut dyn Read,
+    ) -> Result<()> {
+        let (prefix, member) = match format::peek(member, format::MAGIC_LEN) {
+            Ok(peeked) => peeked,
+            Err(e) => return Err(anyhow::anyhow!("Error reading file {}: {}", member_path, e)),
+        };
+        if let Some(file_archive) = archive::from_magic(&prefix) {
+            let mut data = Vec::new();
+            if let Err(e) = self.guard(member).read_to_end(&mut data) {
+                return Err(anyhow::anyhow!("Error reading file {}: {}", member_path, e));
+            }
+            return self.search_indexed(file_archive, summary, member_path, Cursor::new(data));
+        }
+
+        let format = Format::resolve(Format::from_magic(&prefix), member_path);
+        let decoder = match format.decoder(member, self.options.zstd()) {
+            Ok(decoder) => decoder,
+            Err(e) => return Err(anyhow::anyhow!("Error creating decoder for file {}: {}", member_path, e)),
+        };
+        let decoder: Box<dyn Read> = if format == Format::Uncompressed {
+            decoder
+        } else {
+            Box::new(self.guard(decoder))
+        };
+
+        let (prefix, decoder) = match format::peek(decoder, archive::TAR_BLOCK_LEN) {
+            Ok(peeked) => peeked,
+            Err(e) => return Err(anyhow::anyhow!("Error searching file {}: {}", member_path, e)),
+        };
+        if archive::is_tar(&prefix) {
+            return self.search_tar(summary, member_path, decoder);
+        }
+        if !self.selects(name) {
+            return Ok(());
+        }
+
+        let member = search_to_sink(member_path, decoder, self.matcher, &self.options, self.cancel, Instant::now())?;
+        summary.add(&member);
+        summary.members.push(member);
+        Ok(())
+    }
+}
+
+/// Returns the options the members of the archives are searched with.
+/// Their lines are always prefixed with their path.
+fn member_options(options: &SearchOptions) -> SearchOptions {
+    SearchOptions {
+        with_filename: true,
+        ..options.clone()
+    }
+}
+
+/// Returns the number of chunks the frames of a file are searched
+/// in at once.
+#[cfg(feature = "native")]
+fn chunk_workers(options: &SearchOptions) -> usize {
+    options
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |workers| workers.get()))
+}
+
+/// The batches of lines a chunk can send before the ones before it
+/// are printed, after which it waits for them.
+#[cfg(feature = "native")]
+const CHUNK_BATCHES: usize = 16;
+
+/// Searches the chunks of consecutive frames of a seekable or indexed
+/// zstd file in parallel. The lines of the chunk being printed are
+/// printed as they are found, the next chunks keep a few batches of
+/// their lines until it is done, and then wait for their turn.
+#[cfg(feature = "native")]
+async fn search_chunks(
+    file_path: &str,
+    chunks: Vec<seekable::Chunk>,
+    config: &SearchConfig,
+    cancel: &CancelToken,
+    started: Instant,
+) -> Result<FileSummary> {
+    let options = config.options();
+    // With -l and -L, the first chunk that matches stops the others
+    let matched = CancelToken::default();
+    // The chunks move the progress bar of the file together, which
+    // shows the compressed data as the chunks decode the frames
+    // before them too
+    let len = fs::metadata(file_path).ok().map(|metadata| metadata.len());
+    let progress = options.progress.as_ref().map(|progress| Arc::new(progress.add_file(file_path, len, None)));
+    let mut searches = Vec::new();
+    for chunk in chunks {
+        let file_path = file_path.to_string();
+        let config = config.clone();
+        let cancel = cancel.clone();
+        let matched = matched.clone();
+        let progress = progress.clone();
+        let (sender, receiver) = tokio::sync::mpsc::channel(CHUNK_BATCHES);
+        // The chunks wait for their turn without holding the threads
+        // of the runtime, which print the chunks before them
+        let handle = tokio::task::spawn_blocking(move || {
+            let (matcher, options) = (config.line_matcher(), config.options());
+            let send: ChunkLines = Box::new(move |lines| {
+                sender.blocking_send(lines).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+            });
+            search_chunk(&file_path, chunk, matcher, options, &cancel, &matched, progress, send)
+        });
+        searches.push((handle, receiver));
+    }
+
+    let mut summary = FileSummary::new(file_path);
+    let mut output = file_output(options, file_path);
+    for (handle, mut receiver) in searches {
+        while let Some(lines) = receiver.recv().await {
+            output.lines(&lines)?;
+        }
+        let chunk_summary = match handle.await {
+            Ok(searched) => searched?,
+            Err(e) => return Err(anyhow::anyhow!("Error joining task: {}", e)),
+        };
+        summary.add(&chunk_summary);
+    }
+    summary.elapsed = started.elapsed();
+    output.finish(&heading(options, file_path))?;
+    Ok(summary)
+}
+
+/// Searches a chunk of a seekable zstd file and sends its lines.
+/// The compressed bytes read include the frame before the chunk.
+#[cfg(feature = "native")]
+#[allow(clippy::too_many_arguments)]
+fn search_chunk(
+    file_path: &str,
+    chunk: seekable::Chunk,
+    matcher: &LineMatcher,
+    options: &SearchOptions,
+    cancel: &CancelToken,
+    matched: &CancelToken,
+    progress: Option<Arc<FileProgress>>,
+    send: ChunkLines,
+) -> Result<FileSummary> {
+    let started = Instant::now();
+    let mut file = match File::open(file_path) {
+        Ok(file) => file,
+        Err(e) => return Err(anyhow::anyhow!("Error opening file {}: {}", file_path, e)),
+    };
+    file.seek(SeekFrom::Start(chunk.compressed_offset))?;
+
+    let bytes_read = Arc::new(AtomicU64::new(0));
+    let input = CountingReader {
+        inner: CancellableReader {
+            inner: CancellableReader {
+                inner: ProgressReader::new(file, progress.clone()),
+                cancel: cancel.clone(),
+            },
+            cancel: matched.clone(),
+        },
+        count: bytes_read.clone(),
+    };
+    let decoder = match Format::Zstd.decoder(input, options.zstd()) {
+        Ok(decoder) => decoder,
+        Err(e) => return Err(anyhow::anyhow!("Error creating decoder for file {}: {}", file_path, e)),
+    };
+    let decoder = DecodedReader::new(decoder, progress);
+
+    // Only the printed lines are sent, the counts of -c
+    // are summed once all the chunks are searched
+    let lines = chunk.lines(decoder, options.line_terminator());
+    let mut printer = sink::StandardPrinter::new(matcher.regex(), options, FileOutput::chunk(options.line_terminator(), send));
+    let searched = if options.prints_lines() {
+        search_decoded(file_path, lines, matcher, options, cancel, started, chunk.start, &mut printer)
+    } else {
+        search_to_sink(file_path, lines, matcher, options, cancel, started)
+    };
+    let mut summary = match searched {
+        Ok(summary) => summary,
+        // Another chunk matched, this one was stopped wherever it was
+        Err(_) if matched.is_cancelled() => FileSummary::new(file_path),
+        Err(e) => return Err(e),
+    };
+    summary.bytes_read = bytes_read.load(Ordering::Relaxed);
+    if summary.matched_lines > 0 && (options.files_with_matches || options.files_without_match) {
+        matched.cancel();
+    }
+    Ok(summary)
+}
+
+/// Returns the input of a file, which is memory mapped with --mmap.
+/// The file is read as usual when it can't be mapped.
+fn file_input(file: File, options: &SearchOptions) -> Box<dyn Read + Send> {
+    if options.mmap {
+        // The map is only read. Like in other greps, a file truncated
+        // by another program while it is mapped can make the search crash.
+        if let Ok(map) = unsafe { Mmap::map(&file) } {
+            #[cfg(unix)]
+            let _ = map.advise(memmap2::Advice::Sequential);
+            return Box::new(Cursor::new(map));
+        }
+    }
+    Box::new(file)
+}
+
+/// Returns the decoder of a file, which decodes the frames of the
+/// zstd files on threads of their own with --decompress-threads.
+/// With more than one CPU, the other compressed files are decoded on
+/// a thread of their own too, while they are searched.
+fn file_decoder(format: Format, input: Box<dyn Read + Send>, options: &SearchOptions) -> io::Result<Box<dyn Read>> {
+    #[cfg(feature = "native")]
+    {
+        let dictionary = options.dictionary.clone();
+        if format == Format::Zstd && options.decompress_threads > 1 {
+            return Ok(Box::new(parallel::ParallelFrames::new(input, options.decompress_threads, dictionary, options.max_window)));
+        }
+        let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+        if format != Format::Uncompressed && cpus > 1 {
+            return Ok(Box::new(parallel::DecodedAhead::new(format, input, dictionary, options.max_window)?));
+        }
+    }
+    format.decoder(input, options.zstd())
+}
+
+/// Returns the decoder of a file, whose decoded data is read from
+/// the cache given with --cache-dir when it is there. The files that
+/// aren't compressed, stdin and the skippable frames printed with
+/// --show-skippable aren't cached.
+fn cached_decoder<'a>(
+    file_path: &str,
+    format: Format,
+    input: Box<dyn Read + Send>,
+    options: &'a SearchOptions,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let cache = options
+        .cache
+        .as_ref()
+        .filter(|_| file_path != cli::STDIN_PATH && format != Format::Uncompressed && !options.show_skippable)
+        .filter(|_| !options.follow_tail);
+    let dictionary = options.dictionary.as_deref().map(Vec::as_slice);
+    match cache.and_then(|cache| Some((cache, BlockCache::file_key(file_path, format, dictionary)?))) {
+        Some((cache, key)) => Ok(Box::new(cache.reader(key, move || file_decoder(format, input, options)))),
+        None => file_decoder(format, input, options),
+    }
+}
+
+/// Returns the output the lines of a file are printed to.
+fn file_output(options: &SearchOptions, file_path: &str) -> FileOutput {
+    let output = FileOutput::new(options.heading && options.with_filename, options.line_terminator());
+    match &options.output_dir {
+        Some(dir) => output.into_file(output::output_path(dir, file_path), file_path),
+        None => output,
+    }
+}
+
+/// Returns the heading printed above the lines of a file with --heading.
+#[cfg(feature = "native")]
+fn heading(options: &SearchOptions, file_path: &str) -> String {
+    options.colors.path.paint(display_path(file_path)).to_string()
+}
+
+/// Searches the decompressed data of a file and gives its lines
+/// to the sink. Returns the summary of the file.
+#[allow(clippy::too_many_arguments)]
+fn search_decoded<R: Read>(
+    file_path: &str,
+    decoded: R,
+    matcher: &LineMatcher,
+    options: &SearchOptions,
+    cancel: &CancelToken,
+    started: Instant,
+    start: LineStart,
+    sink: &mut dyn OutputSink,
+) -> Result<FileSummary> {
+    // Context lines are only searched for when lines are printed.
+    let (after_context, before_context) = if options.prints_lines() {
+        (options.after_context, options.before_context)
+    } else {
+        (0, 0)
+    };
+
+    let mut searcher = SearcherBuilder::new()
+        .invert_match(options.invert_match)
+        .line_number(options.line_number)
+        .line_terminator(LineTerminator::byte(options.line_terminator()))
+        .multi_line(options.multiline)
+        .binary_detection(options.binary_detection())
+        // A UTF-8 or UTF-16 BOM tells the encoding, and is removed
+        .bom_sniffing(true)
+        .after_context(after_context)
+        .before_context(before_context)
+        .build();
+
+    let mut sink = FileSink {
+        options,
+        cancel,
+        summary: FileSummary::new(file_path),
+        started,
+        start,
+        matched_to: 0,
+        binary_offset: None,
+        sink,
+    };
+
+    let searched = match options.encoding {
+        Some(_) => searcher.search_reader(matcher.for_file(), transcoded(decoded, options), &mut sink),
+        None => searcher.search_reader(matcher.for_file(), decoded, &mut sink),
+    };
+    match searched {
+        Ok(_) => (),
+        // The search was stopped by another one, the decoder may
+        // fail as its input ends in the middle of a frame
+        Err(_) if cancel.is_cancelled() => (),
+        Err(e) => {
+            let e = anyhow::anyhow!("Error searching file {}: {}", file_path, e);
+            return Err(e);
+        }
+    };
+
+    Ok(sink.summary)
+}
+
+/// Returns the decompressed data of a file transcoded to UTF-8 from
+/// the encoding of the options. A BOM at its start tells the encoding
+/// instead, and is removed.
+pub(crate) fn transcoded<R: Read>(decoded: R, options: &SearchOptions) -> DecodeReaderBytes<R, Vec<u8>> {
+    DecodeReaderBytesBuilder::new()
+        .encoding(options.encoding)
+        .utf8_passthru(true)
+        .strip_bom(true)
+        .bom_override(true)
+        .build(decoded)
+}
+
+/// Searches the decompressed data of a file
+/// and prints its lines with its own sink.
+fn search_to_sink<R: Read>(
+    file_path: &str,
+    decoded: R,
+    matcher: &LineMatcher,
+    options: &SearchOptions,
+    cancel: &CancelToken,
+    started: Instant,
+) -> Result<FileSummary> {
+    let mut sink = sink::file_sink(matcher.regex(), options, file_output(options, file_path));
+    search_decoded(file_path, decoded, matcher, options, cancel, started, LineStart::default(), sink.as_mut())
+}
+
+/// Receives the lines found by the searcher in a single file, keeps
+/// the summary of the file up to date and gives the lines to the
+/// output sink, unless the options only need the summary.
+struct FileSink<'a, 's> {
+    options: &'a SearchOptions,
+    cancel: &'a CancelToken,
+    summary: FileSummary,
+    /// When the search of the file started.
+    started: Instant,
+    /// Where the searched data starts in the file.
+    start: LineStart,
+    /// The offset after the last matched line, which is where the
+    /// search ends when it stops at a match.
+    matched_to: u64,
+    /// The offset of the first NUL byte, once the binary data is found.
+    binary_offset: Option<u64>,
+    sink: &'s mut dyn OutputSink,
+}
+
+impl FileSink<'_, '_> {
+    /// Returns true if the file has as many matched lines as --max-count allows.
+    fn reached_max_count(&self) -> bool {
+        match self.options.max_count {
+            Some(max_count) => self.summary.matched_lines >= max_count,
+            None => false,
+        }
+    }
+
+    /// Returns true if a line at the offset is binary data whose
+    /// lines aren't printed.
+    fn suppresses(&self, byte_offset: u64) -> bool {
+        self.options.binary == BinaryMode::Suppress && self.binary_offset.is_some_and(|binary_offset| byte_offset >= binary_offset)
+    }
+}
+
+impl Sink for FileSink<'_, '_> {
+    type Error = io::Error;
+
+    fn begin(&mut self, _searcher: &Searcher) -> Result<bool, io::Error> {
+        self.sink.on_begin(&self.summary.path)?;
+        // With --max-count 0 there is nothing to search.
+        Ok(!self.reached_max_count())
+    }
+
+    fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch<'_>) -> Result<bool, io::Error> {
+        if self.options.skips_line(mat.bytes()) {
+            return Ok(true);
+        }
+        self.summary.matched_lines += 1;
+        self.matched_to = mat.absolute_byte_offset() + mat.bytes().len() as u64;
+        if self.options.quiet {
+            // A single match anywhere is enough, stop all the searches.
+            self.cancel.cancel();
+            return Ok(false);
+        }
+        if self.options.files_with_matches || self.options.files_without_match {
+            // The first match is enough to know that the file matches.
+            // Returning false stops the search, so the rest
+            // of the file isn't decompressed.
+            return Ok(false);
+        }
+        if self.suppresses(mat.absolute_byte_offset()) {
+            // The file is known to match, the rest is binary data
+            self.sink.on_binary()?;
+            return Ok(false);
+        }
+
+        self.sink.on_match(&SinkLine {
+            bytes: mat.bytes(),
+            line_number: mat.line_number().map(|line_number| line_number + self.start.lines_before),
+            byte_offset: mat.absolute_byte_offset() + self.start.byte_offset,
+        })?;
+
+        // Return true to continue searching.
+        // Returning false stops the search and the decompression of the file.
+        Ok(!self.reached_max_count())
+    }
+
+    fn context(&mut self, _searcher: &Searcher, context: &SinkContext<'_>) -> Result<bool, io::Error> {
+        if self.suppresses(context.absolute_byte_offset()) || self.options.skips_line(context.bytes()) {
+            return Ok(true);
+        }
+        self.sink.on_context(&SinkLine {
+            bytes: context.bytes(),
+            line_number: context.line_number().map(|line_number| line_number + self.start.lines_before),
+            byte_offset: context.absolute_byte_offset() + self.start.byte_offset,
+        })?;
+        Ok(true)
+    }
+
+    fn context_break(&mut self, _searcher: &Searcher) -> Result<bool, io::Error> {
+        self.sink.on_context_break()?;
+        Ok(true)
+    }
+
+    fn binary_data(&mut self, _searcher: &Searcher, binary_byte_offset: u64) -> Result<bool, io::Error> {
+        self.binary_offset = Some(binary_byte_offset);
+        Ok(true)
+    }
+
+    fn finish(&mut self, _searcher: &Searcher, finish: &SinkFinish) -> Result<(), io::Error> {
+        // The searcher only counts the data it went past
+        self.summary.bytes_searched = finish.byte_count().max(self.matched_to);
+        self.summary.binary_offset = finish.binary_byte_offset();
+        self.summary.elapsed = self.started.elapsed();
+        self.sink.on_end(&mut self.summary)
+    }
+}
+
+/// Returns true if the decoded data of an indexed or seekable zstd
+/// file starts with a UTF-16 BOM. Its frames are then searched one
+/// after the other, since the indexes and the chunks only know the
+/// UTF-8 lines. The file is rewound afterwards. The errors of the
+/// file are left to its search.
+#[cfg(feature = "native")]
+fn starts_with_utf16_bom(file: &mut File, options: &SearchOptions) -> bool {
+    let mut start = Vec::new();
+    let read = format::zstd_decoder(io::BufReader::new(&*file), options.zstd()).and_then(|decoder| decoder.take(2).read_to_end(&mut start));
+    if read.is_err() || file.rewind().is_err() {
+        return false;
+    }
+    matches!(start[..], [0xff, 0xfe] | [0xfe, 0xff])
+}
+
+/// Opens a file on disk for searching.
+/// Returns None when the file is empty and there is
+/// nothing to search.
+/// Symlinks are rejected unless they are followed.
+fn open_file(file_path: &str, options: &SearchOptions) -> Result<Option<File>> {
+    // File::open follows symlinks, so look at the path itself first
+    let is_symlink = match fs::symlink_metadata(file_path) {
+        Ok(metadata) => metadata.file_type().is_symlink(),
+        Err(_) => false, // Let File::open report the error
+    };
+    if is_symlink && !options.follow_links {
+        // File is a symlink, nothing to do
+        // we don't follow symlinks unless asked to
+        return Err(anyhow::anyhow!("{} is a symlink, use --follow to search it", file_path));
+    }
+
+    let file = match File::open(file_path){
+        Ok(file) => file,
+        Err(e) => {
+            let e = anyhow::anyhow!("Error opening file {}: {}", file_path, e);
+            return Err(e);
+        }
+    
+    };
+
+    if file.metadata()?.len() == 0 && !options.follow_tail {
+        // File is empty, nothing to do
+        return Ok(None);
+    }
+
+    if file.metadata()?.file_type().is_dir() {
+        // File is a directory, nothing to do
+        return Err(anyhow::anyhow!("{} is a directory", file_path));
+    }
+
+    Ok(Some(file))
+}
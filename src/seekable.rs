@@ -0,0 +1,270 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::search::LineStart;
+
+/// The magic number that ends the seek table of a seekable zstd file.
+const SEEKABLE_MAGIC: u32 = 0x8f92_eab1;
+/// The magic number of the skippable frame that holds the seek table.
+const SEEK_TABLE_MAGIC: u32 = 0x184d_2a5e;
+/// The size of the header of a skippable frame.
+const SKIPPABLE_HEADER_LEN: u64 = 8;
+/// The size of the footer at the end of the seek table.
+const FOOTER_LEN: u64 = 9;
+
+/// A zstd frame of a seekable file, as listed in its seek table.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    /// Where the frame starts in the compressed file.
+    pub compressed_offset: u64,
+    /// The size of the frame once decompressed.
+    pub decompressed_size: u64,
+}
+
+/// Reads the seek table at the end of a file in the zstd seekable
+/// format, and returns its frames. Returns None for the files that
+/// aren't seekable. The file is rewound to its start afterwards.
+pub fn read_seek_table(file: &mut File) -> io::Result<Option<Vec<Frame>>> {
+    let frames = parse_seek_table(file);
+    file.rewind()?;
+    frames
+}
+
+fn parse_seek_table(file: &mut File) -> io::Result<Option<Vec<Frame>>> {
+    let len = file.metadata()?.len();
+    if len < SKIPPABLE_HEADER_LEN + FOOTER_LEN {
+        return Ok(None);
+    }
+
+    let mut footer = [0; FOOTER_LEN as usize];
+    file.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+    file.read_exact(&mut footer)?;
+    if u32_at(&footer, 5) != SEEKABLE_MAGIC {
+        return Ok(None);
+    }
+    let frame_count = u64::from(u32_at(&footer, 0));
+    let has_checksums = footer[4] & 0x80 != 0;
+    let entry_len: u64 = if has_checksums { 12 } else { 8 };
+
+    // The seek table is a skippable frame that ends the file
+    let table_len = SKIPPABLE_HEADER_LEN + frame_count * entry_len + FOOTER_LEN;
+    if table_len > len {
+        return Err(invalid("the seek table is larger than the file"));
+    }
+    let mut table = vec![0; (table_len - FOOTER_LEN) as usize];
+    file.seek(SeekFrom::Start(len - table_len))?;
+    file.read_exact(&mut table)?;
+    if u32_at(&table, 0) != SEEK_TABLE_MAGIC || u64::from(u32_at(&table, 4)) != table_len - SKIPPABLE_HEADER_LEN {
+        return Err(invalid("the seek table frame is corrupted"));
+    }
+
+    let mut frames = Vec::new();
+    let mut compressed_offset = 0;
+    for entry in table[SKIPPABLE_HEADER_LEN as usize..].chunks_exact(entry_len as usize) {
+        frames.push(Frame {
+            compressed_offset,
+            decompressed_size: u64::from(u32_at(entry, 4)),
+        });
+        compressed_offset += u64::from(u32_at(entry, 0));
+    }
+    if compressed_offset > len - table_len {
+        return Err(invalid("the frames of the seek table are larger than the file"));
+    }
+    Ok(Some(frames))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Consecutive frames of a seekable file that are searched together.
+#[derive(Debug, Clone, Copy)]
+pub struct Chunk {
+    /// Where decoding starts in the compressed file. Except for the
+    /// first chunk, this is the frame before the chunk, whose last
+    /// byte tells if the chunk starts with a new line.
+    pub compressed_offset: u64,
+    /// The decompressed size of the frame before the chunk.
+    previous_size: u64,
+    /// The decompressed size of the frames of the chunk.
+    size: u64,
+    /// Where the first line of the chunk is in the file, which only
+    /// the indexes of the files know.
+    pub start: LineStart,
+}
+
+impl Chunk {
+    /// Returns the chunk of the frames whose decompressed data takes
+    /// size bytes, after a frame of previous_size bytes that starts at
+    /// compressed_offset, or at the frames for the first chunk.
+    pub fn new(compressed_offset: u64, previous_size: u64, size: u64, start: LineStart) -> Chunk {
+        Chunk {
+            compressed_offset,
+            previous_size,
+            size,
+            start,
+        }
+    }
+
+    /// Wraps the decoder of the chunk, so that it only returns the
+    /// lines of the chunk. See [`ChunkLines`].
+    pub fn lines<R: Read>(&self, decoder: R, terminator: u8) -> ChunkLines<R> {
+        let phase = if self.previous_size > 0 {
+            Phase::PreviousFrame
+        } else {
+            Phase::Lines
+        };
+        ChunkLines {
+            inner: decoder,
+            terminator,
+            phase,
+            skip: self.previous_size,
+            remaining: self.size,
+            last: terminator,
+        }
+    }
+}
+
+/// Splits the frames into at most count chunks
+/// of about the same number of frames.
+pub fn chunks(frames: &[Frame], count: usize) -> Vec<Chunk> {
+    let per_chunk = frames.len().div_ceil(count.max(1)).max(1);
+    let mut chunks = Vec::new();
+    for (index, group) in frames.chunks(per_chunk).enumerate() {
+        let start = index * per_chunk;
+        let previous = start.checked_sub(1).map(|previous| frames[previous]);
+        chunks.push(Chunk {
+            compressed_offset: previous.unwrap_or(group[0]).compressed_offset,
+            previous_size: previous.map_or(0, |previous| previous.decompressed_size),
+            size: group.iter().map(|frame| frame.decompressed_size).sum(),
+            start: LineStart::default(),
+        });
+    }
+    chunks
+}
+
+/// Where the reader is in the decompressed data of its chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// In the frame before the chunk, which is skipped.
+    PreviousFrame,
+    /// In a line that started before the chunk, which is skipped
+    /// as the previous chunk searches it.
+    PreviousLine,
+    /// In the lines of the chunk.
+    Lines,
+    /// Past the end of the chunk, in the end of its last line.
+    LastLine,
+    /// Past the end of the last line of the chunk.
+    Done,
+}
+
+/// Returns the lines of a chunk from the decompressed data that
+/// starts at the frame before it. A line belongs to the chunk its
+/// first byte is in, so the line that started in the previous chunk
+/// is skipped, and the last line of the chunk is read until its end
+/// even when it ends in the next chunk.
+pub struct ChunkLines<R> {
+    inner: R,
+    terminator: u8,
+    phase: Phase,
+    /// The bytes of the frame before the chunk still to skip.
+    skip: u64,
+    /// The bytes of the chunk still to read.
+    remaining: u64,
+    /// The last byte of the frame before the chunk.
+    last: u8,
+}
+
+impl<R: Read> Read for ChunkLines<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.phase == Phase::Done {
+                return Ok(0);
+            }
+            let read = self.inner.read(buf)?;
+            if read == 0 {
+                self.phase = Phase::Done;
+                return Ok(0);
+            }
+
+            let (start, end) = self.select(&buf[..read]);
+            if start < end {
+                buf.copy_within(start..end, 0);
+                return Ok(end - start);
+            }
+        }
+    }
+}
+
+impl<R> ChunkLines<R> {
+    /// Returns the range of the data that belongs to the chunk,
+    /// moving through the phases as the data is read.
+    fn select(&mut self, data: &[u8]) -> (usize, usize) {
+        let mut start = 0;
+        if self.phase == Phase::PreviousFrame {
+            start = self.skip.min(data.len() as u64) as usize;
+            self.skip -= start as u64;
+            if let Some(&last) = data[..start].last() {
+                self.last = last;
+            }
+            if self.skip > 0 {
+                return (start, start);
+            }
+            // The chunk starts with a new line if the previous frame ends one
+            self.phase = if self.last == self.terminator {
+                Phase::Lines
+            } else {
+                Phase::PreviousLine
+            };
+        }
+
+        if self.phase == Phase::PreviousLine {
+            match self.find_terminator(&data[start..]) {
+                Some(end) => {
+                    self.remaining = self.remaining.saturating_sub(end as u64 + 1);
+                    start += end + 1;
+                    // The line may cover the whole chunk
+                    self.phase = if self.remaining == 0 { Phase::Done } else { Phase::Lines };
+                }
+                None => {
+                    self.remaining = self.remaining.saturating_sub((data.len() - start) as u64);
+                    return (data.len(), data.len());
+                }
+            }
+        }
+
+        let mut end = start;
+        if self.phase == Phase::Lines {
+            let read = self.remaining.min((data.len() - start) as u64);
+            end += read as usize;
+            self.remaining -= read;
+            if self.remaining == 0 {
+                self.phase = if data[..end].last() == Some(&self.terminator) {
+                    Phase::Done
+                } else {
+                    Phase::LastLine
+                };
+            }
+        }
+
+        if self.phase == Phase::LastLine {
+            match self.find_terminator(&data[end..]) {
+                Some(line_end) => {
+                    end += line_end + 1;
+                    self.phase = Phase::Done;
+                }
+                None => end = data.len(),
+            }
+        }
+        (start, end)
+    }
+
+    fn find_terminator(&self, data: &[u8]) -> Option<usize> {
+        data.iter().position(|&byte| byte == self.terminator)
+    }
+}
@@ -0,0 +1,313 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+
+use crate::config::{SearchBuilder, SearchConfig};
+use crate::format::Format;
+use crate::json;
+use crate::matcher::{self, CaseMode, MatcherOptions};
+use crate::search::{self, CancelToken, FileSummary, SearchOptions};
+use crate::sink::{JsonPrinter, OutputSink, SinkFactory};
+use crate::walk::{self, WalkOptions};
+
+/// A search sent to the server, as a single line of JSON:
+///
+/// ```json
+/// {"pattern": "error", "paths": ["/var/log/app"], "options": {"recursive": true}}
+/// ```
+///
+/// The server answers with the --json messages of the matched lines,
+/// an `error` message for each file that couldn't be searched, and
+/// the `summary` message once all the files are searched.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Query {
+    /// The pattern to search for.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// More patterns, a line matches if any of them match.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// The files to search, and the directories with `recursive`.
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub options: QueryOptions,
+}
+
+/// The options of a [`Query`], named like the long options of the
+/// command line. The lines are numbered unless `line_number` is false.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryOptions {
+    pub ignore_case: bool,
+    pub smart_case: bool,
+    pub fixed_strings: bool,
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    pub no_unicode: bool,
+    pub multiline: bool,
+    pub pcre2: bool,
+    pub vectorscan: bool,
+    pub invert_match: bool,
+    pub max_count: Option<u64>,
+    pub line_number: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub recursive: bool,
+    /// The format of all the files, like the values of --format.
+    pub format: Option<String>,
+}
+
+impl Default for QueryOptions {
+    fn default() -> QueryOptions {
+        QueryOptions {
+            ignore_case: false,
+            smart_case: false,
+            fixed_strings: false,
+            word_regexp: false,
+            line_regexp: false,
+            no_unicode: false,
+            multiline: false,
+            pcre2: false,
+            vectorscan: false,
+            invert_match: false,
+            max_count: None,
+            line_number: true,
+            before_context: 0,
+            after_context: 0,
+            recursive: false,
+            format: None,
+        }
+    }
+}
+
+impl QueryOptions {
+    fn matcher_options(&self) -> MatcherOptions {
+        let case = if self.ignore_case {
+            CaseMode::Insensitive
+        } else if self.smart_case {
+            CaseMode::Smart
+        } else {
+            CaseMode::Sensitive
+        };
+        MatcherOptions {
+            case,
+            fixed_strings: self.fixed_strings,
+            word: self.word_regexp,
+            whole_line: self.line_regexp,
+            ascii_only: self.no_unicode,
+            multiline: self.multiline,
+            pcre2: self.pcre2,
+            vectorscan: self.vectorscan,
+            ..MatcherOptions::default()
+        }
+    }
+}
+
+/// The messages of the server that aren't --json messages.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum Response<'a> {
+    /// The query, or one of its files, couldn't be searched.
+    Error { path: Option<&'a str>, message: String },
+}
+
+/// Answers the queries sent to a Unix socket, each connection on a
+/// task of its own. The server stays up between the queries, which
+/// all start from its options, like the cache of the decoded data.
+pub struct Server {
+    options: SearchOptions,
+    walk_options: WalkOptions,
+}
+
+impl Server {
+    pub fn new(options: SearchOptions, walk_options: WalkOptions) -> Server {
+        Server { options, walk_options }
+    }
+
+    /// Listens on the socket until the server is stopped. A socket
+    /// left by a server that stopped is removed first, but no other
+    /// file is.
+    pub async fn serve(self, socket: &Path) -> Result<()> {
+        if fs::symlink_metadata(socket).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            if UnixStream::connect(socket).await.is_ok() {
+                return Err(anyhow::anyhow!("Error listening on {}: another server is listening", socket.display()));
+            }
+            let _ = fs::remove_file(socket);
+        }
+        let listener = match UnixListener::bind(socket) {
+            Ok(listener) => listener,
+            Err(e) => return Err(anyhow::anyhow!("Error listening on {}: {}", socket.display(), e)),
+        };
+
+        let server = Arc::new(self);
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => return Err(anyhow::anyhow!("Error listening on {}: {}", socket.display(), e)),
+            };
+            let server = server.clone();
+            tokio::spawn(async move {
+                // The client went away, nothing is left to answer
+                let _ = server.answer(stream).await;
+            });
+        }
+    }
+
+    /// Answers the queries of a connection, one after the other.
+    async fn answer(&self, stream: UnixStream) -> io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let query = match serde_json::from_str::<Query>(&line) {
+                Ok(query) => query,
+                Err(e) => {
+                    let message = format!("Error parsing query: {}", e);
+                    write_response(&mut writer, &Response::Error { path: None, message }).await?;
+                    continue;
+                }
+            };
+            self.search(&query, &mut writer).await?;
+        }
+        Ok(())
+    }
+
+    /// Searches the files of a query one after the other, and writes
+    /// the messages to the client as the lines are found.
+    async fn search(&self, query: &Query, writer: &mut (impl AsyncWrite + Unpin)) -> io::Result<()> {
+        let started = Instant::now();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let config = match self.config(query, sender) {
+            Ok(config) => config,
+            Err(e) => {
+                let message = e.to_string();
+                return write_response(writer, &Response::Error { path: None, message }).await;
+            }
+        };
+
+        let files = self.files(query);
+        let cancel = CancelToken::default();
+        let searching = cancel.clone();
+        // The messages are sent by the sinks until the config is
+        // dropped with the last file. The files are searched on a
+        // thread of their own, while the messages are written here.
+        let runtime = Handle::current();
+        let searches = tokio::task::spawn_blocking(move || runtime.block_on(async move {
+            let mut results = Vec::new();
+            for file_path in files {
+                let result = match file_path {
+                    Ok(file_path) => search::process_file(&file_path, &config, &searching).await.map_err(|e| (Some(file_path), e)),
+                    Err(e) => Err((None, e)),
+                };
+                results.push(result);
+            }
+            results
+        }));
+
+        while let Some(message) = receiver.recv().await {
+            if let Err(e) = writer.write_all(&message).await {
+                // The search stops once its client is gone
+                cancel.cancel();
+                let _ = searches.await;
+                return Err(e);
+            }
+        }
+        let results = match searches.await {
+            Ok(results) => results,
+            Err(e) => return Err(io::Error::other(e)),
+        };
+
+        let mut summaries: Vec<FileSummary> = Vec::new();
+        for result in results {
+            match result {
+                Ok(summary) => summaries.push(summary),
+                Err((path, e)) => {
+                    let message = e.to_string();
+                    write_response(writer, &Response::Error { path: path.as_deref(), message }).await?;
+                }
+            }
+        }
+        writer.write_all(&json::encode(&json::summary(&summaries, started.elapsed()))?).await?;
+        writer.flush().await
+    }
+
+    /// Builds the config of a query, whose sinks send the --json
+    /// messages of the lines to the sender.
+    fn config(&self, query: &Query, sender: mpsc::UnboundedSender<Vec<u8>>) -> Result<SearchConfig> {
+        let patterns: Vec<String> = query.pattern.iter().chain(&query.patterns).cloned().collect();
+        if patterns.is_empty() {
+            return Err(anyhow::anyhow!("Error building search: no pattern to search for"));
+        }
+        let options = &query.options;
+        let format = match &options.format {
+            Some(format) => match Format::from_str(format, true) {
+                Ok(format) => Some(format),
+                Err(e) => return Err(anyhow::anyhow!("Error parsing format {}: {}", format, e)),
+            },
+            None => None,
+        };
+
+        // The sinks need the matcher to find the matches in the lines
+        let matcher_options = options.matcher_options();
+        let regex = matcher::build_matcher(&patterns, &matcher_options)?;
+        let sink = SinkFactory::new(move || {
+            let sender = sender.clone();
+            let output = Box::new(move |line: &[u8]| match sender.send(line.to_vec()) {
+                Ok(()) => Ok(()),
+                Err(_) => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+            });
+            let printer: Box<dyn OutputSink + Send> = Box::new(JsonPrinter::with_output(Cow::Owned(regex.clone()), output));
+            printer
+        });
+
+        let mut builder = SearchBuilder::from_options(SearchOptions {
+            format: format.or(self.options.format),
+            ..self.options.clone()
+        })
+        .patterns(patterns)
+        .matcher_options(matcher_options)
+        .invert_match(options.invert_match)
+        .line_number(options.line_number)
+        .context(options.before_context, options.after_context)
+        .sink(sink);
+        if let Some(max_count) = options.max_count {
+            builder = builder.max_count(max_count);
+        }
+        builder.build()
+    }
+
+    /// Returns the files of a query, with the ones found in its
+    /// directories when it is recursive, or the errors of walking them.
+    fn files(&self, query: &Query) -> Vec<Result<String>> {
+        let mut files = Vec::new();
+        for path in &query.paths {
+            if query.options.recursive && Path::new(path).is_dir() {
+                walk::walk(Path::new(path), &self.walk_options, &mut |found| files.push(found));
+            } else {
+                files.push(Ok(path.clone()));
+            }
+        }
+        files
+    }
+}
+
+/// Writes a message of the server to the client.
+async fn write_response(writer: &mut (impl AsyncWrite + Unpin), response: &Response<'_>) -> io::Result<()> {
+    writer.write_all(&json::encode(response)?).await?;
+    writer.flush().await
+}
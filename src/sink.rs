@@ -0,0 +1,568 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::str;
+use std::sync::Arc;
+
+use grep_matcher::{Captures, Matcher};
+
+use crate::color::Style;
+use crate::json;
+use crate::matcher::{PatternCaptures, PatternMatcher};
+use crate::output::FileOutput;
+use crate::progress;
+use crate::search::{self, FileSummary, SearchOptions};
+
+/// Receives what the search of a file finds, one file at a time.
+///
+/// The standard printer, the --json printer and the counters of -c
+/// are sinks, and library users can give their own with
+/// [`SinkFactory`] to handle the lines instead of printing them.
+pub trait OutputSink {
+    /// Called before the search of the file starts.
+    fn on_begin(&mut self, file_path: &str) -> io::Result<()>;
+
+    /// Called with each matched line, or with each line that doesn't
+    /// match when the match is inverted.
+    fn on_match(&mut self, line: &SinkLine<'_>) -> io::Result<()>;
+
+    /// Called with each context line around the matched lines.
+    fn on_context(&mut self, line: &SinkLine<'_>) -> io::Result<()>;
+
+    /// Called between two groups of lines that aren't next to each other.
+    fn on_context_break(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called instead of on_match at the first matched line of the
+    /// binary data of the file, after which the search of the file
+    /// stops. Only called when the lines of the binary data are
+    /// suppressed, see [`search::BinaryMode`].
+    fn on_binary(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once the file is searched. The sink can add
+    /// what it counts to the summary of the file.
+    fn on_end(&mut self, summary: &mut FileSummary) -> io::Result<()>;
+}
+
+/// A line found in a file, given to an [`OutputSink`].
+#[derive(Debug, Clone, Copy)]
+pub struct SinkLine<'a> {
+    /// The bytes of the line, which end with its terminator
+    /// unless the line ends the file.
+    pub bytes: &'a [u8],
+    /// The 1-based line number, if the lines are counted.
+    pub line_number: Option<u64>,
+    /// The offset of the start of the line in the decompressed data.
+    pub byte_offset: u64,
+}
+
+/// Makes the sink of each searched file, for the programs that
+/// handle the lines themselves instead of printing them.
+#[derive(Clone)]
+pub struct SinkFactory(Arc<dyn Fn() -> Box<dyn OutputSink + Send> + Send + Sync>);
+
+impl SinkFactory {
+    pub fn new(make: impl Fn() -> Box<dyn OutputSink + Send> + Send + Sync + 'static) -> SinkFactory {
+        SinkFactory(Arc::new(make))
+    }
+
+    fn make(&self) -> Box<dyn OutputSink + Send> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for SinkFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SinkFactory")
+    }
+}
+
+/// Returns the sink of a file: the one of the library user if any,
+/// or else the one that prints what the options ask for.
+pub(crate) fn file_sink<'a>(
+    matcher: &'a PatternMatcher,
+    options: &'a SearchOptions,
+    output: FileOutput,
+) -> Box<dyn OutputSink + 'a> {
+    if let Some(factory) = &options.sink {
+        return factory.make();
+    }
+    if !options.prints_lines() {
+        Box::new(Counter::new(matcher, options))
+    } else if options.json {
+        Box::new(JsonPrinter::new(matcher))
+    } else {
+        Box::new(StandardPrinter::new(matcher, options, output))
+    }
+}
+
+/// Where a printed line is in the decompressed data.
+#[derive(Debug, Clone, Copy, Default)]
+struct Position {
+    /// The 1-based line number, if the searcher counts lines.
+    line_number: Option<u64>,
+    /// The 1-based column of the first match in the line.
+    column: Option<u64>,
+    /// The offset of the start of the line.
+    byte_offset: u64,
+}
+
+/// Prints the lines like grep, prefixed with the fields asked for
+/// on the command line.
+pub(crate) struct StandardPrinter<'a> {
+    matcher: &'a PatternMatcher,
+    options: &'a SearchOptions,
+    file_path: String,
+    /// Where the lines are printed.
+    output: FileOutput,
+}
+
+impl<'a> StandardPrinter<'a> {
+    pub(crate) fn new(matcher: &'a PatternMatcher, options: &'a SearchOptions, output: FileOutput) -> StandardPrinter<'a> {
+        StandardPrinter {
+            matcher,
+            options,
+            file_path: String::new(),
+            output,
+        }
+    }
+
+    /// Returns the prefix that is printed before a line,
+    /// made of the fields asked for on the command line.
+    /// Each field is followed by the separator.
+    fn prefix(&self, position: Position, separator: char) -> String {
+        let mut prefix = String::new();
+        if self.options.with_filename && !self.options.heading {
+            prefix.push_str(&self.options.colors.path.paint(search::display_path(&self.file_path)).to_string());
+            prefix.push(separator);
+        }
+        if let Some(line_number) = position.line_number.filter(|_| self.options.line_number) {
+            prefix.push_str(&self.options.colors.line.paint(&line_number.to_string()).to_string());
+            prefix.push(separator);
+        }
+        if let Some(column) = position.column.filter(|_| self.options.column) {
+            prefix.push_str(&self.options.colors.column.paint(&column.to_string()).to_string());
+            prefix.push(separator);
+        }
+        if self.options.byte_offset {
+            prefix.push_str(&self.options.colors.offset.paint(&position.byte_offset.to_string()).to_string());
+            prefix.push(separator);
+        }
+        prefix
+    }
+
+    /// Returns the 1-based column of the first match in the line.
+    /// Columns are counted in bytes, like ripgrep does.
+    fn column(&self, line: &[u8]) -> Option<u64> {
+        if !self.options.column || self.options.invert_match {
+            return None;
+        }
+        match self.matcher.find(line) {
+            Ok(Some(m)) => Some(m.start() as u64 + 1),
+            _ => None,
+        }
+    }
+
+    /// Prints every match of the line on its own line.
+    /// The column and byte offset are the ones of each match.
+    fn print_only_matching(&mut self, found: &SinkLine<'_>) -> io::Result<()> {
+        if self.options.invert_match {
+            // Inverted lines don't contain any match to print.
+            return Ok(());
+        }
+
+        let line = found.bytes;
+        let mut printed = Vec::new();
+        let mut caps = self.matcher.new_captures().map_err(io::Error::other)?;
+        let _ = self.matcher.captures_iter(line, &mut caps, |caps| {
+            let m = match caps.get(0) {
+                Some(m) if !m.is_empty() => m,
+                _ => return true, // Nothing to print, continue with the next match
+            };
+            let position = Position {
+                line_number: found.line_number,
+                column: Some(m.start() as u64 + 1),
+                byte_offset: found.byte_offset + m.start() as u64,
+            };
+            printed.extend_from_slice(self.prefix(position, ':').as_bytes());
+            match &self.options.replace {
+                Some(replace) => {
+                    let expanded = expand(self.matcher, caps, line, replace);
+                    self.options.colors.matched.paint_bytes(&expanded, &mut printed);
+                }
+                None => self.options.colors.matched.paint_bytes(&line[m], &mut printed),
+            }
+            printed.push(self.options.line_terminator());
+            true
+        });
+        self.output.lines(&printed)
+    }
+
+    /// Prints the line once for every match, each time with the
+    /// column of the match, which is what the editors expect.
+    /// Inverted lines have no match, so they are printed once
+    /// with the first column.
+    fn print_vimgrep(&mut self, found: &SinkLine<'_>) -> io::Result<()> {
+        let mut highlighted = Vec::new();
+        highlight(self.matcher, &self.options.colors.matched, found.bytes, self.options.replace.as_deref(), &mut highlighted);
+        let mut columns = Vec::new();
+        if !self.options.invert_match {
+            let _ = self.matcher.find_iter(found.bytes, |m| {
+                columns.push(m.start() as u64 + 1);
+                true
+            });
+        }
+        if columns.is_empty() {
+            columns.push(1);
+        }
+
+        let terminator = self.options.line_terminator();
+        let mut printed = Vec::new();
+        for column in columns {
+            let position = Position {
+                line_number: found.line_number,
+                column: Some(column),
+                byte_offset: found.byte_offset,
+            };
+            printed.extend_from_slice(self.prefix(position, ':').as_bytes());
+            printed.extend_from_slice(&highlighted);
+            if !highlighted.ends_with(&[terminator]) {
+                printed.push(terminator);
+            }
+        }
+        self.output.lines(&printed)
+    }
+
+    /// Prints the lines of a match that spans several of them with
+    /// -U, each one with its own prefix. The matches are colored on
+    /// each of their lines, so the prefixes aren't, and a replacement
+    /// is printed on the line where its match starts.
+    fn print_lines(&mut self, found: &SinkLine<'_>) -> io::Result<()> {
+        let block = found.bytes;
+        let terminator = self.options.line_terminator();
+        let mut matches = Vec::new();
+        if !self.options.invert_match {
+            if let Ok(mut caps) = self.matcher.new_captures() {
+                let _ = self.matcher.captures_iter(block, &mut caps, |caps| {
+                    if let Some(m) = caps.get(0) {
+                        let replaced = self.options.replace.as_deref().map(|replace| expand(self.matcher, caps, block, replace));
+                        matches.push((m.start()..m.end(), replaced));
+                    }
+                    true
+                });
+            }
+        }
+
+        let style = &self.options.colors.matched;
+        let mut printed = Vec::new();
+        let mut start = 0;
+        for (index, line) in block.split_inclusive(|&byte| byte == terminator).enumerate() {
+            let end = start + line.len();
+            // The terminator is printed after the colors
+            let text_end = if line.ends_with(&[terminator]) { end - 1 } else { end };
+            let on_line: Vec<_> = matches
+                .iter()
+                .filter(|(range, replaced)| range.start < end && (range.end > start || replaced.is_some() && range.start >= start))
+                .collect();
+            let position = Position {
+                line_number: found.line_number.map(|line_number| line_number + index as u64),
+                column: on_line.first().filter(|_| self.options.column).map(|(range, _)| (range.start.max(start) - start) as u64 + 1),
+                byte_offset: found.byte_offset + start as u64,
+            };
+            printed.extend_from_slice(self.prefix(position, ':').as_bytes());
+
+            let mut last = start;
+            for (range, replaced) in on_line {
+                let from = range.start.max(last);
+                let to = range.end.min(text_end).max(from);
+                printed.extend_from_slice(&block[last..from]);
+                match replaced {
+                    Some(replaced) if range.start >= start => style.paint_bytes(replaced, &mut printed),
+                    Some(_) => {}
+                    None if from < to => style.paint_bytes(&block[from..to], &mut printed),
+                    None => {}
+                }
+                last = to;
+            }
+            printed.extend_from_slice(&block[last..end]);
+            start = end;
+        }
+        self.output.lines(&printed)
+    }
+}
+
+/// The lines are printed as bytes, without checking that they are
+/// valid UTF-8 or copying them to strings.
+impl OutputSink for StandardPrinter<'_> {
+    fn on_begin(&mut self, file_path: &str) -> io::Result<()> {
+        self.file_path = file_path.to_string();
+        Ok(())
+    }
+
+    fn on_match(&mut self, found: &SinkLine<'_>) -> io::Result<()> {
+        if self.options.only_matching {
+            return self.print_only_matching(found);
+        }
+        if self.options.vimgrep {
+            return self.print_vimgrep(found);
+        }
+        let terminator = self.options.line_terminator();
+        if found.bytes.split_last().is_some_and(|(_, lines)| lines.contains(&terminator)) {
+            return self.print_lines(found);
+        }
+
+        let position = Position {
+            line_number: found.line_number,
+            column: self.column(found.bytes),
+            byte_offset: found.byte_offset,
+        };
+        let mut printed = self.prefix(position, ':').into_bytes();
+
+        // Color the matched strings.
+        // Inverted lines don't contain any match to color.
+        if self.options.invert_match {
+            printed.extend_from_slice(found.bytes);
+        } else {
+            highlight(self.matcher, &self.options.colors.matched, found.bytes, self.options.replace.as_deref(), &mut printed);
+        }
+
+        // Print the line, which already has its line terminator at the end.
+        self.output.lines(&printed)
+    }
+
+    fn on_context(&mut self, context: &SinkLine<'_>) -> io::Result<()> {
+        // Context lines are printed as they are, with a `-`
+        // after the prefix fields like grep does.
+        let position = Position {
+            line_number: context.line_number,
+            column: None,
+            byte_offset: context.byte_offset,
+        };
+        let mut printed = self.prefix(position, '-').into_bytes();
+        printed.extend_from_slice(context.bytes);
+        self.output.lines(&printed)
+    }
+
+    fn on_context_break(&mut self) -> io::Result<()> {
+        // Separate the groups of lines that aren't next to each other.
+        let separator = self.options.colors.separator.paint("--").to_string();
+        self.output.lines(separator.as_bytes())
+    }
+
+    fn on_binary(&mut self) -> io::Result<()> {
+        // Like grep, instead of printing the binary lines
+        let notice = format!("binary file {} matches", search::display_path(&self.file_path));
+        self.output.lines(notice.as_bytes())
+    }
+
+    fn on_end(&mut self, _summary: &mut FileSummary) -> io::Result<()> {
+        // With --heading, the lines of the file are only printed now
+        let heading = self.options.colors.path.paint(search::display_path(&self.file_path)).to_string();
+        self.output.finish(&heading)
+    }
+}
+
+/// Where a [`JsonPrinter`] writes its messages, each one at once.
+pub(crate) type JsonOutput<'a> = Box<dyn FnMut(&[u8]) -> io::Result<()> + Send + 'a>;
+
+/// Prints the lines as --json messages. The JSON messages
+/// already carry the line numbers, so the groups of context
+/// lines aren't separated.
+pub(crate) struct JsonPrinter<'a> {
+    matcher: Cow<'a, PatternMatcher>,
+    output: JsonOutput<'a>,
+    file_path: String,
+    /// True once the begin message is printed.
+    begun: bool,
+    /// The number of matches in the matched lines.
+    matches: u64,
+    /// The number of bytes printed.
+    printed: u64,
+}
+
+impl<'a> JsonPrinter<'a> {
+    fn new(matcher: &'a PatternMatcher) -> JsonPrinter<'a> {
+        JsonPrinter::with_output(Cow::Borrowed(matcher), Box::new(progress::print))
+    }
+
+    /// Returns a printer that gives its messages to the output
+    /// instead of printing them, like the server does.
+    pub(crate) fn with_output(matcher: Cow<'a, PatternMatcher>, output: JsonOutput<'a>) -> JsonPrinter<'a> {
+        JsonPrinter {
+            matcher,
+            output,
+            file_path: String::new(),
+            begun: false,
+            matches: 0,
+            printed: 0,
+        }
+    }
+
+    /// Prints a matched or context line as a --json message.
+    /// The begin message is printed before the first line, so files
+    /// without any match don't print anything, like in ripgrep.
+    /// The invalid UTF-8 of the line is replaced with U+FFFD, and
+    /// the submatches are the ones of the replaced line.
+    fn print(&mut self, found: &SinkLine<'_>, matched: bool) -> io::Result<()> {
+        let line = String::from_utf8_lossy(found.bytes);
+        let line = line.as_ref();
+        let path = search::display_path(&self.file_path);
+        if !self.begun {
+            let begin = json::Message::Begin(json::Begin {
+                path: json::Text { text: path },
+            });
+            self.printed += write_json(&mut self.output, &begin)?;
+            self.begun = true;
+        }
+
+        let submatches = json::submatches(&self.matcher, line);
+        if matched {
+            self.matches += submatches.len() as u64;
+        }
+        let lines = json::Lines {
+            path: json::Text { text: path },
+            lines: json::Text { text: line },
+            line_number: found.line_number,
+            absolute_offset: found.byte_offset,
+            submatches,
+        };
+        let message = if matched {
+            json::Message::Match(lines)
+        } else {
+            json::Message::Context(lines)
+        };
+        self.printed += write_json(&mut self.output, &message)?;
+        Ok(())
+    }
+}
+
+/// Writes a message to the output of a [`JsonPrinter`] and
+/// returns the number of written bytes.
+fn write_json(output: &mut JsonOutput<'_>, message: &json::Message<'_>) -> io::Result<u64> {
+    let line = json::encode(message)?;
+    output(&line)?;
+    Ok(line.len() as u64)
+}
+
+impl OutputSink for JsonPrinter<'_> {
+    fn on_begin(&mut self, file_path: &str) -> io::Result<()> {
+        self.file_path = file_path.to_string();
+        Ok(())
+    }
+
+    fn on_match(&mut self, found: &SinkLine<'_>) -> io::Result<()> {
+        self.print(found, true)
+    }
+
+    fn on_context(&mut self, context: &SinkLine<'_>) -> io::Result<()> {
+        self.print(context, false)
+    }
+
+    fn on_end(&mut self, summary: &mut FileSummary) -> io::Result<()> {
+        summary.matches += self.matches;
+        summary.bytes_printed += self.printed;
+        if self.begun {
+            let end = json::Message::End(json::End {
+                path: json::Text { text: summary.display_path() },
+                binary_offset: summary.binary_offset,
+                stats: json::file_stats(summary),
+            });
+            summary.bytes_printed += write_json(&mut self.output, &end)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the matches for --count-matches, without printing
+/// anything. The counts are printed once all the files are done.
+struct Counter<'a> {
+    matcher: &'a PatternMatcher,
+    options: &'a SearchOptions,
+    matches: u64,
+}
+
+impl<'a> Counter<'a> {
+    fn new(matcher: &'a PatternMatcher, options: &'a SearchOptions) -> Counter<'a> {
+        Counter {
+            matcher,
+            options,
+            matches: 0,
+        }
+    }
+}
+
+impl OutputSink for Counter<'_> {
+    fn on_begin(&mut self, _file_path: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_match(&mut self, found: &SinkLine<'_>) -> io::Result<()> {
+        if self.options.count_matches {
+            self.matches += count_matches(self.matcher, self.options, found.bytes);
+        }
+        Ok(())
+    }
+
+    fn on_context(&mut self, _context: &SinkLine<'_>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_end(&mut self, summary: &mut FileSummary) -> io::Result<()> {
+        summary.matches += self.matches;
+        Ok(())
+    }
+}
+
+/// Counts the matches of the matcher in the line.
+/// Inverted lines don't contain any match, so they count once.
+fn count_matches(matcher: &PatternMatcher, options: &SearchOptions, line: &[u8]) -> u64 {
+    if options.invert_match {
+        return 1;
+    }
+
+    let mut matches = 0;
+    let _ = matcher.find_iter(line, |_| {
+        matches += 1;
+        true
+    });
+    matches
+}
+
+/// Colors every match of the matcher in the line with the style,
+/// and adds the line to the output.
+/// With multiple patterns, this colors whichever pattern matched.
+/// With a replacement, each match is replaced before being colored.
+fn highlight(matcher: &PatternMatcher, style: &Style, line: &[u8], replace: Option<&str>, output: &mut Vec<u8>) {
+    let mut last = 0;
+    let mut caps = match matcher.new_captures() {
+        Ok(caps) => caps,
+        Err(_) => return output.extend_from_slice(line),
+    };
+    let _ = matcher.captures_iter(line, &mut caps, |caps| {
+        let m = match caps.get(0) {
+            Some(m) => m,
+            None => return true,
+        };
+        output.extend_from_slice(&line[last..m.start()]);
+        match replace {
+            Some(replace) => style.paint_bytes(&expand(matcher, caps, line, replace), output),
+            // Nothing to color, continue with the next match
+            None if m.is_empty() => return true,
+            None => style.paint_bytes(&line[m], output),
+        }
+        last = m.end();
+        true
+    });
+    output.extend_from_slice(&line[last..]);
+}
+
+/// Expands the capture groups of a match in the replacement.
+/// `$1` is the first group and `${name}` a named one, `$$` is a `$`.
+fn expand(matcher: &PatternMatcher, caps: &PatternCaptures, line: &[u8], replace: &str) -> Vec<u8> {
+    let mut expanded = Vec::new();
+    caps.interpolate(|name| matcher.capture_index(name), line, replace.as_bytes(), &mut expanded);
+    expanded
+}
@@ -0,0 +1,190 @@
+use std::io::{self, Read};
+
+use crate::headers::{self, BlockHeader, FrameHeader, BLOCK_HEADER_LEN, CHECKSUM_LEN, ZSTD_MAGIC};
+use crate::search;
+
+/// What the reader expects next in the compressed data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// The magic number that starts a frame.
+    Magic,
+    /// The size of the payload of a skippable frame.
+    SkippableSize(u32),
+    /// The descriptor of the header of a zstd frame.
+    HeaderDescriptor,
+    /// The header of a block of a zstd frame.
+    BlockHeader { checksum: bool },
+    /// Bytes to skip, then the given state.
+    Skip(u64, Next),
+    /// Data that isn't zstd, which is no longer followed.
+    Lost,
+}
+
+/// The state after some skipped bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Next {
+    Magic,
+    BlockHeader { checksum: bool },
+}
+
+/// Wraps the compressed input of a zstd file to print the skippable
+/// frames it contains to stderr, as the decoder reads them.
+///
+/// The reader follows the frames from their headers to know where
+/// each one ends, without decoding anything.
+pub struct SkippableFrames<R> {
+    inner: R,
+    file_path: String,
+    state: State,
+    /// The bytes of the field being read, when it is split between reads.
+    field: Vec<u8>,
+    /// The offset of the next byte in the compressed data.
+    offset: u64,
+    /// The offset where the current frame starts.
+    frame_offset: u64,
+}
+
+impl<R: Read> SkippableFrames<R> {
+    pub fn new(inner: R, file_path: &str) -> SkippableFrames<R> {
+        SkippableFrames {
+            inner,
+            file_path: file_path.to_string(),
+            state: State::Magic,
+            field: Vec::new(),
+            offset: 0,
+            frame_offset: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for SkippableFrames<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let mut data = &buf[..read];
+        while !data.is_empty() && self.state != State::Lost {
+            data = self.follow(data);
+        }
+        Ok(read)
+    }
+}
+
+impl<R> SkippableFrames<R> {
+    /// Follows the frames in the data and returns the data left.
+    fn follow<'a>(&mut self, data: &'a [u8]) -> &'a [u8] {
+        if let State::Skip(count, next) = self.state {
+            let skipped = count.min(data.len() as u64);
+            self.offset += skipped;
+            self.state = match count - skipped {
+                0 => self.after_skip(next),
+                left => State::Skip(left, next),
+            };
+            return &data[skipped as usize..];
+        }
+
+        let len = match self.state {
+            State::Magic | State::SkippableSize(_) => 4,
+            State::HeaderDescriptor => 1,
+            State::BlockHeader { .. } => BLOCK_HEADER_LEN,
+            State::Skip(..) | State::Lost => unreachable!(),
+        };
+        let taken = (len - self.field.len()).min(data.len());
+        self.field.extend_from_slice(&data[..taken]);
+        self.offset += taken as u64;
+        if self.field.len() == len {
+            let field = std::mem::take(&mut self.field);
+            self.state = self.parse(&field);
+        }
+        &data[taken..]
+    }
+
+    /// Returns the state after a field of the current state is read.
+    fn parse(&mut self, field: &[u8]) -> State {
+        match self.state {
+            State::Magic => {
+                self.frame_offset = self.offset - 4;
+                let magic = u32::from_le_bytes([field[0], field[1], field[2], field[3]]);
+                if magic == ZSTD_MAGIC {
+                    State::HeaderDescriptor
+                } else if headers::is_skippable(magic) {
+                    State::SkippableSize(magic)
+                } else {
+                    State::Lost
+                }
+            }
+            State::SkippableSize(magic) => {
+                let size = u32::from_le_bytes([field[0], field[1], field[2], field[3]]);
+                eprintln!(
+                    "{}: skippable frame {:#010x} at offset {}, {} bytes of payload",
+                    search::display_path(&self.file_path),
+                    magic,
+                    self.frame_offset,
+                    size
+                );
+                State::Skip(u64::from(size), Next::Magic)
+            }
+            State::HeaderDescriptor => {
+                let header = FrameHeader::new(field[0]);
+                let checksum = header.checksum();
+                State::Skip(header.fields_len() as u64, Next::BlockHeader { checksum })
+            }
+            State::BlockHeader { checksum } => {
+                let block = BlockHeader::new([field[0], field[1], field[2]]);
+                let content_len = block.content_len as u64;
+                if block.last {
+                    let checksum_len = if checksum { CHECKSUM_LEN as u64 } else { 0 };
+                    State::Skip(content_len + checksum_len, Next::Magic)
+                } else {
+                    State::Skip(content_len, Next::BlockHeader { checksum })
+                }
+            }
+            State::Skip(..) | State::Lost => unreachable!(),
+        }
+    }
+
+    fn after_skip(&self, next: Next) -> State {
+        match next {
+            Next::Magic => State::Magic,
+            Next::BlockHeader { checksum } => State::BlockHeader { checksum },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A skippable frame with the given payload.
+    fn skippable_frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = 0x184d_2a53u32.to_le_bytes().to_vec();
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn skippable_frame_between_data_frames() {
+        let mut data = zstd::encode_all(&b"first\n"[..], 3).unwrap();
+        data.extend(skippable_frame(b"metadata"));
+        let second = data.len() as u64;
+        data.extend(zstd::encode_all(&b"second\n"[..], 3).unwrap());
+
+        // Reads a few bytes at a time, to split the fields between reads
+        let mut frames = SkippableFrames::new(&data[..], "test.zst");
+        let mut read = Vec::new();
+        let mut buf = [0; 3];
+        loop {
+            let len = frames.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            read.extend_from_slice(&buf[..len]);
+        }
+        assert_eq!(read, data);
+        assert_eq!(frames.state, State::Magic);
+        assert_eq!(frames.frame_offset, second);
+        assert_eq!(frames.offset, data.len() as u64);
+
+        let decoded = zstd::decode_all(SkippableFrames::new(&data[..], "test.zst")).unwrap();
+        assert_eq!(decoded, b"first\nsecond\n");
+    }
+}
@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use futures::stream::{self, Stream};
+use tokio::sync::mpsc;
+
+use crate::config::SearchConfig;
+use crate::matches::{self, Match};
+
+/// The number of events sent ahead of the ones the stream returned.
+/// The search waits once the stream is this far behind.
+const EVENT_BUFFER: usize = 64;
+
+/// A matched line of a file searched by [`search_file_stream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchEvent {
+    /// The searched file.
+    pub file: PathBuf,
+    /// The 1-based number of the line.
+    pub line_number: u64,
+    /// The offset of the line in the decompressed data.
+    pub byte_offset: u64,
+    /// The line, without its terminator.
+    pub line: Vec<u8>,
+    /// Where the patterns match, as offsets in the line.
+    /// The offsets in the decompressed data are the ones
+    /// of the line plus these.
+    pub submatches: Vec<Range<usize>>,
+}
+
+impl MatchEvent {
+    fn new(file: &Path, found: Match) -> MatchEvent {
+        MatchEvent {
+            file: file.to_path_buf(),
+            line_number: found.line_number,
+            byte_offset: found.byte_offset,
+            line: found.line,
+            submatches: found.matches,
+        }
+    }
+}
+
+/// Searches a file like [`search`](crate::search) and returns its
+/// matched lines as a stream, for the programs running on tokio.
+/// The format of the file is told by its first bytes, or else by its
+/// extension.
+///
+/// The file is decoded and searched on a blocking thread, which stays
+/// a few lines ahead of the stream and stops once the stream is dropped.
+/// An error ends the stream, and is its last item.
+pub fn search_file_stream(path: impl AsRef<Path>, config: &SearchConfig) -> impl Stream<Item = Result<MatchEvent>> {
+    let path = path.as_ref().to_path_buf();
+    let config = config.clone();
+    let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = send_matches(&path, &config, &sender) {
+            // The stream may be dropped already, then nobody wants the error
+            let _ = sender.blocking_send(Err(e));
+        }
+    });
+
+    stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((event, receiver))
+    })
+}
+
+/// Sends the matched lines of the file, until the stream is dropped.
+fn send_matches(path: &Path, config: &SearchConfig, sender: &mpsc::Sender<Result<MatchEvent>>) -> Result<()> {
+    let file_path = path.to_string_lossy();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Err(anyhow::anyhow!("Error opening file {}: {}", file_path, e)),
+    };
+
+    let mut matches = matches::search_input(config, file, Some(&file_path))?;
+    for found in matches.by_ref() {
+        if sender.blocking_send(Ok(MatchEvent::new(path, found))).is_err() {
+            return Ok(());
+        }
+    }
+    match matches.error() {
+        Some(e) => Err(anyhow::anyhow!("Error searching file {}: {}", file_path, e)),
+        None => Ok(()),
+    }
+}
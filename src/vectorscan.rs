@@ -0,0 +1,111 @@
+use std::io;
+use std::sync::Arc;
+
+use grep_matcher::Match;
+use vectorscan_rs::{BlockDatabase, BlockScanner, Flag, Pattern, Scan};
+
+use crate::matcher::{CaseMode, MatcherOptions};
+
+/// The patterns compiled by vectorscan, which matches thousands of
+/// them at once much faster than the regex crate, like the indicators
+/// of compromise looked for in the logs.
+///
+/// The patterns are PCRE patterns without the backreferences and the
+/// look-around, matched on the bytes rather than on the UTF-8
+/// characters, and can't match an empty line. Each line is scanned
+/// on its own.
+#[derive(Debug, Clone)]
+pub struct VectorscanMatcher {
+    /// Tells the lines that match, stopping at the first match.
+    lines: Arc<BlockDatabase>,
+    /// Finds where the matches start, for what is printed of them.
+    matches: Arc<BlockDatabase>,
+}
+
+impl VectorscanMatcher {
+    pub fn new(patterns: &[String], options: &MatcherOptions) -> Result<VectorscanMatcher, vectorscan_rs::Error> {
+        let lines = compile(patterns, options, Flag::SINGLEMATCH)?;
+        let matches = compile(patterns, options, Flag::SOM_LEFTMOST)?;
+        Ok(VectorscanMatcher {
+            lines: Arc::new(lines),
+            matches: Arc::new(matches),
+        })
+    }
+
+    /// Returns a scanner of the patterns. Each scanner has the scratch
+    /// space of its scans, so the search of a file keeps the same one.
+    pub fn scanner(&self) -> io::Result<Scanner<'_>> {
+        Ok(Scanner {
+            lines: self.lines.create_scanner().map_err(scan_error)?,
+            matches: self.matches.create_scanner().map_err(scan_error)?,
+        })
+    }
+}
+
+/// Compiles the patterns into a database, each pattern with its own
+/// flags and the ones given.
+fn compile(patterns: &[String], options: &MatcherOptions, flags: Flag) -> Result<BlockDatabase, vectorscan_rs::Error> {
+    let patterns = patterns.iter().enumerate().map(|(id, pattern)| {
+        let mut expression = if options.fixed_strings {
+            regex_syntax::escape(pattern)
+        } else {
+            pattern.clone()
+        };
+        if options.whole_line {
+            expression = format!("^(?:{})$", expression);
+        } else if options.word {
+            expression = format!("\\b(?:{})\\b", expression);
+        }
+
+        let caseless = match options.case {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !pattern.chars().any(char::is_uppercase),
+        };
+        let flags = if caseless { flags | Flag::CASELESS } else { flags };
+        Pattern::new(expression.into_bytes(), flags, Some(id as u32))
+    });
+    BlockDatabase::new(patterns.collect())
+}
+
+/// Scans the lines for the patterns of a [`VectorscanMatcher`].
+pub struct Scanner<'a> {
+    lines: BlockScanner<'a>,
+    matches: BlockScanner<'a>,
+}
+
+impl Scanner<'_> {
+    /// Returns the end of the first match that ends after at,
+    /// which is the shortest match.
+    pub fn shortest_match_at(&mut self, haystack: &[u8], at: usize) -> io::Result<Option<usize>> {
+        let mut end = None;
+        self.lines
+            .scan(&haystack[at..], |_, _, to, _| {
+                end = Some(at + to as usize);
+                Scan::Terminate
+            })
+            .map_err(scan_error)?;
+        Ok(end)
+    }
+
+    /// Returns the leftmost match that starts at at or after it, the
+    /// longest one of those that start there. The whole haystack is
+    /// scanned, so that `^` and `\b` see the bytes before at.
+    pub fn find_at(&mut self, haystack: &[u8], at: usize) -> io::Result<Option<Match>> {
+        let mut found: Option<Match> = None;
+        self.matches
+            .scan(haystack, |_, from, to, _| {
+                let (start, end) = (from as usize, to as usize);
+                if start >= at && found.is_none_or(|found| (start, found.end()) < (found.start(), end)) {
+                    found = Some(Match::new(start, end));
+                }
+                Scan::Continue
+            })
+            .map_err(scan_error)?;
+        Ok(found)
+    }
+}
+
+fn scan_error(e: vectorscan_rs::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
@@ -0,0 +1,77 @@
+use std::io::{self, Read};
+
+use anyhow::Result;
+
+use crate::format::{self, Format, FrameVisitor};
+use crate::search::{self, SearchOptions};
+
+/// Decodes a whole file to check that it isn't corrupted, for
+/// `rzstd test`, and returns the size of its decoded data. The
+/// checksums of the zstd frames are checked as they are decoded.
+///
+/// The error of a corrupted file tells how much of its data could be
+/// decoded, and for the zstd files, which frame is corrupted and its
+/// offset in the file.
+pub fn verify_file(file_path: &str, options: &SearchOptions) -> Result<u64> {
+    let (format, input, _) = search::open_input(file_path, options)?;
+
+    if format == Format::Zstd {
+        let mut frames = FrameCounter::default();
+        return match format::decode_zstd_frames(input, options.zstd(), &mut frames) {
+            Ok(()) => Ok(frames.decoded),
+            Err(e) => {
+                let (frame, offset) = (frames.frames, frames.frame_offset);
+                let e = anyhow::anyhow!(
+                    "frame {} at offset {} is corrupted, after {} bytes of decoded data: {}",
+                    frame,
+                    offset,
+                    frames.decoded,
+                    e
+                );
+                Err(anyhow::anyhow!("Error testing file {}: {}", file_path, e))
+            }
+        };
+    }
+
+    let mut decoded = 0;
+    let result = format.decoder(input, options.zstd()).and_then(|mut decoder| {
+        let mut buf = vec![0; 64 << 10];
+        loop {
+            match decoder.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(read) => decoded += read as u64,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    });
+    match result {
+        Ok(()) => Ok(decoded),
+        Err(e) => {
+            let e = anyhow::anyhow!("corrupted after {} bytes of decoded data: {}", decoded, e);
+            Err(anyhow::anyhow!("Error testing file {}: {}", file_path, e))
+        }
+    }
+}
+
+/// Follows the frames of a zstd file, to tell where it is corrupted.
+#[derive(Debug, Default)]
+struct FrameCounter {
+    /// The number of frames started, so the 1-based number of the last one.
+    frames: u64,
+    /// The offset of the last frame in the file.
+    frame_offset: u64,
+    /// The size of the decoded data so far.
+    decoded: u64,
+}
+
+impl FrameVisitor for FrameCounter {
+    fn frame(&mut self, compressed_offset: u64) {
+        self.frames += 1;
+        self.frame_offset = compressed_offset;
+    }
+
+    fn data(&mut self, data: &[u8]) {
+        self.decoded += data.len() as u64;
+    }
+}
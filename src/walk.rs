@@ -0,0 +1,187 @@
+use std::path::Path;
+
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+
+/// Options that change which files are found when
+/// walking a directory.
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// Only files with one of these extensions are searched.
+    pub extensions: Vec<String>,
+    /// The globs given with -g.
+    pub globs: GlobFilter,
+    /// Skip the files ignored by .gitignore, .ignore and .rzstdignore files.
+    pub ignore_files: bool,
+    /// Follow the symlinks to files and directories.
+    pub follow_links: bool,
+    /// Search the hidden files and directories, whose name starts with a dot.
+    pub hidden: bool,
+}
+
+impl WalkOptions {
+    /// Returns true if the file has one of the searched extensions.
+    fn has_extension(&self, path: &Path) -> bool {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => self.extensions.iter().any(|e| e == extension),
+            None => false,
+        }
+    }
+
+    /// Returns true if a file found in a directory is searched.
+    /// The globs take precedence over the extensions.
+    fn is_searched(&self, relative: &Path) -> bool {
+        match self.globs.selects(relative) {
+            Some(selected) => selected,
+            None => self.has_extension(relative),
+        }
+    }
+}
+
+/// Include and exclude globs. Globs starting with `!` exclude.
+///
+/// Like in .gitignore files, a glob without a `/` is matched
+/// against the file name, and a glob with a `/` against the path
+/// relative to the searched directory.
+#[derive(Debug, Clone, Default)]
+pub struct GlobFilter {
+    includes: GlobMatcher,
+    excludes: GlobMatcher,
+}
+
+impl GlobFilter {
+    /// Builds the filter from the globs given on the command line.
+    pub fn new(globs: &[String]) -> Result<GlobFilter> {
+        let mut includes = Vec::new();
+        let mut excludes = Vec::new();
+        for glob in globs {
+            match glob.strip_prefix('!') {
+                Some(exclude) => excludes.push(exclude),
+                None => includes.push(glob.as_str()),
+            }
+        }
+
+        Ok(GlobFilter {
+            includes: GlobMatcher::new(&includes)?,
+            excludes: GlobMatcher::new(&excludes)?,
+        })
+    }
+
+    /// Returns Some(true) if the file is selected by an include glob,
+    /// Some(false) if it is excluded, and None if there are no globs
+    /// that decide for it.
+    pub fn selects(&self, path: &Path) -> Option<bool> {
+        if self.excludes.is_match(path) {
+            return Some(false);
+        }
+        if self.includes.is_empty() {
+            return None;
+        }
+        Some(self.includes.is_match(path))
+    }
+
+    /// Returns true if the path is excluded by an exclude glob.
+    /// Nothing is searched in the excluded directories.
+    pub fn excludes(&self, path: &Path) -> bool {
+        self.excludes.is_match(path)
+    }
+}
+
+/// Matches the globs without a `/` against file names
+/// and the other globs against whole paths.
+#[derive(Debug, Clone, Default)]
+struct GlobMatcher {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl GlobMatcher {
+    fn new(globs: &[&str]) -> Result<GlobMatcher> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for glob in globs {
+            let parsed = match Glob::new(glob) {
+                Ok(parsed) => parsed,
+                Err(e) => return Err(anyhow::anyhow!("Error parsing glob {}: {}", glob, e)),
+            };
+            if glob.contains('/') {
+                paths.add(parsed);
+            } else {
+                names.add(parsed);
+            }
+        }
+
+        Ok(GlobMatcher {
+            names: names.build()?,
+            paths: paths.build()?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.paths.is_empty()
+    }
+
+    fn is_match(&self, path: &Path) -> bool {
+        let name_matches = match path.file_name() {
+            Some(name) => self.names.is_match(name),
+            None => false,
+        };
+        name_matches || self.paths.is_match(path)
+    }
+}
+
+/// The name of the ignore files specific to rzstd.
+/// They use the same syntax as .gitignore files.
+const IGNORE_FILENAME: &str = ".rzstdignore";
+
+/// Walks a directory recursively and calls found with each
+/// file to search, or with the error if a directory can't be read.
+/// Entries are visited in the order of their names so that the
+/// output doesn't depend on the file system.
+/// Symlinks are only followed when asked to, in which case
+/// symlinks that loop back to a parent directory are reported
+/// as errors instead of being walked forever.
+///
+/// Unless disabled, the files ignored by .gitignore, .ignore
+/// and .rzstdignore files are skipped, and so are hidden files.
+pub fn walk(root: &Path, options: &WalkOptions, found: &mut impl FnMut(Result<String>)) {
+    let mut builder = WalkBuilder::new(root);
+    builder
+        .standard_filters(options.ignore_files)
+        .hidden(!options.hidden)
+        .follow_links(options.follow_links)
+        .sort_by_file_name(|a, b| a.cmp(b));
+    if options.ignore_files {
+        builder.add_custom_ignore_filename(IGNORE_FILENAME);
+    }
+
+    // Skip the excluded directories without walking them.
+    let globs = options.globs.clone();
+    let filter_root = root.to_path_buf();
+    builder.filter_entry(move |entry| {
+        let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+        !is_dir || entry.depth() == 0 || !globs.excludes(relative(&filter_root, entry.path()))
+    });
+
+    for entry in builder.build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                found(Err(anyhow::anyhow!("Error walking directory {}: {}", root.display(), e)));
+                continue;
+            }
+        };
+
+        // The globs match the paths relative to the searched directory
+        let is_file = entry.file_type().is_some_and(|file_type| file_type.is_file());
+        if is_file && options.is_searched(relative(root, entry.path())) {
+            found(Ok(entry.path().to_string_lossy().into_owned()));
+        }
+    }
+}
+
+/// Returns the path relative to the searched directory.
+fn relative<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(path)
+}
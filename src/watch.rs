@@ -0,0 +1,112 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+use crate::cli;
+
+/// How long the files must stay unchanged before they are searched
+/// again, so that a file still being written is searched once whole.
+const QUIET_PERIOD: Duration = Duration::from_millis(500);
+
+/// Watches the files and directories given with --watch, and tells
+/// which files changed or were added to them.
+///
+/// The directories of the files are watched rather than the files
+/// themselves, so that a file replaced by another one, like a log
+/// rotated in, is still seen.
+pub struct PathWatcher {
+    /// Sends the events for as long as it is kept.
+    _watcher: RecommendedWatcher,
+    events: UnboundedReceiver<notify::Result<Event>>,
+}
+
+impl PathWatcher {
+    /// Starts watching the paths. The directories are only watched
+    /// when searching recursively, as they aren't searched otherwise.
+    pub fn new(paths: &[String], recursive: bool) -> Result<PathWatcher> {
+        let (sender, events) = mpsc::unbounded_channel();
+        let mut watcher = match notify::recommended_watcher(move |event| {
+            // The receiver is only dropped with the watcher
+            let _ = sender.send(event);
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => return Err(anyhow::anyhow!("Error watching files: {}", e)),
+        };
+
+        // A directory with several of the files is watched once
+        let mut watched = BTreeSet::new();
+        for path in paths.iter().filter(|path| *path != cli::STDIN_PATH) {
+            let canonical = match fs::canonicalize(path) {
+                Ok(canonical) => canonical,
+                Err(e) => return Err(anyhow::anyhow!("Error watching {}: {}", path, e)),
+            };
+            if canonical.is_dir() {
+                if recursive {
+                    watched.insert((canonical, true));
+                }
+            } else if let Some(parent) = canonical.parent() {
+                watched.insert((parent.to_path_buf(), false));
+            }
+        }
+        for (dir, recursive) in watched {
+            let mode = if recursive {
+                RecursiveMode::Recursive
+            } else {
+                RecursiveMode::NonRecursive
+            };
+            if let Err(e) = watcher.watch(&dir, mode) {
+                return Err(anyhow::anyhow!("Error watching {}: {}", dir.display(), e));
+            }
+        }
+
+        Ok(PathWatcher {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Waits for files to change, and returns their canonical paths
+    /// once nothing changed for a while. The files removed meanwhile
+    /// aren't returned.
+    pub async fn changed(&mut self) -> Result<HashSet<PathBuf>> {
+        let mut changed = HashSet::new();
+        loop {
+            let event = if changed.is_empty() {
+                self.events.recv().await
+            } else {
+                match tokio::time::timeout(QUIET_PERIOD, self.events.recv()).await {
+                    Ok(event) => event,
+                    Err(_) => return Ok(changed.iter().filter_map(|path| fs::canonicalize(path).ok()).collect()),
+                }
+            };
+            let event = match event {
+                Some(Ok(event)) => event,
+                Some(Err(e)) => return Err(anyhow::anyhow!("Error watching files: {}", e)),
+                None => return Err(anyhow::anyhow!("Error watching files: the watcher stopped")),
+            };
+            if is_change(&event.kind) {
+                changed.extend(event.paths);
+            }
+        }
+    }
+}
+
+/// Returns true for the events of the files whose data changed,
+/// or that were created or moved there.
+fn is_change(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any)
+    )
+}
+
+/// Returns true if the file is one of the changed ones.
+pub fn is_changed(file_path: &str, changed: &HashSet<PathBuf>) -> bool {
+    fs::canonicalize(file_path).is_ok_and(|path| changed.contains(&path))
+}
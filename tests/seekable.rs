@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The magic number of the skippable frame that holds the seek table.
+const SEEK_TABLE_MAGIC: u32 = 0x184d_2a5e;
+/// The magic number that ends the seek table.
+const SEEKABLE_MAGIC: u32 = 0x8f92_eab1;
+
+/// Lines of different lengths, some of them matching "ab+c".
+fn lines() -> Vec<u8> {
+    let mut lines = Vec::new();
+    for i in 0..3000 {
+        let word = ["abc", "xyz", "abbbc", "", "ac abc"][i % 5];
+        lines.extend_from_slice(format!("{} {}{}\n", i, word, "-".repeat(i % 37)).as_bytes());
+    }
+    // The last line has no terminator
+    lines.extend_from_slice(b"last abc");
+    lines
+}
+
+/// Writes the data in the zstd seekable format, in frames of
+/// frame_len bytes that end in the middle of the lines.
+fn write_seekable(path: &Path, data: &[u8], frame_len: usize) {
+    let mut file = Vec::new();
+    let mut table = Vec::new();
+    for frame in data.chunks(frame_len) {
+        let compressed = zstd::bulk::compress(frame, 3).unwrap();
+        table.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        table.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend(compressed);
+    }
+    let frame_count = data.chunks(frame_len).count() as u32;
+    file.extend_from_slice(&SEEK_TABLE_MAGIC.to_le_bytes());
+    file.extend_from_slice(&(table.len() as u32 + 9).to_le_bytes());
+    file.extend(table);
+    file.extend_from_slice(&frame_count.to_le_bytes());
+    file.push(0);
+    file.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+    fs::write(path, file).unwrap();
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rzstd-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn rzstd(args: &[&str], path: &Path) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_rzstd")).args(args).arg(path).output().unwrap();
+    assert!(output.status.code().is_some_and(|code| code < 2), "{}", String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+#[test]
+fn chunks_print_the_lines_of_a_plain_scan() {
+    let dir = temp_dir("seekable");
+    let data = lines();
+    let plain = dir.join("plain.zst");
+    fs::write(&plain, zstd::bulk::compress(&data, 3).unwrap()).unwrap();
+    let seekable = dir.join("seekable.zst");
+    // Frames of a prime number of bytes, so that they split the lines
+    write_seekable(&seekable, &data, 997);
+
+    for flags in [&[][..], &["-c"], &["-v"], &["-o"], &["-v", "-c"]] {
+        let mut args = flags.to_vec();
+        args.push("ab+c");
+        let expected = rzstd(&args, &plain);
+        assert!(!expected.is_empty());
+        for threads in ["1", "3", "8"] {
+            let mut chunked = vec!["-j", threads];
+            chunked.extend(&args);
+            assert_eq!(rzstd(&chunked, &seekable), expected, "{:?}", chunked);
+        }
+    }
+    fs::remove_dir_all(dir).unwrap();
+}